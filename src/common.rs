@@ -1,7 +1,9 @@
 use std::convert::TryInto;
 
+pub(crate) mod cardinality;
 pub(crate) mod deque;
 pub(crate) mod frequency_sketch;
+pub(crate) mod hit_rate;
 
 // Note: `CacheRegion` cannot have more than four enum variants. This is because
 // `crate::{sync,unsync}::DeqNodes` uses a `tagptr::TagNonNull<DeqNode<T>, 2>`
@@ -14,6 +16,18 @@ pub(crate) enum CacheRegion {
     Other = 3,
 }
 
+impl CacheRegion {
+    /// Returns the public counterpart of this region, or `None` for `Other`.
+    pub(crate) fn entry_region(self) -> Option<EntryRegion> {
+        match self {
+            Self::Window => Some(EntryRegion::Window),
+            Self::MainProbation => Some(EntryRegion::MainProbation),
+            Self::MainProtected => Some(EntryRegion::MainProtected),
+            Self::Other => None,
+        }
+    }
+}
+
 impl From<usize> for CacheRegion {
     fn from(n: usize) -> Self {
         match n {
@@ -38,6 +52,25 @@ impl PartialEq<usize> for CacheRegion {
     }
 }
 
+/// The region of the cache holding an entry, returned by the
+/// [`region_of`][region-of-method] method of the `Cache`.
+///
+/// [region-of-method]: ./unsync/struct.Cache.html#method.region_of
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EntryRegion {
+    /// The admission window.
+    Window,
+    /// The probation region of the main space, where the entries are evicted
+    /// from first.
+    MainProbation,
+    /// The protected region of the main space, holding the entries promoted by
+    /// the [`promotion_threshold`][promotion-threshold].
+    ///
+    /// [promotion-threshold]: ./unsync/struct.CacheBuilder.html#method.promotion_threshold
+    MainProtected,
+}
+
 // Ensures the value fits in a range of `128u32..=u32::MAX`.
 pub(crate) fn sketch_capacity(max_capacity: u64) -> u32 {
     max_capacity.try_into().unwrap_or(u32::MAX).max(128)
@@ -12,7 +12,11 @@
 // For full authorship information, see the version control history of
 // https://github.com/rust-lang/rust/ or https://thanks.rust-lang.org
 
-use std::{marker::PhantomData, ptr::NonNull};
+use std::{
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr::{self, NonNull},
+};
 
 use super::CacheRegion;
 
@@ -46,7 +50,6 @@ impl<T> DeqNode<T> {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn next_node_ptr(this: NonNull<Self>) -> Option<NonNull<DeqNode<T>>> {
         unsafe { this.as_ref() }.next
     }
@@ -64,6 +67,7 @@ pub(crate) struct Deque<T> {
     head: Option<NonNull<DeqNode<T>>>,
     tail: Option<NonNull<DeqNode<T>>>,
     cursor: Option<DeqCursor<T>>,
+    slab: NodeSlab<T>,
     marker: PhantomData<Box<DeqNode<T>>>,
 }
 
@@ -79,9 +83,9 @@ impl<T> Drop for Deque<T> {
             }
         }
 
-        while let Some(node) = self.pop_front() {
+        while let Some(element) = self.pop_front() {
             let guard = DropGuard(self);
-            drop(node);
+            drop(element);
             std::mem::forget(guard);
         }
     }
@@ -96,6 +100,7 @@ impl<T> Deque<T> {
             head: None,
             tail: None,
             cursor: None,
+            slab: NodeSlab::new(),
             marker: PhantomData,
         }
     }
@@ -104,7 +109,6 @@ impl<T> Deque<T> {
         self.region
     }
 
-    #[cfg(test)]
     pub(crate) fn len(&self) -> usize {
         self.len
     }
@@ -143,8 +147,8 @@ impl<T> Deque<T> {
         self.head.as_ref().cloned()
     }
 
-    /// Removes and returns the node at the front of the list.
-    pub(crate) fn pop_front(&mut self) -> Option<Box<DeqNode<T>>> {
+    /// Removes the node at the front of the list, and returns its element.
+    pub(crate) fn pop_front(&mut self) -> Option<T> {
         // This method takes care not to create mutable references to whole nodes,
         // to maintain validity of aliasing pointers into `element`.
         self.head.map(|node| unsafe {
@@ -152,8 +156,7 @@ impl<T> Deque<T> {
                 self.advance_cursor();
             }
 
-            let mut node = Box::from_raw(node.as_ptr());
-            self.head = node.next;
+            self.head = (*node.as_ptr()).next;
 
             match self.head {
                 None => self.tail = None,
@@ -163,9 +166,7 @@ impl<T> Deque<T> {
 
             self.len -= 1;
 
-            node.prev = None;
-            node.next = None;
-            node
+            self.slab.take(node)
         })
     }
 
@@ -174,14 +175,15 @@ impl<T> Deque<T> {
         self.tail.as_ref().map(|node| unsafe { node.as_ref() })
     }
 
-    /// Adds the given node to the back of the list.
-    pub(crate) fn push_back(&mut self, mut node: Box<DeqNode<T>>) -> NonNull<DeqNode<T>> {
+    /// Adds the given node to the back of the list. The node is moved into a slot
+    /// of the slab of this list, and a pointer to the slot is returned.
+    pub(crate) fn push_back(&mut self, mut node: DeqNode<T>) -> NonNull<DeqNode<T>> {
         // This method takes care not to create mutable references to whole nodes,
         // to maintain validity of aliasing pointers into `element`.
         unsafe {
             node.next = None;
             node.prev = self.tail;
-            let node = NonNull::new(Box::into_raw(node)).expect("Got a null ptr");
+            let node = self.slab.alloc(node);
 
             match self.tail {
                 None => self.head = Some(node),
@@ -242,8 +244,8 @@ impl<T> Deque<T> {
     /// maintain validity of aliasing pointers.
     ///
     /// IMPORTANT: This method does not drop the node. If the node is no longer
-    /// needed, use `unlink_and_drop` instead, or drop it at the caller side.
-    /// Otherwise, the node will leak.
+    /// needed, use `unlink_and_drop` instead, or call `drop_unlinked` after this.
+    /// Otherwise, the node will leak until this list is dropped.
     pub(crate) unsafe fn unlink(&mut self, mut node: NonNull<DeqNode<T>>) {
         debug_assert!(
             self.contains(node.as_ref()),
@@ -282,13 +284,38 @@ impl<T> Deque<T> {
     /// Panics:
     pub(crate) unsafe fn unlink_and_drop(&mut self, node: NonNull<DeqNode<T>>) {
         self.unlink(node);
-        std::mem::drop(Box::from_raw(node.as_ptr()));
+        self.drop_unlinked(node);
+    }
+
+    /// Unlinks the specified node from the current list, and returns its element.
+    /// The slot of the node is returned to the slab.
+    pub(crate) unsafe fn unlink_and_take(&mut self, node: NonNull<DeqNode<T>>) -> T {
+        self.unlink(node);
+        self.slab.take(node)
+    }
+
+    /// Drops the specified node, which must have been unlinked from this list by
+    /// `unlink`, and returns its slot to the slab.
+    pub(crate) unsafe fn drop_unlinked(&mut self, node: NonNull<DeqNode<T>>) {
+        std::mem::drop(self.slab.take(node));
     }
 
     #[cfg(test)]
     pub(crate) fn reset_cursor(&mut self) {
         self.cursor = None;
     }
+
+    /// Returns an iterator visiting the elements from the front to the back.
+    ///
+    /// Unlike iterating a `&mut Deque`, this does not use (or disturb) the cursor.
+    pub(crate) fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            head: self.head,
+            tail: self.tail,
+            len: self.len,
+            marker: PhantomData,
+        }
+    }
 }
 
 impl<'a, T> Iterator for &'a mut Deque<T> {
@@ -310,6 +337,52 @@ impl<'a, T> Iterator for &'a mut Deque<T> {
     }
 }
 
+/// An iterator over the elements of a `Deque`, created by `Deque::iter`.
+pub(crate) struct Iter<'a, T> {
+    head: Option<NonNull<DeqNode<T>>>,
+    tail: Option<NonNull<DeqNode<T>>>,
+    len: usize,
+    marker: PhantomData<&'a DeqNode<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.head.map(|node| unsafe {
+            // Need an unbound lifetime to get 'a
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.head = node.next;
+            &node.element
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.tail.map(|node| unsafe {
+            // Need an unbound lifetime to get 'a
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.tail = node.prev;
+            &node.element
+        })
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
 // Private function/methods
 impl<T> Deque<T> {
     fn is_head(&self, node: &DeqNode<T>) -> bool {
@@ -353,6 +426,117 @@ impl<T> Deque<T> {
     }
 }
 
+// The number of node slots in the first chunk of a `NodeSlab`. Each following
+// chunk doubles the number of slots up to `MAX_CHUNK_LEN`.
+const MIN_CHUNK_LEN: usize = 16;
+const MAX_CHUNK_LEN: usize = 4096;
+
+/// An allocator of `DeqNode`s for a `Deque`. Nodes are stored in chunks, so that
+/// pushing N nodes does only O(log N) allocations (up to `MAX_CHUNK_LEN` nodes
+/// per chunk) instead of N. The slot of a node never moves until the slab is
+/// dropped, so pointers to the nodes are stable.
+///
+/// Freed slots are kept in an intrusive singly-linked free list, which reuses
+/// the `next` field of the node, and are handed out again by later allocations.
+///
+/// Chunks are never freed while the slab lives, even when all of their slots
+/// are free. So a deque keeps the memory for the largest number of nodes it has
+/// ever held, and shrinking the cache (e.g. by `set_max_capacity`, `trim_to` or
+/// `evict_n`) does not give it back. Replacing the deque with a new one, as
+/// `Cache::compact` does, is the way to release it.
+struct NodeSlab<T> {
+    // The chunks are kept as raw pointers rather than `Box`es, so that handing
+    // out a slot will not create a reference to a whole chunk, which would
+    // invalidate the pointers to the other slots in the chunk.
+    chunks: Vec<NonNull<[MaybeUninit<DeqNode<T>>]>>,
+    // The number of slots ever handed out from the last chunk.
+    last_chunk_used: usize,
+    free: Option<NonNull<DeqNode<T>>>,
+}
+
+impl<T> Drop for NodeSlab<T> {
+    fn drop(&mut self) {
+        // The elements of the live nodes must have been dropped by the `Deque`.
+        // Here, only deallocate the memory of the chunks.
+        for chunk in self.chunks.drain(..) {
+            std::mem::drop(unsafe { Box::from_raw(chunk.as_ptr()) });
+        }
+    }
+}
+
+impl<T> NodeSlab<T> {
+    fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            last_chunk_used: 0,
+            free: None,
+        }
+    }
+
+    /// Moves the given node into a free slot, and returns a pointer to the slot.
+    fn alloc(&mut self, node: DeqNode<T>) -> NonNull<DeqNode<T>> {
+        let slot = match self.free {
+            Some(slot) => {
+                // Not creating references to the slot as it is not initialized.
+                self.free = unsafe { ptr::addr_of!((*slot.as_ptr()).next).read() };
+                slot
+            }
+            None => self.alloc_unused_slot(),
+        };
+        unsafe { slot.as_ptr().write(node) };
+        slot
+    }
+
+    fn alloc_unused_slot(&mut self) -> NonNull<DeqNode<T>> {
+        let chunk = match self.chunks.last() {
+            Some(chunk) if self.last_chunk_used < chunk.len() => *chunk,
+            last => {
+                let len = last.map_or(MIN_CHUNK_LEN, |c| (c.len() * 2).min(MAX_CHUNK_LEN));
+                let chunk = std::iter::repeat_with(MaybeUninit::uninit)
+                    .take(len)
+                    .collect::<Box<[_]>>();
+                let chunk = NonNull::new(Box::into_raw(chunk)).expect("Got a null ptr");
+                self.chunks.push(chunk);
+                self.last_chunk_used = 0;
+                chunk
+            }
+        };
+
+        let slot = unsafe { (chunk.as_ptr() as *mut DeqNode<T>).add(self.last_chunk_used) };
+        self.last_chunk_used += 1;
+        NonNull::new(slot).expect("Got a null ptr")
+    }
+
+    /// Moves the element out of the given node, and returns the slot of the node
+    /// to the free list.
+    ///
+    /// The node must have been allocated by this slab and must not be freed yet.
+    unsafe fn take(&mut self, node: NonNull<DeqNode<T>>) -> T {
+        debug_assert!(
+            self.owns(node),
+            "NodeSlab::take called with a node not allocated by this slab"
+        );
+        let DeqNode { element, .. } = node.as_ptr().read();
+        ptr::addr_of_mut!((*node.as_ptr()).next).write(self.free);
+        self.free = Some(node);
+        element
+    }
+
+    fn owns(&self, node: NonNull<DeqNode<T>>) -> bool {
+        let addr = node.as_ptr() as usize;
+        self.chunks.iter().any(|chunk| {
+            let start = chunk.as_ptr() as *mut DeqNode<T> as usize;
+            let end = start + chunk.len() * std::mem::size_of::<DeqNode<T>>();
+            (start..end).contains(&addr)
+        })
+    }
+
+    #[cfg(test)]
+    fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{CacheRegion::MainProbation, DeqNode, Deque};
@@ -368,7 +552,6 @@ mod tests {
         // push_back(node1)
         let node1 = DeqNode::new("a".to_string());
         assert!(!deque.contains(&node1));
-        let node1 = Box::new(node1);
         let node1_ptr = deque.push_back(node1);
         assert_eq!(deque.len(), 1);
 
@@ -404,7 +587,7 @@ mod tests {
         // push_back(node2)
         let node2 = DeqNode::new("b".to_string());
         assert!(!deque.contains(&node2));
-        let node2_ptr = deque.push_back(Box::new(node2));
+        let node2_ptr = deque.push_back(node2);
         assert_eq!(deque.len(), 2);
 
         // peek_front() -> node1
@@ -479,7 +662,7 @@ mod tests {
         // push_back(node3)
         let node3 = DeqNode::new("c".to_string());
         assert!(!deque.contains(&node3));
-        let node3_ptr = deque.push_back(Box::new(node3));
+        let node3_ptr = deque.push_back(node3);
         assert_eq!(deque.len(), 3);
 
         // peek_front() -> node2
@@ -543,7 +726,7 @@ mod tests {
         assert!(!deque.contains(node3_ref));
         assert!(node3_ref.next.is_none());
         assert!(node3_ref.next.is_none());
-        std::mem::drop(unsafe { Box::from_raw(node3_ptr.as_ptr()) });
+        unsafe { deque.drop_unlinked(node3_ptr) };
 
         // peek_front() -> node2
         let head_h = deque.peek_front().unwrap();
@@ -576,7 +759,7 @@ mod tests {
         assert!(!deque.contains(node2_ref));
         assert!(node2_ref.next.is_none());
         assert!(node2_ref.next.is_none());
-        std::mem::drop(unsafe { Box::from_raw(node2_ptr.as_ptr()) });
+        unsafe { deque.drop_unlinked(node2_ptr) };
 
         // peek_front() -> node1
         let head_g = deque.peek_front().unwrap();
@@ -603,7 +786,7 @@ mod tests {
         assert!(!deque.contains(node1_ref));
         assert!(node1_ref.next.is_none());
         assert!(node1_ref.next.is_none());
-        std::mem::drop(unsafe { Box::from_raw(node1_ptr.as_ptr()) });
+        unsafe { deque.drop_unlinked(node1_ptr) };
 
         // peek_front() -> node1
         let head_h = deque.peek_front();
@@ -620,11 +803,11 @@ mod tests {
         assert!((&mut deque).next().is_none());
 
         let node1 = DeqNode::new("a".into());
-        deque.push_back(Box::new(node1));
+        deque.push_back(node1);
         let node2 = DeqNode::new("b".into());
-        let node2_ptr = deque.push_back(Box::new(node2));
+        let node2_ptr = deque.push_back(node2);
         let node3 = DeqNode::new("c".into());
-        let node3_ptr = deque.push_back(Box::new(node3));
+        let node3_ptr = deque.push_back(node3);
 
         // -------------------------------------------------------
         // First iteration.
@@ -672,7 +855,7 @@ mod tests {
         // -------------------------------------------------------
         // Try pop_front during iteration.
         let node3 = DeqNode::new("c".into());
-        deque.push_back(Box::new(node3));
+        deque.push_back(node3);
 
         assert_eq!((&mut deque).next(), Some(&"a".into()));
         // Next will be "b", but we call pop_front twice to remove "a" and "b".
@@ -689,16 +872,50 @@ mod tests {
         assert!((&mut deque).next().is_none());
     }
 
+    #[test]
+    fn iter_without_cursor() {
+        let mut deque: Deque<String> = Deque::new(MainProbation);
+        assert!(deque.iter().next().is_none());
+
+        deque.push_back(DeqNode::new("a".into()));
+        let node2_ptr = deque.push_back(DeqNode::new("b".into()));
+        deque.push_back(DeqNode::new("c".into()));
+
+        // Start iterating with the cursor.
+        assert_eq!((&mut deque).next(), Some(&"a".into()));
+
+        let elems = deque.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(elems, ["a", "b", "c"]);
+        let elems = deque.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(elems, ["c", "b", "a"]);
+        assert_eq!(deque.iter().len(), 3);
+
+        // Both ends meet in the middle.
+        let mut iter = deque.iter();
+        assert_eq!(iter.next(), Some(&"a".into()));
+        assert_eq!(iter.next_back(), Some(&"c".into()));
+        assert_eq!(iter.next(), Some(&"b".into()));
+        assert!(iter.next_back().is_none());
+        assert!(iter.next().is_none());
+
+        // The cursor was not disturbed.
+        assert_eq!((&mut deque).next(), Some(&"b".into()));
+
+        unsafe { deque.move_to_back(node2_ptr) };
+        let elems = deque.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(elems, ["a", "c", "b"]);
+    }
+
     #[test]
     fn next_node() {
         let mut deque: Deque<String> = Deque::new(MainProbation);
 
         let node1 = DeqNode::new("a".into());
-        deque.push_back(Box::new(node1));
+        deque.push_back(node1);
         let node2 = DeqNode::new("b".into());
-        let node2_ptr = deque.push_back(Box::new(node2));
+        let node2_ptr = deque.push_back(node2);
         let node3 = DeqNode::new("c".into());
-        let node3_ptr = deque.push_back(Box::new(node3));
+        let node3_ptr = deque.push_back(node3);
 
         // -------------------------------------------------------
         // First iteration.
@@ -739,11 +956,11 @@ mod tests {
         let mut deque: Deque<String> = Deque::new(MainProbation);
 
         let node1 = DeqNode::new("a".into());
-        deque.push_back(Box::new(node1));
+        deque.push_back(node1);
         let node2 = DeqNode::new("b".into());
-        let _ = deque.push_back(Box::new(node2));
+        let _ = deque.push_back(node2);
         let node3 = DeqNode::new("c".into());
-        let _ = deque.push_back(Box::new(node3));
+        let _ = deque.push_back(node3);
         // "a" -> "b" -> "c"
 
         let node1a = deque.peek_front_ptr().unwrap();
@@ -763,16 +980,39 @@ mod tests {
         assert_eq!(node1b.element, "b".to_string());
     }
 
+    #[test]
+    fn move_to_back_on_tail_does_not_relink() {
+        let mut deque: Deque<String> = Deque::new(MainProbation);
+
+        let node1_ptr = deque.push_back(DeqNode::new("a".into()));
+        let node2_ptr = deque.push_back(DeqNode::new("b".into()));
+
+        // Place the cursor on the tail node "b".
+        assert_eq!((&mut deque).next(), Some(&"a".into()));
+
+        // Relinking the node would have advanced the cursor past it.
+        unsafe { deque.move_to_back(node2_ptr) };
+        assert_eq!((&mut deque).next(), Some(&"b".into()));
+        assert!((&mut deque).next().is_none());
+
+        let node1 = unsafe { node1_ptr.as_ref() };
+        let node2 = unsafe { node2_ptr.as_ref() };
+        assert_eq!(node1.next, Some(node2_ptr));
+        assert_eq!(node2.prev, Some(node1_ptr));
+        assert!(node2.next.is_none());
+        assert!(deque.is_tail(node2));
+    }
+
     #[test]
     fn reachable_from_head_rejects_foreign_non_head_node() {
         let mut deque_a: Deque<String> = Deque::new(MainProbation);
         let mut deque_b: Deque<String> = Deque::new(MainProbation);
 
-        deque_a.push_back(Box::new(DeqNode::new("a1".into())));
-        deque_a.push_back(Box::new(DeqNode::new("a2".into())));
+        deque_a.push_back(DeqNode::new("a1".into()));
+        deque_a.push_back(DeqNode::new("a2".into()));
 
-        deque_b.push_back(Box::new(DeqNode::new("b1".into())));
-        let node_b2_ptr = deque_b.push_back(Box::new(DeqNode::new("b2".into())));
+        deque_b.push_back(DeqNode::new("b1".into()));
+        let node_b2_ptr = deque_b.push_back(DeqNode::new("b2".into()));
 
         // node_b2 is a non-head node (prev.is_some()) in deque_b.
         // The fast-path check (prev.is_some() || is_head) would return true
@@ -791,11 +1031,11 @@ mod tests {
         let mut deque_a: Deque<String> = Deque::new(MainProbation);
         let mut deque_b: Deque<String> = Deque::new(MainProbation);
 
-        deque_a.push_back(Box::new(DeqNode::new("a1".into())));
-        deque_a.push_back(Box::new(DeqNode::new("a2".into())));
+        deque_a.push_back(DeqNode::new("a1".into()));
+        deque_a.push_back(DeqNode::new("a2".into()));
 
-        deque_b.push_back(Box::new(DeqNode::new("b1".into())));
-        let node_b2_ptr = deque_b.push_back(Box::new(DeqNode::new("b2".into())));
+        deque_b.push_back(DeqNode::new("b1".into()));
+        let node_b2_ptr = deque_b.push_back(DeqNode::new("b2".into()));
 
         let node_b2_ref = unsafe { node_b2_ptr.as_ref() };
         assert!(node_b2_ref.prev.is_some());
@@ -810,9 +1050,9 @@ mod tests {
         let mut deque: Deque<String> = Deque::new(MainProbation);
 
         let node1 = DeqNode::new("a".to_string());
-        let node1_ptr = deque.push_back(Box::new(node1));
+        let node1_ptr = deque.push_back(node1);
         let node2 = DeqNode::new("b".to_string());
-        let node2_ptr = deque.push_back(Box::new(node2));
+        let node2_ptr = deque.push_back(node2);
 
         unsafe { deque.unlink(node2_ptr) };
 
@@ -821,7 +1061,63 @@ mod tests {
 
         // Clean up
         assert!(deque.contains(unsafe { node1_ptr.as_ref() }));
-        unsafe { std::mem::drop(Box::from_raw(node2_ptr.as_ptr())) };
+        unsafe { deque.drop_unlinked(node2_ptr) };
+    }
+
+    #[test]
+    fn slab_reuses_freed_slots() {
+        let mut deque: Deque<String> = Deque::new(MainProbation);
+
+        let node1_ptr = deque.push_back(DeqNode::new("a".into()));
+        let node2_ptr = deque.push_back(DeqNode::new("b".into()));
+        unsafe { deque.unlink_and_drop(node1_ptr) };
+
+        // The freed slot of node1 is handed out again.
+        let node3_ptr = deque.push_back(DeqNode::new("c".into()));
+        assert_eq!(node3_ptr, node1_ptr);
+        assert_eq!(deque.len(), 2);
+        assert_eq!(deque.peek_front().unwrap().element, "b".to_string());
+        assert_eq!(deque.peek_back().unwrap().element, "c".to_string());
+
+        // Slots freed by pop_front are reused too.
+        assert_eq!(deque.pop_front(), Some("b".to_string()));
+        let node4_ptr = deque.push_back(DeqNode::new("d".into()));
+        assert_eq!(node4_ptr, node2_ptr);
+        assert_eq!(deque.slab.chunk_count(), 1);
+    }
+
+    #[test]
+    fn slab_allocates_nodes_in_chunks() {
+        const NUM_NODES: usize = 10_000;
+
+        let mut deque: Deque<usize> = Deque::new(MainProbation);
+        assert_eq!(deque.slab.chunk_count(), 0);
+
+        let ptrs = (0..NUM_NODES)
+            .map(|i| deque.push_back(DeqNode::new(i)))
+            .collect::<Vec<_>>();
+        assert_eq!(deque.len(), NUM_NODES);
+        // 16 + 32 + ... + 4096 = 8176 slots in the first nine chunks.
+        assert_eq!(deque.slab.chunk_count(), 10);
+
+        // All pointers are still valid and in order.
+        for (i, ptr) in ptrs.iter().enumerate() {
+            assert_eq!(unsafe { ptr.as_ref() }.element, i);
+        }
+
+        // Free every other node and push them back. No new chunk is needed.
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { deque.unlink_and_drop(*ptr) };
+        }
+        for i in 0..NUM_NODES / 2 {
+            deque.push_back(DeqNode::new(i));
+        }
+        assert_eq!(deque.len(), NUM_NODES);
+        assert_eq!(deque.slab.chunk_count(), 10);
+
+        while deque.pop_front().is_some() {}
+        assert_eq!(deque.len(), 0);
+        assert_eq!(deque.slab.chunk_count(), 10);
     }
 
     #[test]
@@ -843,10 +1139,10 @@ mod tests {
         let node2 = DeqNode::new(X(2, Rc::clone(&dropped)));
         let node3 = DeqNode::new(X(3, Rc::clone(&dropped)));
         let node4 = DeqNode::new(X(4, Rc::clone(&dropped)));
-        deque.push_back(Box::new(node1));
-        deque.push_back(Box::new(node2));
-        deque.push_back(Box::new(node3));
-        deque.push_back(Box::new(node4));
+        deque.push_back(node1);
+        deque.push_back(node2);
+        deque.push_back(node3);
+        deque.push_back(node4);
         assert_eq!(deque.len(), 4);
 
         std::mem::drop(deque);
@@ -11,10 +11,134 @@
 // For full authorship information, see the version control history of
 // https://github.com/ben-manes/caffeine/
 
+/// An estimator of the popularity of the keys, used by the TinyLFU admission
+/// policy of a cache.
+///
+/// A cache uses a built-in frequency sketch (a 4-bit Count-Min sketch with
+/// periodic aging) by default. Implement this trait to experiment with a
+/// different estimator, and set it with
+/// [`CacheBuilder::frequency_estimator`][builder-method].
+///
+/// The keys are identified by their hashes, computed with the hasher of the
+/// cache. The cache calls `increment` on every read of a key, and compares the
+/// `frequency` of a candidate key with the one of the eviction victim to decide
+/// whether to admit the candidate: it is admitted only if its frequency is
+/// strictly greater.
+///
+/// The estimator must be `Clone`, so that
+/// [`Cache::clone_with_capacity`][clone-method] can clone it with the cache.
+/// The [`CloneEstimator`] supertrait is then implemented automatically.
+///
+/// [builder-method]: ./unsync/struct.CacheBuilder.html#method.frequency_estimator
+/// [clone-method]: ./unsync/struct.Cache.html#method.clone_with_capacity
+pub trait FrequencyEstimator: CloneEstimator {
+    /// Records an occurrence of the key with the given hash.
+    fn increment(&mut self, hash: u64);
+
+    /// Returns the estimated number of occurrences of the key with the given
+    /// hash.
+    fn frequency(&self, hash: u64) -> u8;
+
+    /// Prepares the estimator to track about `cap` distinct keys.
+    ///
+    /// The cache calls this when the estimator is enabled, i.e. when the cache
+    /// is filled to half of its max capacity, and again when the max capacity
+    /// is raised. `cap` is the max capacity, or the expected key cardinality of
+    /// the cache if it is greater.
+    fn ensure_capacity(&mut self, cap: u64);
+
+    /// Forgets all the recorded occurrences.
+    ///
+    /// The cache calls this when the hashes of the keys are no longer valid,
+    /// e.g. when the cache is rebuilt with a different hasher.
+    fn reset(&mut self);
+}
+
+/// A helper supertrait of [`FrequencyEstimator`] to clone an estimator behind a
+/// `Box<dyn FrequencyEstimator>`, as `Clone` itself cannot be a supertrait of a
+/// trait object.
+///
+/// There is no need to implement this trait: a blanket implementation covers
+/// every `FrequencyEstimator` that is also `Clone` and `'static`.
+pub trait CloneEstimator {
+    /// Returns a boxed clone of this estimator.
+    fn clone_box(&self) -> Box<dyn FrequencyEstimator>;
+}
+
+impl<T> CloneEstimator for T
+where
+    T: FrequencyEstimator + Clone + 'static,
+{
+    fn clone_box(&self) -> Box<dyn FrequencyEstimator> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn FrequencyEstimator> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// The popularity estimator of a cache: the built-in `FrequencySketch`, or a
+/// user-provided `FrequencyEstimator`.
+#[derive(Clone)]
+pub(crate) enum Estimator {
+    Sketch(FrequencySketch),
+    Custom(Box<dyn FrequencyEstimator>),
+}
+
+impl Default for Estimator {
+    fn default() -> Self {
+        Self::Sketch(FrequencySketch::default())
+    }
+}
+
+impl Estimator {
+    pub(crate) fn ensure_capacity(&mut self, cap: u64) {
+        match self {
+            Self::Sketch(sketch) => sketch.ensure_capacity(super::sketch_capacity(cap)),
+            Self::Custom(estimator) => estimator.ensure_capacity(cap),
+        }
+    }
+
+    pub(crate) fn frequency(&self, hash: u64) -> u8 {
+        match self {
+            Self::Sketch(sketch) => sketch.frequency(hash),
+            Self::Custom(estimator) => estimator.frequency(hash),
+        }
+    }
+
+    pub(crate) fn increment(&mut self, hash: u64) {
+        match self {
+            Self::Sketch(sketch) => sketch.increment(hash),
+            Self::Custom(estimator) => estimator.increment(hash),
+        }
+    }
+
+    /// Returns the fraction of the counters at the maximum, or `None` for a
+    /// user-provided estimator.
+    pub(crate) fn saturation(&self) -> Option<f64> {
+        match self {
+            Self::Sketch(sketch) => Some(sketch.saturation()),
+            Self::Custom(_) => None,
+        }
+    }
+
+    /// Forgets all the recorded occurrences. The built-in sketch also frees its
+    /// table, which is allocated again by `ensure_capacity`.
+    pub(crate) fn reset(&mut self) {
+        match self {
+            Self::Sketch(sketch) => *sketch = FrequencySketch::default(),
+            Self::Custom(estimator) => estimator.reset(),
+        }
+    }
+}
+
 /// A probabilistic multi-set for estimating the popularity of an element within
 /// a time window. The maximum frequency of an element is limited to 15 (4-bits)
 /// and an aging process periodically halves the popularity of all elements.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub(crate) struct FrequencySketch {
     sample_size: u32,
     table_mask: u32,
@@ -152,6 +276,24 @@ impl FrequencySketch {
         }
     }
 
+    /// Returns the fraction of the counters at the maximum value (15), or `0.0`
+    /// if the table is not allocated. This scans the whole table.
+    pub(crate) fn saturation(&self) -> f64 {
+        if self.table.is_empty() {
+            return 0.0;
+        }
+
+        let saturated = self
+            .table
+            .iter()
+            // Set the lowest bit of each 4-bit counter only if all its bits are set.
+            .map(|&entry| {
+                (entry & (entry >> 1) & (entry >> 2) & (entry >> 3) & ONE_MASK).count_ones()
+            })
+            .sum::<u32>();
+        saturated as f64 / (self.table.len() * 16) as f64
+    }
+
     /// Takes a table index (each entry has 16 counters) and counter index, and
     /// increments the counter by 1 if it is not already at the maximum value
     /// (15). Returns `true` if incremented.
@@ -194,6 +336,16 @@ impl FrequencySketch {
     }
 }
 
+#[cfg(test)]
+impl Estimator {
+    pub(crate) fn table_len(&self) -> usize {
+        match self {
+            Self::Sketch(sketch) => sketch.table_len(),
+            Self::Custom(_) => panic!("not the built-in sketch"),
+        }
+    }
+}
+
 // Some test cases were ported from Caffeine at:
 // https://github.com/ben-manes/caffeine/blob/master/caffeine/src/test/java/com/github/benmanes/caffeine/cache/FrequencySketchTest.java
 //
@@ -282,6 +434,22 @@ mod tests {
         assert!(sketch.size <= sketch.sample_size / 2);
     }
 
+    #[test]
+    fn saturation() {
+        let mut sketch = FrequencySketch::default();
+        assert_eq!(sketch.saturation(), 0.0);
+
+        sketch.ensure_capacity(64);
+        let hasher = hasher();
+        let item_hash = hasher(*ITEM);
+        for _ in 0..20 {
+            sketch.increment(item_hash);
+        }
+        // Each item has one counter at each of the 4 depths.
+        let counters = (sketch.table.len() * 16) as f64;
+        assert_eq!(sketch.saturation(), 4.0 / counters);
+    }
+
     // This test was ported from Caffeine.
     #[test]
     fn heavy_hitters() {
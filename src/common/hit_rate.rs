@@ -0,0 +1,64 @@
+/// The default number of the latest read operations covered by the window.
+pub(crate) const DEFAULT_HIT_RATE_WINDOW: usize = 1024;
+
+const NUM_BUCKETS: usize = 8;
+
+/// Estimates the hit rate of the latest read operations.
+///
+/// The window is a ring of buckets, each counting the hits and misses of a
+/// fixed number of operations. When the current bucket is full, the ring
+/// advances and the oldest bucket is cleared for reuse. So the estimate covers
+/// between `window - window / NUM_BUCKETS` and `window` operations.
+#[derive(Clone, Debug)]
+pub(crate) struct HitRateWindow {
+    // The (hits, misses) of each bucket.
+    buckets: [(u64, u64); NUM_BUCKETS],
+    bucket_size: u64,
+    current: usize,
+}
+
+impl HitRateWindow {
+    pub(crate) fn new(window: usize) -> Self {
+        let bucket_size = (window as u64).div_ceil(NUM_BUCKETS as u64).max(1);
+        Self {
+            buckets: Default::default(),
+            bucket_size,
+            current: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, hit: bool) {
+        let (hits, misses) = self.buckets[self.current];
+        if hits + misses >= self.bucket_size {
+            self.current = (self.current + 1) % NUM_BUCKETS;
+            self.buckets[self.current] = (0, 0);
+        }
+
+        let bucket = &mut self.buckets[self.current];
+        if hit {
+            bucket.0 += 1;
+        } else {
+            bucket.1 += 1;
+        }
+    }
+
+    /// Forgets all the recorded operations.
+    pub(crate) fn reset(&mut self) {
+        self.buckets = Default::default();
+        self.current = 0;
+    }
+
+    /// Returns the ratio of the hits in the window, or `1.0` if no operation has
+    /// been recorded.
+    pub(crate) fn hit_rate(&self) -> f64 {
+        let (hits, misses) = self
+            .buckets
+            .iter()
+            .fold((0, 0), |(h, m), (hits, misses)| (h + hits, m + misses));
+        if hits + misses == 0 {
+            1.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        }
+    }
+}
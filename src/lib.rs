@@ -51,7 +51,11 @@ pub(crate) mod common;
 pub(crate) mod policy;
 pub mod unsync;
 
-pub use policy::Policy;
+pub use common::{
+    frequency_sketch::{CloneEstimator, FrequencyEstimator},
+    EntryRegion,
+};
+pub use policy::{EvictionPolicy, InsertPolicy, Policy};
 
 #[cfg(doctest)]
 mod doctests {
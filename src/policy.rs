@@ -1,16 +1,182 @@
+use crate::common::hit_rate::DEFAULT_HIT_RATE_WINDOW;
+
+/// The options of a cache, set by the `CacheBuilder` and kept by the cache, so
+/// that a cache can be rebuilt (e.g. by `rehash`) with the same options.
+#[derive(Clone, Debug)]
+pub(crate) struct CacheSettings {
+    pub(crate) max_capacity: Option<u64>,
+    pub(crate) insert_policy: InsertPolicy,
+    pub(crate) eviction_policy: EvictionPolicy,
+    pub(crate) promotion_threshold: Option<u8>,
+    pub(crate) protected_capacity: Option<u64>,
+    pub(crate) expected_key_cardinality: Option<u64>,
+    pub(crate) frequency_sketch_activation: Option<f64>,
+    pub(crate) negative_caching: bool,
+    pub(crate) lazy_eviction: bool,
+    pub(crate) maintenance_interval: Option<u64>,
+    pub(crate) track_insertion_order: bool,
+    pub(crate) track_cardinality: bool,
+    pub(crate) skip_orphaned_victims: bool,
+    pub(crate) panic_safe_mode: bool,
+    pub(crate) update_refreshes_recency: bool,
+    pub(crate) hit_rate_window: usize,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            max_capacity: None,
+            insert_policy: InsertPolicy::default(),
+            eviction_policy: EvictionPolicy::default(),
+            promotion_threshold: None,
+            protected_capacity: None,
+            expected_key_cardinality: None,
+            frequency_sketch_activation: None,
+            negative_caching: true,
+            lazy_eviction: false,
+            maintenance_interval: None,
+            track_insertion_order: false,
+            track_cardinality: false,
+            skip_orphaned_victims: false,
+            panic_safe_mode: false,
+            update_refreshes_recency: true,
+            hit_rate_window: DEFAULT_HIT_RATE_WINDOW,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 /// The policy of a cache.
 pub struct Policy {
-    max_capacity: Option<u64>,
+    settings: CacheSettings,
+    eviction_batch_size: usize,
 }
 
 impl Policy {
-    pub(crate) fn new(max_capacity: Option<u64>) -> Self {
-        Self { max_capacity }
+    pub(crate) fn new(settings: CacheSettings, eviction_batch_size: usize) -> Self {
+        Self {
+            settings,
+            eviction_batch_size,
+        }
     }
 
     /// Returns the `max_capacity` of the cache.
     pub fn max_capacity(&self) -> Option<u64> {
-        self.max_capacity
+        self.settings.max_capacity
+    }
+
+    /// Returns the `insert_policy` of the cache.
+    pub fn insert_policy(&self) -> InsertPolicy {
+        self.settings.insert_policy
+    }
+
+    /// Returns the `eviction_policy` of the cache.
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.settings.eviction_policy
+    }
+
+    /// Returns the `promotion_threshold` of the cache, or `None` if the entries
+    /// are never promoted to the protected region.
+    pub fn promotion_threshold(&self) -> Option<u8> {
+        self.settings.promotion_threshold
+    }
+
+    /// Returns the `expected_key_cardinality` of the cache, or `None` if the
+    /// frequency sketch is sized for the max capacity.
+    pub fn expected_key_cardinality(&self) -> Option<u64> {
+        self.settings.expected_key_cardinality
+    }
+
+    /// Returns the fraction of the max capacity that the cache must be filled to
+    /// before the frequency sketch is enabled.
+    pub fn frequency_sketch_activation(&self) -> f64 {
+        self.settings.frequency_sketch_activation.unwrap_or(0.5)
+    }
+
+    /// Returns `true` if the cache keeps tombstones of negatively cached keys.
+    pub fn negative_caching(&self) -> bool {
+        self.settings.negative_caching
     }
+
+    /// Returns `true` if the read operations of the cache defer the eviction of
+    /// the entries over the max capacity.
+    pub fn lazy_eviction(&self) -> bool {
+        self.settings.lazy_eviction
+    }
+
+    /// Returns `true` if the cache tracks the order in which the keys were
+    /// inserted.
+    pub fn track_insertion_order(&self) -> bool {
+        self.settings.track_insertion_order
+    }
+
+    /// Returns the number of the latest read operations covered by the recent
+    /// hit rate of the cache.
+    pub fn hit_rate_window(&self) -> usize {
+        self.settings.hit_rate_window
+    }
+
+    /// Returns `true` if updating the value of an existing key moves the entry
+    /// to the most recently used end.
+    pub fn update_refreshes_recency(&self) -> bool {
+        self.settings.update_refreshes_recency
+    }
+
+    /// Returns the `protected_capacity` of the cache, or `None` if the protected
+    /// region takes the default 80% of the max capacity.
+    pub fn protected_capacity(&self) -> Option<u64> {
+        self.settings.protected_capacity
+    }
+
+    /// Returns the number of write operations between the runs of the
+    /// maintenance of the cache. It is 1 unless set by `maintenance_interval`
+    /// of the builder.
+    pub fn maintenance_interval(&self) -> u64 {
+        self.settings.maintenance_interval.unwrap_or(1)
+    }
+
+    /// Returns the max number of entries evicted by a single write operation
+    /// when the cache is over its max capacity.
+    pub fn eviction_batch_size(&self) -> usize {
+        self.eviction_batch_size
+    }
+}
+
+/// The policy applied when a new key is inserted to a full cache.
+///
+/// Set by the [`insert_policy`][insert-policy-method] method of the
+/// `CacheBuilder`.
+///
+/// [insert-policy-method]: ./unsync/struct.CacheBuilder.html#method.insert_policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InsertPolicy {
+    /// The new entry goes through the admission policy, and if admitted, the
+    /// least recently used entry is evicted to make room for it. This is the
+    /// default.
+    #[default]
+    EvictToFit,
+    /// The new entry is rejected and the existing entries are left intact, like
+    /// a bounded buffer. Updating the value of an existing key is still allowed.
+    RejectWhenFull,
+}
+
+/// The algorithm to select the entries to evict from a full cache.
+///
+/// Set by the [`eviction_policy`][eviction-policy-method] method of the
+/// `CacheBuilder`.
+///
+/// [eviction-policy-method]: ./unsync/struct.CacheBuilder.html#method.eviction_policy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The least recently used entry is the eviction victim, and a new entry is
+    /// admitted only if it is estimated to be more popular than the victim
+    /// (TinyLFU admission). This is the default.
+    #[default]
+    TinyLfu,
+    /// CLOCK (second-chance) eviction. A read only sets a reference bit of the
+    /// entry, which is cheaper than relinking it to the back of the LRU queue.
+    /// The eviction sweep clears the bit of the referenced entries and skips
+    /// them, evicting the first entry found with a clear bit. New entries are
+    /// always admitted.
+    Clock,
 }
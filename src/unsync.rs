@@ -6,14 +6,18 @@
 mod builder;
 mod cache;
 mod deques;
+mod frozen;
 mod iter;
+mod value_ref;
 
-use std::rc::Rc;
+use std::{ptr::NonNull, rc::Rc};
 use tagptr::TagNonNull;
 
-pub use builder::CacheBuilder;
-pub use cache::Cache;
-pub use iter::Iter;
+pub use builder::{BuildError, CacheBuilder};
+pub use cache::{AdmissionPreview, Cache, CacheSummary, Lookup, SizeInfo, UpsertResult};
+pub use frozen::FrozenCache;
+pub use iter::{Cursor, Iter};
+pub use value_ref::ValueRef;
 
 use crate::common::deque::DeqNode;
 
@@ -31,28 +35,142 @@ impl<K> KeyHashDate<K> {
 // DeqNode for an access order queue.
 type KeyDeqNodeAo<K> = TagNonNull<DeqNode<KeyHashDate<K>>, 2>;
 
+// DeqNode for the write order queue.
+type KeyDeqNodeWo<K> = NonNull<DeqNode<KeyHashDate<K>>>;
+
 struct EntryInfo<K> {
     access_order_q_node: Option<KeyDeqNodeAo<K>>,
+    write_order_q_node: Option<KeyDeqNodeWo<K>>,
+    // The reference bit for the CLOCK eviction policy.
+    referenced: bool,
+    // The number of hits while in the probation region, for the promotion to the
+    // protected region.
+    hits: u8,
+    // The exact number of read hits since the value was set.
+    access_count: u64,
+    // The version of the cache when the value was last set.
+    modified_at_version: u64,
+}
+
+impl<K> EntryInfo<K> {
+    // Copies the information except the deque nodes.
+    fn unlinked(&self) -> Self {
+        Self {
+            access_order_q_node: None,
+            write_order_q_node: None,
+            referenced: self.referenced,
+            hits: self.hits,
+            access_count: self.access_count,
+            modified_at_version: self.modified_at_version,
+        }
+    }
 }
 
 pub(crate) struct ValueEntry<K, V> {
-    pub(crate) value: V,
+    // `None` if this entry is a tombstone of a negatively cached key.
+    pub(crate) value: Option<V>,
+    // The initializer of a value inserted by `insert_lazy`, run by the first read.
+    // `value` is `None` until then.
+    init: Option<Box<dyn FnOnce() -> V>>,
+    // The version of the value given to `insert_versioned`, or zero.
+    value_version: u64,
     info: EntryInfo<K>,
 }
 
 impl<K, V> ValueEntry<K, V> {
     pub(crate) fn new(value: V) -> Self {
+        Self::with_value(Some(value))
+    }
+
+    pub(crate) fn new_versioned(value: V, version: u64) -> Self {
+        Self {
+            value_version: version,
+            ..Self::with_value(Some(value))
+        }
+    }
+
+    pub(crate) fn new_tombstone() -> Self {
+        Self::with_value(None)
+    }
+
+    pub(crate) fn new_lazy(init: Box<dyn FnOnce() -> V>) -> Self {
+        Self {
+            init: Some(init),
+            ..Self::with_value(None)
+        }
+    }
+
+    fn with_value(value: Option<V>) -> Self {
         Self {
             value,
+            init: None,
+            value_version: 0,
             info: EntryInfo {
                 access_order_q_node: None,
+                write_order_q_node: None,
+                referenced: false,
+                hits: 0,
+                access_count: 0,
+                modified_at_version: 0,
             },
         }
     }
 
     #[inline]
-    pub(crate) fn replace_deq_nodes_with(&mut self, mut other: Self) {
+    pub(crate) fn value_version(&self) -> u64 {
+        self.value_version
+    }
+
+    /// Moves the value, or the initializer of a pending value, into a new entry
+    /// not linked to any deque, keeping the per-entry information (the counts
+    /// of hits, the versions and the reference bit). Used to rebuild a cache.
+    pub(crate) fn into_unlinked(self) -> Self {
+        Self {
+            value: self.value,
+            init: self.init,
+            value_version: self.value_version,
+            info: self.info.unlinked(),
+        }
+    }
+
+    /// Clones the value into a new entry not linked to any deque, keeping the
+    /// per-entry information like `into_unlinked`.
+    ///
+    /// Returns `None` if the value is pending, as its initializer cannot be
+    /// cloned.
+    pub(crate) fn clone_unlinked(&self) -> Option<Self>
+    where
+        V: Clone,
+    {
+        if self.is_pending() {
+            return None;
+        }
+        Some(Self {
+            value: self.value.clone(),
+            init: None,
+            value_version: self.value_version,
+            info: self.info.unlinked(),
+        })
+    }
+
+    /// Returns `true` if the value is not initialized yet.
+    #[inline]
+    pub(crate) fn is_pending(&self) -> bool {
+        self.init.is_some()
+    }
+
+    /// Runs the initializer of a lazily inserted value, if it has not run yet.
+    #[inline]
+    pub(crate) fn force(&mut self) {
+        if let Some(init) = self.init.take() {
+            self.value = Some(init());
+        }
+    }
+
+    #[inline]
+    pub(crate) fn replace_deq_nodes_with(&mut self, other: &mut Self) {
         self.info.access_order_q_node = other.info.access_order_q_node.take();
+        self.info.write_order_q_node = other.info.write_order_q_node.take();
     }
 
     #[inline]
@@ -70,6 +188,69 @@ impl<K, V> ValueEntry<K, V> {
         self.info.access_order_q_node.take()
     }
 
+    #[inline]
+    pub(crate) fn write_order_q_node(&self) -> Option<KeyDeqNodeWo<K>> {
+        self.info.write_order_q_node
+    }
+
+    #[inline]
+    pub(crate) fn set_write_order_q_node(&mut self, node: Option<KeyDeqNodeWo<K>>) {
+        self.info.write_order_q_node = node;
+    }
+
+    #[inline]
+    pub(crate) fn take_write_order_q_node(&mut self) -> Option<KeyDeqNodeWo<K>> {
+        self.info.write_order_q_node.take()
+    }
+
+    #[inline]
+    pub(crate) fn is_referenced(&self) -> bool {
+        self.info.referenced
+    }
+
+    #[inline]
+    pub(crate) fn set_referenced(&mut self) {
+        self.info.referenced = true;
+    }
+
+    /// Clears the reference bit, returning its old value.
+    #[inline]
+    pub(crate) fn take_referenced(&mut self) -> bool {
+        std::mem::replace(&mut self.info.referenced, false)
+    }
+
+    /// Increments the hit count, returning the new count.
+    #[inline]
+    pub(crate) fn increment_hits(&mut self) -> u8 {
+        self.info.hits = self.info.hits.saturating_add(1);
+        self.info.hits
+    }
+
+    #[inline]
+    pub(crate) fn reset_hits(&mut self) {
+        self.info.hits = 0;
+    }
+
+    #[inline]
+    pub(crate) fn access_count(&self) -> u64 {
+        self.info.access_count
+    }
+
+    #[inline]
+    pub(crate) fn increment_access_count(&mut self) {
+        self.info.access_count = self.info.access_count.saturating_add(1);
+    }
+
+    #[inline]
+    pub(crate) fn modified_at_version(&self) -> u64 {
+        self.info.modified_at_version
+    }
+
+    #[inline]
+    pub(crate) fn set_modified_at_version(&mut self, version: u64) {
+        self.info.modified_at_version = version;
+    }
+
     #[inline]
     pub(crate) fn policy_weight(&self) -> u32 {
         1
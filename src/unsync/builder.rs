@@ -1,9 +1,15 @@
-use super::Cache;
+use super::{
+    cache::{CostFn, EvictionListener},
+    Cache,
+};
+use crate::{policy::CacheSettings, EvictionPolicy, FrequencyEstimator, InsertPolicy};
 
 use std::{
     collections::hash_map::RandomState,
+    fmt,
     hash::{BuildHasher, Hash},
     marker::PhantomData,
+    rc::Rc,
 };
 
 /// Builds a [`Cache`][cache-struct] with various configuration knobs.
@@ -27,8 +33,13 @@ use std::{
 ///
 #[must_use]
 pub struct CacheBuilder<K, V, C> {
-    max_capacity: Option<u64>,
+    settings: CacheSettings,
     initial_capacity: Option<usize>,
+    preallocate_frequency_sketch: bool,
+    target_load_factor: Option<f64>,
+    frequency_estimator: Option<Box<dyn FrequencyEstimator>>,
+    eviction_listener: Option<EvictionListener<K, V>>,
+    cost_fn: Option<CostFn<K, V>>,
     cache_type: PhantomData<C>,
     _marker: PhantomData<(K, V)>,
 }
@@ -39,8 +50,13 @@ where
 {
     fn default() -> Self {
         Self {
-            max_capacity: None,
+            settings: CacheSettings::default(),
             initial_capacity: None,
+            preallocate_frequency_sketch: false,
+            target_load_factor: None,
+            frequency_estimator: None,
+            eviction_listener: None,
+            cost_fn: None,
             cache_type: Default::default(),
             _marker: Default::default(),
         }
@@ -55,31 +71,193 @@ where
     /// up to `max_capacity` entries.
     pub fn new(max_capacity: u64) -> Self {
         Self {
-            max_capacity: Some(max_capacity),
+            settings: CacheSettings {
+                max_capacity: Some(max_capacity),
+                ..Default::default()
+            },
             ..Default::default()
         }
     }
 
     /// Builds a `Cache<K, V>`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid. See
+    /// [`build_checked`](#method.build_checked) for the validations.
     pub fn build(self) -> Cache<K, V, RandomState> {
-        let build_hasher = RandomState::default();
-        Cache::with_everything(self.max_capacity, self.initial_capacity, build_hasher)
+        self.build_checked().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds a `Cache<K, V>`, or returns a [`BuildError`][build-error-enum] if
+    /// the configuration has conflicting options.
+    ///
+    /// The hash map is allocated with a fallible allocation, so an initial
+    /// capacity that overflows or cannot be allocated is reported as
+    /// `BuildError::AllocationFailed` rather than a panic.
+    ///
+    /// [build-error-enum]: ./enum.BuildError.html
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::{unsync::{BuildError, Cache}, EvictionPolicy};
+    ///
+    /// // The CLOCK policy has no protected region to promote the entries to.
+    /// let result = Cache::<u32, u32>::builder()
+    ///     .max_capacity(10)
+    ///     .eviction_policy(EvictionPolicy::Clock)
+    ///     .promotion_threshold(2)
+    ///     .build_checked();
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(BuildError::PromotionThresholdWithClock)
+    /// ));
+    /// ```
+    ///
+    pub fn build_checked(self) -> Result<Cache<K, V, RandomState>, BuildError> {
+        self.try_build_with_hasher(RandomState::default())
     }
 
     /// Builds a `Cache<K, V, S>`, with the given `hasher`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, or if the hash map cannot be
+    /// allocated for the initial capacity. See
+    /// [`build_checked`](#method.build_checked) for the validations.
     pub fn build_with_hasher<S>(self, hasher: S) -> Cache<K, V, S>
     where
         S: BuildHasher + Clone,
     {
-        Cache::with_everything(self.max_capacity, self.initial_capacity, hasher)
+        self.try_build_with_hasher(hasher)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_build_with_hasher<S>(self, hasher: S) -> Result<Cache<K, V, S>, BuildError>
+    where
+        S: BuildHasher + Clone,
+    {
+        self.validate()?;
+        let map_capacity = self.map_capacity();
+        let mut cache = Cache::with_everything(
+            self.settings,
+            None,
+            self.frequency_estimator,
+            self.eviction_listener,
+            self.cost_fn,
+            hasher,
+        );
+        if let Some(capacity) = map_capacity {
+            cache
+                .try_reserve(capacity)
+                .map_err(|_| BuildError::AllocationFailed { capacity })?;
+        }
+        if self.preallocate_frequency_sketch {
+            cache.preallocate_frequency_sketch();
+        }
+        Ok(cache)
+    }
+}
+
+/// A hasher with fixed keys, making the hashes, and therefore the admission
+/// and eviction decisions, reproducible across test runs.
+#[cfg(test)]
+pub(crate) type TestHasher =
+    std::hash::BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+
+#[cfg(test)]
+impl<K, V> CacheBuilder<K, V, Cache<K, V, RandomState>>
+where
+    K: Eq + Hash,
+{
+    /// Builds a `Cache<K, V>` with the [`TestHasher`].
+    pub(crate) fn build_with_test_hasher(self) -> Cache<K, V, TestHasher> {
+        self.build_with_hasher(TestHasher::default())
     }
 }
 
 impl<K, V, C> CacheBuilder<K, V, C> {
+    fn validate(&self) -> Result<(), BuildError> {
+        if let Some(threshold) = self.settings.promotion_threshold {
+            if threshold == 0 {
+                return Err(BuildError::ZeroPromotionThreshold);
+            }
+            if self.settings.eviction_policy == EvictionPolicy::Clock {
+                return Err(BuildError::PromotionThresholdWithClock);
+            }
+        }
+
+        if let Some(factor) = self.target_load_factor {
+            if !(factor > 0.0 && factor < 1.0) {
+                return Err(BuildError::InvalidTargetLoadFactor);
+            }
+            if self.settings.max_capacity.is_none() {
+                return Err(BuildError::TargetLoadFactorWithoutMaxCapacity);
+            }
+        }
+
+        if let Some(fraction) = self.settings.frequency_sketch_activation {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(BuildError::InvalidFrequencySketchActivation);
+            }
+            if self.settings.max_capacity.is_none() {
+                return Err(BuildError::FrequencySketchActivationWithoutMaxCapacity);
+            }
+        }
+
+        if self.settings.hit_rate_window == 0 {
+            return Err(BuildError::ZeroHitRateWindow);
+        }
+
+        if self.settings.maintenance_interval == Some(0) {
+            return Err(BuildError::ZeroMaintenanceInterval);
+        }
+
+        if self.preallocate_frequency_sketch && self.settings.max_capacity.is_none() {
+            return Err(BuildError::FrequencySketchPreallocationWithoutMaxCapacity);
+        }
+
+        match self.settings.max_capacity {
+            Some(max_capacity) => {
+                if let Some(protected_capacity) = self.settings.protected_capacity {
+                    if protected_capacity > max_capacity {
+                        return Err(BuildError::ProtectedCapacityExceedsMaxCapacity {
+                            protected_capacity,
+                            max_capacity,
+                        });
+                    }
+                }
+            }
+            None => {
+                if self.settings.promotion_threshold.is_some() {
+                    return Err(BuildError::PromotionThresholdWithoutMaxCapacity);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the initial capacity of the hash map, enlarged to keep the load
+    /// factor below the target when the cache is filled to the max capacity.
+    fn map_capacity(&self) -> Option<usize> {
+        let for_load_factor = self
+            .target_load_factor
+            .zip(self.settings.max_capacity)
+            .map(|(factor, max_capacity)| (max_capacity as f64 / factor).ceil() as usize);
+        match (self.initial_capacity, for_load_factor) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
     /// Sets the max capacity of the cache.
     pub fn max_capacity(self, max_capacity: u64) -> Self {
         Self {
-            max_capacity: Some(max_capacity),
+            settings: CacheSettings {
+                max_capacity: Some(max_capacity),
+                ..self.settings
+            },
             ..self
         }
     }
@@ -91,11 +269,513 @@ impl<K, V, C> CacheBuilder<K, V, C> {
             ..self
         }
     }
+
+    /// Sets the policy applied when a new key is inserted to a full cache.
+    ///
+    /// The default is [`InsertPolicy::EvictToFit`][insert-policy-enum].
+    ///
+    /// [insert-policy-enum]: ../enum.InsertPolicy.html
+    pub fn insert_policy(self, policy: InsertPolicy) -> Self {
+        Self {
+            settings: CacheSettings {
+                insert_policy: policy,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the algorithm to select the entries to evict from a full cache.
+    ///
+    /// The default is [`EvictionPolicy::TinyLfu`][eviction-policy-enum].
+    ///
+    /// [eviction-policy-enum]: ../enum.EvictionPolicy.html
+    pub fn eviction_policy(self, policy: EvictionPolicy) -> Self {
+        Self {
+            settings: CacheSettings {
+                eviction_policy: policy,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Enables the protected region of the main space, and sets the number of
+    /// hits needed to promote an entry from the probation region to it.
+    ///
+    /// Without this, all entries stay in the probation region. With it, an entry
+    /// read (or updated) `threshold` times is promoted to the protected region,
+    /// where it is shielded from eviction while there are entries in probation.
+    /// The protected region holds up to 80% of the max capacity, or the
+    /// [`protected_capacity`](#method.protected_capacity) if set. Its least
+    /// recently used entries are demoted back to probation on the following
+    /// write operations when it is over that size.
+    ///
+    /// The hits are counted per entry: a new entry starts in probation with no
+    /// hits, is promoted when it reaches `threshold` hits, and its count is reset
+    /// when it is demoted, so it has to earn the promotion again. Replacing the
+    /// value of an entry also resets its count.
+    ///
+    /// The threshold must be at least 1. It can only be used with the
+    /// [`EvictionPolicy::TinyLfu`][eviction-policy-enum].
+    ///
+    /// [eviction-policy-enum]: ../enum.EvictionPolicy.html
+    pub fn promotion_threshold(self, threshold: u8) -> Self {
+        Self {
+            settings: CacheSettings {
+                promotion_threshold: Some(threshold),
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the max number of entries in the protected region, instead of the
+    /// default 80% of the max capacity.
+    ///
+    /// When a promotion makes the protected region larger than this, its least
+    /// recently used entries are demoted back to probation on the following
+    /// write operations.
+    ///
+    /// It only takes effect with a [`promotion_threshold`][promotion-threshold],
+    /// and must not be greater than the `max_capacity`.
+    ///
+    /// [promotion-threshold]: #method.promotion_threshold
+    pub fn protected_capacity(self, capacity: u64) -> Self {
+        Self {
+            settings: CacheSettings {
+                protected_capacity: Some(capacity),
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the expected number of distinct keys accessed through the cache, to
+    /// size the historic popularity estimator (frequency sketch).
+    ///
+    /// By default, the estimator is sized for the max capacity. When the universe
+    /// of keys is far larger than the cache (high churn), sizing it for the
+    /// number of distinct keys improves the accuracy of the estimation, at the
+    /// cost of more memory. The estimator is never sized smaller than for the max
+    /// capacity.
+    pub fn expected_key_cardinality(self, cardinality: u64) -> Self {
+        Self {
+            settings: CacheSettings {
+                expected_key_cardinality: Some(cardinality),
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the fraction of the max capacity, in the range `[0, 1]`, that the
+    /// cache must be filled to before the historic popularity estimator
+    /// (frequency sketch) is enabled. The default is `0.5`.
+    ///
+    /// Until it is enabled, the estimator does not record the reads. A lower
+    /// fraction makes the admission policy better informed when the cache first
+    /// becomes full, at the cost of allocating the estimator earlier. `0.0`
+    /// enables it on the first insert. It requires `max_capacity` to be set.
+    pub fn frequency_sketch_activation(self, fraction: f64) -> Self {
+        Self {
+            settings: CacheSettings {
+                frequency_sketch_activation: Some(fraction),
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Allocates the historic popularity estimator (frequency sketch) when the
+    /// cache is built, instead of when the cache is filled to half of its max
+    /// capacity.
+    ///
+    /// Allocating the estimator can take a while for a large max capacity. This
+    /// moves the latency spike to the build time, at the cost of holding the
+    /// memory while the cache is mostly empty. It requires `max_capacity` to be
+    /// set, and has no effect under `EvictionPolicy::Clock`, which does not use
+    /// the estimator.
+    pub fn preallocate_frequency_sketch(self) -> Self {
+        Self {
+            preallocate_frequency_sketch: true,
+            ..self
+        }
+    }
+
+    /// Sets whether the cache keeps tombstones of negatively cached keys. The
+    /// default is `true`.
+    ///
+    /// When disabled, [`insert_absent`][insert-absent-method] discards the entry
+    /// for the key like `invalidate`, and [`take`][take-method] removes the entry
+    /// like `remove`.
+    ///
+    /// [insert-absent-method]: ./struct.Cache.html#method.insert_absent
+    /// [take-method]: ./struct.Cache.html#method.take
+    pub fn negative_caching(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                negative_caching: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether the read operations defer the eviction of the entries over
+    /// the max capacity. The default is `false`.
+    ///
+    /// When enabled, a read operation that would evict the entries over the max
+    /// capacity (e.g. [`get_quiet`][get-quiet-method] after the capacity was
+    /// lowered) only marks the maintenance as due. The entries are then evicted
+    /// by the next write operation, or by
+    /// [`run_pending_maintenance`][run-pending-maintenance-method]. This keeps
+    /// the latency of the reads independent of the eviction cost.
+    ///
+    /// [get-quiet-method]: ./struct.Cache.html#method.get_quiet
+    /// [run-pending-maintenance-method]: ./struct.Cache.html#method.run_pending_maintenance
+    pub fn lazy_eviction(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                lazy_eviction: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the number of write operations between the maintenance runs, to
+    /// amortize their cost. By default, every write operation runs it.
+    ///
+    /// The maintenance demotes the entries over the
+    /// [`protected_capacity`](#method.protected_capacity) back to probation and
+    /// evicts the entries over the max capacity (e.g. after the capacity was
+    /// lowered). With an interval of `n`, only every `n`-th write operation
+    /// (`insert`, `invalidate`, `remove`, ...) runs it, and the others only mark
+    /// it as due, like the [`lazy_eviction`](#method.lazy_eviction). The read
+    /// operations never run it.
+    ///
+    /// An insert into a full cache still evicts an entry to make room, and an
+    /// insert into a cache over its max capacity runs the maintenance right away,
+    /// so the inserts never grow the cache beyond the bound.
+    ///
+    /// The interval must be at least 1.
+    pub fn maintenance_interval(self, operations: u64) -> Self {
+        Self {
+            settings: CacheSettings {
+                maintenance_interval: Some(operations),
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether the cache tracks the order in which the keys were inserted,
+    /// independent of the access order used for the eviction. The default is
+    /// `false`, which avoids the cost of the extra queue.
+    ///
+    /// The order can be read by [`insertion_order`][insertion-order-method].
+    ///
+    /// [insertion-order-method]: ./struct.Cache.html#method.insertion_order
+    pub fn track_insertion_order(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                track_insertion_order: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether the cache estimates the number of distinct keys inserted into
+    /// it, including the evicted ones. The default is `false`.
+    ///
+    /// The estimate is read by
+    /// [`estimated_distinct_keys`][estimated-distinct-keys-method]. Tracking it
+    /// takes 4 KiB and one more hash computation per insert.
+    ///
+    /// [estimated-distinct-keys-method]: ./struct.Cache.html#method.estimated_distinct_keys
+    pub fn track_cardinality(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                track_cardinality: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether the admission skips a victim whose entry is missing in the
+    /// hash map, instead of panicking. The default is `false`.
+    ///
+    /// Such an orphaned node in the internal queues indicates a bug in the cache.
+    /// When enabled, the admission drops the orphaned node and picks the next
+    /// victim, which hardens long-running processes against the internal drift.
+    /// [`check_invariants`][check-invariants-method] can be used to detect it.
+    ///
+    /// [check-invariants-method]: ./struct.Cache.html#method.check_invariants
+    pub fn skip_orphaned_victims(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                skip_orphaned_victims: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether updating the value of an existing key moves the entry to the
+    /// most recently used end, like a read. The default is `true`.
+    ///
+    /// When disabled, `insert` and the other write operations on an existing key
+    /// keep the position of the entry in the LRU order, so only the reads keep
+    /// the entry from being evicted. This suits e.g. a write-through cache, where
+    /// the writes do not indicate the popularity of the key.
+    pub fn update_refreshes_recency(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                update_refreshes_recency: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets whether the cache skips the nodes of its internal queues found in an
+    /// inconsistent state, instead of panicking. The default is `false`.
+    ///
+    /// Such a node, e.g. one tagged with a region it is not in, indicates a bug
+    /// in the cache. When enabled, the operation leaves the node as is and keeps
+    /// going, so an embedding service does not crash. This trades the crash for
+    /// a possible minor inconsistency, e.g. a node no longer reachable from its
+    /// entry, which [`check_invariants`][check-invariants-method] reports and
+    /// [`prune_orphans`][prune-orphans-method] drops.
+    ///
+    /// [check-invariants-method]: ./struct.Cache.html#method.check_invariants
+    /// [prune-orphans-method]: ./struct.Cache.html#method.prune_orphans
+    pub fn panic_safe_mode(self, enabled: bool) -> Self {
+        Self {
+            settings: CacheSettings {
+                panic_safe_mode: enabled,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the number of the latest read operations covered by
+    /// [`recent_hit_rate`][recent-hit-rate-method]. It must be at least 1. The
+    /// default is 1024.
+    ///
+    /// A smaller window reacts faster to a change of the workload, but the
+    /// estimate is noisier.
+    ///
+    /// [recent-hit-rate-method]: ./struct.Cache.html#method.recent_hit_rate
+    pub fn hit_rate_window(self, operations: usize) -> Self {
+        Self {
+            settings: CacheSettings {
+                hit_rate_window: operations,
+                ..self.settings
+            },
+            ..self
+        }
+    }
+
+    /// Sets the target load factor of the backing hash map, which must be in the
+    /// range `(0, 1)`.
+    ///
+    /// The hash map is allocated upfront with room for `max_capacity / factor`
+    /// entries, so that its load factor stays below the target as the cache
+    /// fills up. This trades memory for fewer rehashes during the warmup and fewer
+    /// collisions. It requires `max_capacity` to be set.
+    pub fn target_load_factor(self, factor: f64) -> Self {
+        Self {
+            target_load_factor: Some(factor),
+            ..self
+        }
+    }
+
+    /// Sets the estimator of the popularity of the keys, used by the TinyLFU
+    /// admission policy, replacing the built-in frequency sketch.
+    ///
+    /// This is meant for research and experimentation. The estimator is not
+    /// used under `EvictionPolicy::Clock`, nor by a cache without `max_capacity`.
+    pub fn frequency_estimator(self, estimator: impl FrequencyEstimator + 'static) -> Self {
+        Self {
+            frequency_estimator: Some(Box::new(estimator)),
+            ..self
+        }
+    }
+
+    /// Sets a listener to receive the evicted entries in batches.
+    ///
+    /// The listener is called once per operation that evicts entries, with all
+    /// the entries evicted by it, e.g. once per write operation, or once per
+    /// [`run_pending_maintenance`][run-pending-maintenance-method] after the
+    /// max capacity was lowered a lot. This amortizes the cost of the listener,
+    /// e.g. a single batched write to a backing store.
+    ///
+    /// The tombstones of negatively cached keys are not passed, as they have no
+    /// value. Neither are the entries explicitly removed by `invalidate` or
+    /// `remove`, nor the evicted entries already returned to the caller by
+    /// `insert_and_collect_evicted` or `on_memory_pressure`.
+    ///
+    /// The listener is called after the cache has been updated.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    /// use std::{cell::RefCell, rc::Rc};
+    ///
+    /// let evicted = Rc::new(RefCell::new(Vec::new()));
+    /// let sink = Rc::clone(&evicted);
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .batch_eviction_listener(move |batch| sink.borrow_mut().push(batch))
+    ///     .build();
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    ///
+    /// cache.set_max_capacity(0);
+    /// cache.run_pending_maintenance();
+    /// assert_eq!(*evicted.borrow(), vec![vec![('a', "alice"), ('b', "bob")]]);
+    /// ```
+    ///
+    /// [run-pending-maintenance-method]: ./struct.Cache.html#method.run_pending_maintenance
+    pub fn batch_eviction_listener(self, listener: impl Fn(Vec<(K, V)>) + 'static) -> Self
+    where
+        K: Clone + 'static,
+        V: 'static,
+    {
+        let listener = move |batch: Vec<(Rc<K>, V)>| {
+            listener(
+                batch
+                    .into_iter()
+                    .map(|(key, value)| (Rc::unwrap_or_clone(key), value))
+                    .collect(),
+            )
+        };
+        Self {
+            eviction_listener: Some(Rc::new(listener)),
+            ..self
+        }
+    }
+
+    /// Sets a function estimating the cost of the backend work avoided by a
+    /// cache hit on an entry, e.g. the size of the value in bytes or the latency
+    /// of the backend.
+    ///
+    /// The costs of the hits are tallied by the
+    /// [`cost_served`][cost-served-method] method of the `Cache`. The function
+    /// is called on every cache hit, so it should be cheap.
+    ///
+    /// [cost-served-method]: ./struct.Cache.html#method.cost_served
+    pub fn cost_fn(self, cost_fn: impl Fn(&K, &V) -> u64 + 'static) -> Self {
+        Self {
+            cost_fn: Some(Rc::new(cost_fn)),
+            ..self
+        }
+    }
 }
 
+/// The error returned by [`CacheBuilder::build_checked`][build-checked-method]
+/// when the builder has conflicting options.
+///
+/// [build-checked-method]: ./struct.CacheBuilder.html#method.build_checked
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// The `promotion_threshold` is zero.
+    ZeroPromotionThreshold,
+    /// A `promotion_threshold` was set with the CLOCK eviction policy, which does
+    /// not use the protected region.
+    PromotionThresholdWithClock,
+    /// A `promotion_threshold` was set for a cache without `max_capacity`, which
+    /// never evicts.
+    PromotionThresholdWithoutMaxCapacity,
+    /// The `protected_capacity` is greater than the `max_capacity`.
+    ProtectedCapacityExceedsMaxCapacity {
+        protected_capacity: u64,
+        max_capacity: u64,
+    },
+    /// The `target_load_factor` is not in the range `(0, 1)`.
+    InvalidTargetLoadFactor,
+    /// A `target_load_factor` was set for a cache without `max_capacity`, so the
+    /// size of the hash map to allocate is unknown.
+    TargetLoadFactorWithoutMaxCapacity,
+    /// The `frequency_sketch_activation` is not in the range `[0, 1]`.
+    InvalidFrequencySketchActivation,
+    /// A `frequency_sketch_activation` was set for a cache without
+    /// `max_capacity`, which does not use the frequency sketch.
+    FrequencySketchActivationWithoutMaxCapacity,
+    /// The frequency sketch is preallocated for a cache without `max_capacity`,
+    /// which does not use it.
+    FrequencySketchPreallocationWithoutMaxCapacity,
+    /// The hash map could not be allocated for the initial capacity, as the
+    /// capacity overflows or the allocator reported a failure.
+    AllocationFailed { capacity: usize },
+    /// The `hit_rate_window` is zero.
+    ZeroHitRateWindow,
+    /// The `maintenance_interval` is zero.
+    ZeroMaintenanceInterval,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroPromotionThreshold => write!(f, "promotion_threshold must be at least 1"),
+            Self::PromotionThresholdWithClock => write!(
+                f,
+                "promotion_threshold cannot be used with the CLOCK eviction policy"
+            ),
+            Self::PromotionThresholdWithoutMaxCapacity => {
+                write!(f, "promotion_threshold requires max_capacity to be set")
+            }
+            Self::ProtectedCapacityExceedsMaxCapacity {
+                protected_capacity,
+                max_capacity,
+            } => write!(
+                f,
+                "protected_capacity ({}) must not be greater than max_capacity ({})",
+                protected_capacity, max_capacity
+            ),
+            Self::InvalidTargetLoadFactor => {
+                write!(f, "target_load_factor must be in the range (0, 1)")
+            }
+            Self::TargetLoadFactorWithoutMaxCapacity => {
+                write!(f, "target_load_factor requires max_capacity to be set")
+            }
+            Self::InvalidFrequencySketchActivation => {
+                write!(f, "frequency_sketch_activation must be in the range [0, 1]")
+            }
+            Self::FrequencySketchActivationWithoutMaxCapacity => write!(
+                f,
+                "frequency_sketch_activation requires max_capacity to be set"
+            ),
+            Self::FrequencySketchPreallocationWithoutMaxCapacity => write!(
+                f,
+                "preallocate_frequency_sketch requires max_capacity to be set"
+            ),
+            Self::AllocationFailed { capacity } => write!(
+                f,
+                "failed to allocate the hash map for {} entries",
+                capacity
+            ),
+            Self::ZeroHitRateWindow => write!(f, "hit_rate_window must be at least 1"),
+            Self::ZeroMaintenanceInterval => {
+                write!(f, "maintenance_interval must be at least 1")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
 #[cfg(test)]
 mod tests {
-    use super::CacheBuilder;
+    use super::{BuildError, CacheBuilder};
+    use crate::{EvictionPolicy, InsertPolicy};
 
     #[test]
     fn build_cache() {
@@ -104,8 +784,258 @@ mod tests {
         let policy = cache.policy();
 
         assert_eq!(policy.max_capacity(), Some(100));
+        assert_eq!(policy.insert_policy(), InsertPolicy::EvictToFit);
+        assert_eq!(policy.eviction_policy(), EvictionPolicy::TinyLfu);
+        assert_eq!(policy.promotion_threshold(), None);
+        assert_eq!(policy.expected_key_cardinality(), None);
+        assert_eq!(policy.frequency_sketch_activation(), 0.5);
+        assert!(policy.negative_caching());
+        assert!(!policy.lazy_eviction());
+        assert!(!policy.track_insertion_order());
+        assert_eq!(policy.hit_rate_window(), 1024);
+        assert!(policy.update_refreshes_recency());
+        assert_eq!(policy.protected_capacity(), None);
+        assert_eq!(policy.maintenance_interval(), 1);
+        assert_eq!(policy.eviction_batch_size(), 100);
 
         cache.insert('a', "Alice".to_string());
         assert_eq!(cache.get(&'a'), Some(&"Alice".to_string()));
     }
+
+    #[test]
+    fn build_cache_with_insert_policy() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .build();
+        assert_eq!(cache.policy().insert_policy(), InsertPolicy::RejectWhenFull);
+    }
+
+    #[test]
+    fn build_cache_with_eviction_policy() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .eviction_policy(EvictionPolicy::Clock)
+            .build();
+        assert_eq!(cache.policy().eviction_policy(), EvictionPolicy::Clock);
+    }
+
+    #[test]
+    fn build_cache_without_negative_caching() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .negative_caching(false)
+            .build();
+        assert!(!cache.policy().negative_caching());
+    }
+
+    #[test]
+    fn build_cache_with_lazy_eviction() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .lazy_eviction(true)
+            .build();
+        assert!(cache.policy().lazy_eviction());
+    }
+
+    #[test]
+    fn build_cache_with_promotion_threshold() {
+        let cache = CacheBuilder::<char, String, _>::new(100).build();
+        assert_eq!(cache.policy().promotion_threshold(), None);
+
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .promotion_threshold(3)
+            .build();
+        assert_eq!(cache.policy().promotion_threshold(), Some(3));
+    }
+
+    #[test]
+    fn build_cache_with_all_options() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .promotion_threshold(3)
+            .expected_key_cardinality(1000)
+            .frequency_sketch_activation(0.25)
+            .negative_caching(false)
+            .lazy_eviction(true)
+            .track_insertion_order(true)
+            .hit_rate_window(64)
+            .update_refreshes_recency(false)
+            .protected_capacity(50)
+            .maintenance_interval(8)
+            .build();
+        let policy = cache.policy();
+
+        assert_eq!(policy.max_capacity(), Some(100));
+        assert_eq!(policy.insert_policy(), InsertPolicy::RejectWhenFull);
+        assert_eq!(policy.eviction_policy(), EvictionPolicy::TinyLfu);
+        assert_eq!(policy.promotion_threshold(), Some(3));
+        assert_eq!(policy.expected_key_cardinality(), Some(1000));
+        assert_eq!(policy.frequency_sketch_activation(), 0.25);
+        assert!(!policy.negative_caching());
+        assert!(policy.lazy_eviction());
+        assert!(policy.track_insertion_order());
+        assert_eq!(policy.hit_rate_window(), 64);
+        assert!(!policy.update_refreshes_recency());
+        assert_eq!(policy.protected_capacity(), Some(50));
+        assert_eq!(policy.maintenance_interval(), 8);
+        assert_eq!(policy.eviction_batch_size(), 100);
+    }
+
+    #[test]
+    fn build_cache_with_preallocated_frequency_sketch() {
+        let cache = CacheBuilder::<char, String, _>::new(100).build();
+        assert!(!cache.is_frequency_sketch_enabled());
+
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .preallocate_frequency_sketch()
+            .build();
+        assert!(cache.is_frequency_sketch_enabled());
+
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .preallocate_frequency_sketch()
+            .build_with_hasher(std::collections::hash_map::RandomState::default());
+        assert!(cache.is_frequency_sketch_enabled());
+
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .eviction_policy(EvictionPolicy::Clock)
+            .preallocate_frequency_sketch()
+            .build();
+        assert!(!cache.is_frequency_sketch_enabled());
+    }
+
+    #[test]
+    fn build_checked() {
+        let cache = CacheBuilder::<char, String, _>::new(100)
+            .initial_capacity(100)
+            .build_checked();
+        assert!(cache.is_ok());
+
+        // An initial capacity over the max capacity, and the policies of a
+        // cache that never becomes full, are harmless.
+        let cache = CacheBuilder::<char, String, _>::new(10)
+            .initial_capacity(11)
+            .build_checked();
+        assert!(cache.is_ok());
+
+        let cache = CacheBuilder::<char, String, _>::default()
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .eviction_policy(EvictionPolicy::Clock)
+            .build_checked();
+        assert!(cache.is_ok());
+
+        let result = CacheBuilder::<char, String, _>::new(10)
+            .promotion_threshold(0)
+            .build_checked();
+        assert_eq!(result.err(), Some(BuildError::ZeroPromotionThreshold));
+
+        let result = CacheBuilder::<char, String, _>::new(10)
+            .eviction_policy(EvictionPolicy::Clock)
+            .promotion_threshold(2)
+            .build_checked();
+        assert_eq!(result.err(), Some(BuildError::PromotionThresholdWithClock));
+
+        let result = CacheBuilder::<char, String, _>::default()
+            .promotion_threshold(2)
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::PromotionThresholdWithoutMaxCapacity)
+        );
+
+        let result = CacheBuilder::<char, String, _>::new(10)
+            .promotion_threshold(2)
+            .protected_capacity(11)
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::ProtectedCapacityExceedsMaxCapacity {
+                protected_capacity: 11,
+                max_capacity: 10
+            })
+        );
+
+        for factor in [0.0, 1.0, -0.5, 2.0, f64::NAN] {
+            let result = CacheBuilder::<char, String, _>::new(10)
+                .target_load_factor(factor)
+                .build_checked();
+            assert_eq!(result.err(), Some(BuildError::InvalidTargetLoadFactor));
+        }
+
+        let result = CacheBuilder::<char, String, _>::default()
+            .target_load_factor(0.5)
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::TargetLoadFactorWithoutMaxCapacity)
+        );
+
+        for fraction in [-0.5, 1.5, f64::NAN] {
+            let result = CacheBuilder::<char, String, _>::new(10)
+                .frequency_sketch_activation(fraction)
+                .build_checked();
+            assert_eq!(
+                result.err(),
+                Some(BuildError::InvalidFrequencySketchActivation)
+            );
+        }
+
+        let result = CacheBuilder::<char, String, _>::default()
+            .frequency_sketch_activation(0.5)
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::FrequencySketchActivationWithoutMaxCapacity)
+        );
+
+        let result = CacheBuilder::<char, String, _>::default()
+            .preallocate_frequency_sketch()
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::FrequencySketchPreallocationWithoutMaxCapacity)
+        );
+
+        let result = CacheBuilder::<char, String, _>::new(10)
+            .hit_rate_window(0)
+            .build_checked();
+        assert_eq!(result.err(), Some(BuildError::ZeroHitRateWindow));
+
+        let result = CacheBuilder::<char, String, _>::new(10)
+            .maintenance_interval(0)
+            .build_checked();
+        assert_eq!(result.err(), Some(BuildError::ZeroMaintenanceInterval));
+
+        // The capacity overflows, so the allocation fails without reaching the
+        // allocator.
+        let result = CacheBuilder::<char, String, _>::default()
+            .initial_capacity(usize::MAX)
+            .build_checked();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::AllocationFailed {
+                capacity: usize::MAX
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "promotion_threshold must be at least 1")]
+    fn build_panics_on_invalid_options() {
+        let _cache = CacheBuilder::<char, String, _>::new(10)
+            .promotion_threshold(0)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "promotion_threshold requires max_capacity to be set")]
+    fn build_with_hasher_panics_on_invalid_options() {
+        let _cache = CacheBuilder::<char, String, _>::default()
+            .promotion_threshold(2)
+            .build_with_hasher(std::collections::hash_map::RandomState::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to allocate the hash map for")]
+    fn build_with_hasher_panics_on_allocation_failure() {
+        let _cache = CacheBuilder::<char, String, _>::default()
+            .initial_capacity(usize::MAX)
+            .build_with_hasher(std::collections::hash_map::RandomState::default());
+    }
 }
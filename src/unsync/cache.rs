@@ -1,12 +1,21 @@
-use super::{deques::Deques, CacheBuilder, Iter, KeyHashDate, ValueEntry};
+use super::{
+    deques::Deques, CacheBuilder, Cursor, FrozenCache, Iter, KeyHashDate, ValueEntry, ValueRef,
+};
 use crate::{
-    common::{self, deque::DeqNode, frequency_sketch::FrequencySketch, CacheRegion},
-    Policy,
+    common::{
+        cardinality::CardinalityEstimator,
+        deque::{DeqNode, Deque},
+        frequency_sketch::Estimator,
+        hit_rate::HitRateWindow,
+        CacheRegion,
+    },
+    policy::CacheSettings,
+    EntryRegion, EvictionPolicy, FrequencyEstimator, InsertPolicy, Policy,
 };
 
 use std::{
     borrow::Borrow,
-    collections::{hash_map::RandomState, HashMap},
+    collections::{hash_map::RandomState, BTreeMap, HashMap, TryReserveError, VecDeque},
     fmt,
     hash::{BuildHasher, Hash},
     ptr::NonNull,
@@ -14,9 +23,16 @@ use std::{
 };
 
 const EVICTION_BATCH_SIZE: usize = 100;
+const ITER_FROM_BATCH_SIZE: usize = 100;
 
 type CacheStore<K, V, S> = std::collections::HashMap<Rc<K>, ValueEntry<K, V>, S>;
 
+// Receives the entries evicted by an operation in a single batch.
+pub(crate) type EvictionListener<K, V> = Rc<dyn Fn(Vec<(Rc<K>, V)>)>;
+
+// Estimates the cost of the backend work avoided by a cache hit.
+pub(crate) type CostFn<K, V> = Rc<dyn Fn(&K, &V) -> u64>;
+
 /// An in-memory cache that is _not_ thread-safe.
 ///
 /// `Cache` utilizes a hash table [`std::collections::HashMap`][std-hashmap] from the
@@ -71,6 +87,21 @@ type CacheStore<K, V, S> = std::collections::HashMap<Rc<K>, ValueEntry<K, V>, S>
 /// }
 /// ```
 ///
+/// # Reading without maintenance
+///
+/// The read operations like `get` take `&mut self`, as they record the access
+/// in the LRU order and the historic popularity estimator, and may evict the
+/// entries over the max capacity. The following methods take `&self` instead.
+/// They do no bookkeeping at all, so they can be called through a shared
+/// reference:
+///
+/// - [`peek`](#method.peek) and [`contains_key_ref`](#method.contains_key_ref)
+///   to look up a key.
+/// - [`contains_all`](#method.contains_all) and
+///   [`contains_any`](#method.contains_any) to look up many keys.
+/// - [`iter`](#method.iter) to visit the entries.
+/// - [`entry_count`](#method.entry_count) to get the number of entries.
+///
 /// # Hashing Algorithm
 ///
 /// By default, `Cache` uses a hashing algorithm selected to provide resistance
@@ -91,13 +122,23 @@ type CacheStore<K, V, S> = std::collections::HashMap<Rc<K>, ValueEntry<K, V>, S>
 /// [ahash-crate]: https://crates.io/crates/ahash
 ///
 pub struct Cache<K, V, S = RandomState> {
-    max_capacity: Option<u64>,
+    settings: CacheSettings,
+    maintenance_due: bool,
+    ops_since_maintenance: u64,
     entry_count: u64,
+    version: u64,
+    total_evicted: u64,
+    total_rejected: u64,
+    total_cost_served: u64,
     cache: CacheStore<K, V, S>,
     build_hasher: S,
     deques: Deques<K>,
-    frequency_sketch: FrequencySketch,
+    frequency_sketch: Estimator,
     frequency_sketch_enabled: bool,
+    hit_rate: HitRateWindow,
+    distinct_keys: Option<CardinalityEstimator>,
+    eviction_listener: Option<EvictionListener<K, V>>,
+    cost_fn: Option<CostFn<K, V>>,
 }
 
 impl<K, V, S> fmt::Debug for Cache<K, V, S>
@@ -130,7 +171,11 @@ where
     /// [builder-struct]: ./struct.CacheBuilder.html
     pub fn new(max_capacity: u64) -> Self {
         let build_hasher = RandomState::default();
-        Self::with_everything(Some(max_capacity), None, build_hasher)
+        let settings = CacheSettings {
+            max_capacity: Some(max_capacity),
+            ..Default::default()
+        };
+        Self::with_everything(settings, None, None, None, None, build_hasher)
     }
 
     /// Returns a [`CacheBuilder`][builder-struct], which can builds a `Cache` with
@@ -142,20 +187,65 @@ where
     }
 }
 
+impl<K> Cache<K, (), RandomState>
+where
+    K: Clone + Hash + Eq,
+{
+    /// Replays an access trace on a fresh cache with the given max capacity and
+    /// eviction policy, and returns the ratio of the hits, in the range `[0, 1]`.
+    ///
+    /// Each key in the trace is read with `get` and, on a miss, inserted with a
+    /// dummy value. This is a harness to compare the eviction policies on a
+    /// recorded workload. Returns `1.0` for an empty trace.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::{unsync::Cache, EvictionPolicy};
+    ///
+    /// let trace = [1, 2, 1, 2, 3, 1];
+    /// let hit_rate = Cache::hit_rate_for_trace(10, EvictionPolicy::TinyLfu, &trace);
+    /// assert_eq!(hit_rate, 0.5);
+    /// ```
+    ///
+    pub fn hit_rate_for_trace(max_capacity: u64, policy: EvictionPolicy, trace: &[K]) -> f64 {
+        if trace.is_empty() {
+            return 1.0;
+        }
+
+        let mut cache = Cache::builder()
+            .max_capacity(max_capacity)
+            .eviction_policy(policy)
+            .build();
+        let mut hits = 0;
+        for key in trace {
+            if cache.get(key).is_some() {
+                hits += 1;
+            } else {
+                cache.insert(key.clone(), ());
+            }
+        }
+        hits as f64 / trace.len() as f64
+    }
+}
+
 //
 // public
 //
 impl<K, V, S> Cache<K, V, S> {
     /// Returns a read-only cache policy of this cache.
     ///
-    /// At this time, cache policy cannot be modified after cache creation.
-    /// A future version may support to modify it.
+    /// At this time, only the max capacity can be modified after cache creation,
+    /// by the [`set_max_capacity`](#method.set_max_capacity) method.
     pub fn policy(&self) -> Policy {
-        Policy::new(self.max_capacity)
+        Policy::new(self.settings.clone(), EVICTION_BATCH_SIZE)
     }
 
     /// Returns the number of entries in this cache.
     ///
+    /// The tombstones of negatively cached keys (see
+    /// [`insert_absent`](#method.insert_absent)) are counted as entries.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -177,12 +267,362 @@ impl<K, V, S> Cache<K, V, S> {
         self.entry_count
     }
 
+    /// Returns the number of entries in the internal hash map, counted directly
+    /// rather than read from the bookkeeping counter behind `entry_count`.
+    ///
+    /// Like `entry_count`, this includes the entries over the max capacity that
+    /// are not evicted yet, and the tombstones of negatively cached keys. The two
+    /// counts are always equal unless the bookkeeping has a bug, so this is
+    /// useful as a cross-check.
+    pub fn live_entry_count(&self) -> usize {
+        self.cache.len()
+    }
+
     /// Returns the total weighted size of entries in this cache.
     ///
     /// This is equivalent to `entry_count` as weight support has been removed.
     pub fn weighted_size(&self) -> u64 {
         self.entry_count
     }
+
+    /// Returns the entry count and the weighted size of this cache together, as
+    /// a coherent snapshot.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    ///
+    /// let size = cache.size_info();
+    /// assert_eq!(size.entry_count(), 1);
+    /// assert_eq!(size.weighted_size(), 1);
+    /// ```
+    ///
+    pub fn size_info(&self) -> SizeInfo {
+        SizeInfo {
+            entry_count: self.entry_count(),
+            weighted_size: self.weighted_size(),
+        }
+    }
+
+    /// Returns the remaining capacity before the cache starts evicting entries,
+    /// i.e. the max capacity minus the weighted size, or `None` if the cache is
+    /// unbounded.
+    ///
+    /// This is useful to decide how many entries a batch of inserts can add
+    /// without evicting any. It returns zero when the cache holds more entries
+    /// than its max capacity, e.g. after the capacity was lowered.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// assert_eq!(cache.capacity_remaining(), Some(9));
+    /// ```
+    ///
+    pub fn capacity_remaining(&self) -> Option<u64> {
+        self.settings
+            .max_capacity
+            .map(|max| max.saturating_sub(self.weighted_size()))
+    }
+
+    /// Returns `true` if the weighted size of the cache exceeds its max
+    /// capacity, or `false` if the cache is unbounded.
+    ///
+    /// The cache can be over its max capacity between maintenance passes, e.g.
+    /// after the capacity was lowered by `set_max_capacity`, as the write
+    /// operations evict the excess entries in batches, and the reads defer the
+    /// eviction when lazy eviction is enabled. A scheduler can use this to decide
+    /// when to call [`run_pending_maintenance`](#method.run_pending_maintenance).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// assert!(!cache.is_over_capacity());
+    ///
+    /// cache.set_max_capacity(1);
+    /// assert!(cache.is_over_capacity());
+    ///
+    /// cache.run_pending_maintenance();
+    /// assert!(!cache.is_over_capacity());
+    /// ```
+    ///
+    pub fn is_over_capacity(&self) -> bool {
+        self.settings
+            .max_capacity
+            .is_some_and(|max| self.weighted_size() > max)
+    }
+
+    /// Returns the version of the contents of this cache.
+    ///
+    /// The version is a counter increased by every operation that changes the
+    /// contents: inserting or updating an entry, and removing one by
+    /// invalidation or eviction. Comparing two versions tells cheaply whether
+    /// anything changed in between, without diffing the contents. Reads and
+    /// rejected inserts do not change the version.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// let v0 = cache.version();
+    ///
+    /// cache.insert('a', "alice");
+    /// let v1 = cache.version();
+    /// assert_ne!(v0, v1);
+    ///
+    /// cache.get(&'a');
+    /// assert_eq!(cache.version(), v1);
+    /// ```
+    ///
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns `true` if the historic popularity estimator (frequency sketch) is
+    /// enabled.
+    ///
+    /// The estimator is enabled when the cache is filled to half of its max
+    /// capacity (see the [`frequency_sketch_activation`][activation-method]
+    /// method of the `CacheBuilder`), or when the cache is built if it is
+    /// [preallocated][preallocate-method]. It is never enabled for an unbounded
+    /// cache or under `EvictionPolicy::Clock`.
+    ///
+    /// [activation-method]: ./struct.CacheBuilder.html#method.frequency_sketch_activation
+    /// [preallocate-method]: ./struct.CacheBuilder.html#method.preallocate_frequency_sketch
+    pub fn is_frequency_sketch_enabled(&self) -> bool {
+        self.frequency_sketch_enabled
+    }
+
+    /// Returns the fraction of the counters of the historic popularity estimator
+    /// (frequency sketch) at their maximum value, in the range `[0, 1]`.
+    ///
+    /// The 4-bit counters saturate at 15. The sketch periodically halves them,
+    /// but a high saturation means it can no longer tell the popular keys apart,
+    /// e.g. when the sketch is undersized for the key space. Then consider
+    /// setting a larger [`expected_key_cardinality`][cardinality-method].
+    ///
+    /// This scans the whole sketch, so it is intended for monitoring rather than
+    /// hot paths. Returns `0.0` if the sketch is not enabled, or if it is replaced
+    /// by a custom [`frequency_estimator`][estimator-method].
+    ///
+    /// [cardinality-method]: ./struct.CacheBuilder.html#method.expected_key_cardinality
+    /// [estimator-method]: ./struct.CacheBuilder.html#method.frequency_estimator
+    pub fn frequency_sketch_saturation(&self) -> f64 {
+        self.frequency_sketch.saturation().unwrap_or_default()
+    }
+
+    /// Returns the number of entries evicted from this cache since its creation.
+    ///
+    /// This counts the entries evicted to make room for new entries or to bring
+    /// the cache back within its max capacity, including the evictions requested
+    /// by methods like `evict_n` and `trim_to`. Invalidated and removed entries
+    /// are not counted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    ///
+    /// cache.evict_n(1);
+    /// cache.invalidate(&'b');
+    /// assert_eq!(cache.total_evicted(), 1);
+    /// ```
+    ///
+    pub fn total_evicted(&self) -> u64 {
+        self.total_evicted
+    }
+
+    /// Returns the number of new entries rejected by this cache since its
+    /// creation, either by the admission policy, by
+    /// `InsertPolicy::RejectWhenFull`, or because they were too large to fit.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(1);
+    /// cache.insert('a', "alice");
+    /// cache.get(&'a');
+    ///
+    /// // 'b' is less popular than 'a', so it is rejected.
+    /// cache.insert('b', "bob");
+    /// assert_eq!(cache.total_rejected(), 1);
+    /// ```
+    ///
+    pub fn total_rejected(&self) -> u64 {
+        self.total_rejected
+    }
+
+    /// Returns the ratio of the hits among the latest read operations, in the
+    /// range `[0, 1]`, or `1.0` if nothing has been read yet.
+    ///
+    /// The read operations are the ones recording an access, e.g. `get` and
+    /// `get_state`. A negative hit on a tombstone counts as a hit. The number of
+    /// the operations covered is set by
+    /// [`hit_rate_window`][hit-rate-window-method] (1024 by default). Unlike a
+    /// lifetime ratio, this surfaces a sudden drop, e.g. after a workload shift.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.get(&'a');
+    /// cache.get(&'b');
+    /// assert_eq!(cache.recent_hit_rate(), 0.5);
+    /// ```
+    ///
+    /// [hit-rate-window-method]: ./struct.CacheBuilder.html#method.hit_rate_window
+    pub fn recent_hit_rate(&self) -> f64 {
+        self.hit_rate.hit_rate()
+    }
+
+    /// Returns the total cost of the values served by the cache hits since the
+    /// creation of this cache, or `0` if the cache was not built with a
+    /// [`cost_fn`][cost-fn-method].
+    ///
+    /// Each read operation returning a value, e.g. `get` and `get_state`, adds
+    /// the cost of the entry. So this tallies the backend work avoided by the
+    /// cache. Negative hits on tombstones cost nothing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .cost_fn(|_k, v: &String| v.len() as u64)
+    ///     .build();
+    /// cache.insert('a', "alice".to_string());
+    /// cache.get(&'a');
+    /// cache.get(&'a');
+    /// cache.get(&'b');
+    /// assert_eq!(cache.cost_served(), 10);
+    /// ```
+    ///
+    /// [cost-fn-method]: ./struct.CacheBuilder.html#method.cost_fn
+    pub fn cost_served(&self) -> u64 {
+        self.total_cost_served
+    }
+
+    /// Returns the estimated number of distinct keys inserted so far, including
+    /// the ones evicted or rejected since then, or `0` if the cache was not built
+    /// with [`track_cardinality`][track-cardinality-method] enabled.
+    ///
+    /// The estimate is computed by a HyperLogLog estimator fed by every insert
+    /// operation, and has a standard error of about 1.6%. Comparing it with the
+    /// max capacity tells whether the cache is undersized for the working set.
+    /// [`rehash`](#method.rehash) resets it to the keys in the cache, as the
+    /// hashes change.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .track_cardinality(true)
+    ///     .build();
+    /// for i in 0..100 {
+    ///     cache.insert(i % 20, i);
+    /// }
+    /// let estimate = cache.estimated_distinct_keys();
+    /// assert!((16..=24).contains(&estimate));
+    /// ```
+    ///
+    /// [track-cardinality-method]: ./struct.CacheBuilder.html#method.track_cardinality
+    pub fn estimated_distinct_keys(&self) -> u64 {
+        self.distinct_keys
+            .as_ref()
+            .map_or(0, CardinalityEstimator::estimate)
+    }
+
+    /// Resets the statistics of this cache: the counts of the evicted and
+    /// rejected entries, the recent hit rate, the cost served and the estimated
+    /// number of distinct keys.
+    ///
+    /// The cached entries and the historic popularity estimator are not affected.
+    /// This is useful to measure a specific phase of a workload, e.g. after
+    /// warming up the cache.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(1);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'c');
+    ///
+    /// cache.reset_stats();
+    /// assert_eq!(cache.total_evicted(), 0);
+    /// assert_eq!(cache.recent_hit_rate(), 1.0);
+    /// assert_eq!(cache.entry_count(), 1);
+    /// ```
+    ///
+    pub fn reset_stats(&mut self) {
+        self.total_evicted = 0;
+        self.total_rejected = 0;
+        self.total_cost_served = 0;
+        self.hit_rate.reset();
+        if let Some(estimator) = &mut self.distinct_keys {
+            *estimator = CardinalityEstimator::default();
+        }
+    }
+
+    /// Returns a summary of the counts and the capacity of this cache.
+    ///
+    /// Unlike the `Debug` implementation of the cache, which lists the entries,
+    /// formatting the summary does not iterate the entries nor allocate, and does
+    /// not require `K: Debug` or `V: Debug`. This is useful for logging in hot
+    /// paths.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    ///
+    /// assert_eq!(
+    ///     cache.summary().to_string(),
+    ///     "entries: 1/10, evicted: 0, rejected: 0"
+    /// );
+    /// ```
+    ///
+    pub fn summary(&self) -> CacheSummary {
+        CacheSummary {
+            entry_count: self.entry_count,
+            max_capacity: self.settings.max_capacity,
+            total_evicted: self.total_evicted,
+            total_rejected: self.total_rejected,
+        }
+    }
 }
 
 impl<K, V, S> Cache<K, V, S>
@@ -191,8 +631,11 @@ where
     S: BuildHasher + Clone,
 {
     pub(crate) fn with_everything(
-        max_capacity: Option<u64>,
+        settings: CacheSettings,
         initial_capacity: Option<usize>,
+        frequency_estimator: Option<Box<dyn FrequencyEstimator>>,
+        eviction_listener: Option<EvictionListener<K, V>>,
+        cost_fn: Option<CostFn<K, V>>,
         build_hasher: S,
     ) -> Self {
         let cache = HashMap::with_capacity_and_hasher(
@@ -200,14 +643,53 @@ where
             build_hasher.clone(),
         );
 
+        let panic_safe = settings.panic_safe_mode;
+        let hit_rate = HitRateWindow::new(settings.hit_rate_window);
+        let distinct_keys = settings
+            .track_cardinality
+            .then(CardinalityEstimator::default);
+
         Self {
-            max_capacity,
+            settings,
+            maintenance_due: false,
+            ops_since_maintenance: 0,
             entry_count: 0,
+            version: 0,
+            total_evicted: 0,
+            total_rejected: 0,
+            total_cost_served: 0,
             cache,
             build_hasher,
-            deques: Default::default(),
-            frequency_sketch: Default::default(),
+            deques: Deques {
+                panic_safe,
+                ..Default::default()
+            },
+            frequency_sketch: frequency_estimator.map_or_else(Default::default, Estimator::Custom),
             frequency_sketch_enabled: false,
+            hit_rate,
+            distinct_keys,
+            eviction_listener,
+            cost_fn,
+        }
+    }
+
+    /// Sets the max capacity of the cache.
+    ///
+    /// When the capacity is lowered below the current number of entries, the
+    /// excess entries are evicted from the LRU end by the following write
+    /// operations (`insert`, `invalidate`, `remove`), in batches.
+    ///
+    /// When the capacity is raised and the historic popularity estimator is
+    /// already enabled, the estimator is grown to match the new capacity. Growing
+    /// it forgets all the recorded popularity.
+    pub fn set_max_capacity(&mut self, max_capacity: u64) {
+        self.settings.max_capacity = Some(max_capacity);
+
+        if self.frequency_sketch_enabled {
+            // `ensure_capacity` is a no-op unless the sketch needs to grow.
+            self.do_enable_frequency_sketch(max_capacity);
+        } else if self.should_enable_frequency_sketch() {
+            self.enable_frequency_sketch();
         }
     }
 
@@ -223,724 +705,6853 @@ where
         Rc<K>: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.cache.contains_key(key)
+        self.has_value(key)
     }
 
-    /// Returns an immutable reference of the value corresponding to the key.
+    /// Returns `true` if the cache contains a value for the key, through a shared
+    /// reference.
+    ///
+    /// This works like the `contains_key` method. See
+    /// [reading without maintenance](#reading-without-maintenance).
     ///
     /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
     /// on the borrowed form _must_ match those for the key type.
-    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    pub fn contains_key_ref<Q>(&self, key: &Q) -> bool
     where
         Rc<K>: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.frequency_sketch.increment(self.hash(key));
-
-        if let Some(entry) = self.cache.get_mut(key) {
-            Self::record_hit(&mut self.deques, entry);
-            Some(&entry.value)
-        } else {
-            None
-        }
+        self.has_value(key)
     }
 
-    /// Inserts a key-value pair into the cache.
+    /// Returns an immutable reference of the value corresponding to the key,
+    /// through a shared reference.
     ///
-    /// If the cache has this key present, the value is updated.
-    pub fn insert(&mut self, key: K, value: V) {
-        self.evict_lru_entries();
-        let policy_weight = 1;
-        let key = Rc::new(key);
-        let entry = ValueEntry::new(value);
-
-        if let Some(old_entry) = self.cache.insert(Rc::clone(&key), entry) {
-            self.handle_update(key, policy_weight, old_entry);
-        } else {
-            let hash = self.hash(&key);
-            self.handle_insert(key, hash, policy_weight);
-        }
-    }
-
-    /// Discards any cached value for the key.
+    /// Unlike the `get` method, this method is not considered a cache read
+    /// operation, so it does not update the historic popularity estimator nor
+    /// the LRU order. Unlike `get_quiet`, it never evicts the entries over the
+    /// max capacity. See [reading without maintenance](#reading-without-maintenance).
     ///
     /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
     /// on the borrowed form _must_ match those for the key type.
-    pub fn invalidate<Q>(&mut self, key: &Q)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// fn describe(cache: &Cache<char, &str>, key: char) -> String {
+    ///     cache.peek(&key).map_or("unknown".into(), |name| name.to_string())
+    /// }
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// assert_eq!(describe(&cache, 'a'), "alice");
+    /// assert_eq!(describe(&cache, 'b'), "unknown");
+    /// ```
+    ///
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
     where
         Rc<K>: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.evict_lru_entries();
-
-        if let Some(mut entry) = self.cache.remove(key) {
-            self.deques.unlink_ao(&mut entry);
-            self.entry_count -= 1;
-        }
+        self.cache.get(key).and_then(|entry| entry.value.as_ref())
     }
 
-    /// Discards any cached value for the key, returning the cached value.
+    /// Returns `true` if the cache contains values for all of the keys. Returns
+    /// early on the first missing key.
     ///
-    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
-    /// on the borrowed form _must_ match those for the key type.
-    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    /// Like the `contains_key` method, this method is not considered a cache read
+    /// operation. A negatively cached key is treated as missing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 1);
+    /// cache.insert('b', 2);
+    ///
+    /// assert!(cache.contains_all(&[&'a', &'b']));
+    /// assert!(!cache.contains_all(&[&'a', &'c']));
+    /// ```
+    ///
+    pub fn contains_all<Q>(&self, keys: &[&Q]) -> bool
     where
         Rc<K>: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.evict_lru_entries();
-
-        if let Some(mut entry) = self.cache.remove(key) {
-            self.deques.unlink_ao(&mut entry);
-            self.entry_count -= 1;
-            Some(entry.value)
-        } else {
-            None
-        }
+        keys.iter().all(|key| self.has_value(*key))
     }
 
-    /// Discards all cached values.
+    /// Returns `true` if the cache contains a value for any of the keys. Returns
+    /// early on the first present key.
     ///
-    /// Like the `invalidate` method, this method does not clear the historic
-    /// popularity estimator of keys so that it retains the client activities of
-    /// trying to retrieve an item.
-    pub fn invalidate_all(&mut self) {
-        // Phase 1: swap out the cache before resetting internal state so that
-        // a panic in V::drop leaves `self` in a consistent (empty) state.
-        let old_capacity = self.cache.capacity();
-        let old_cache = std::mem::replace(
-            &mut self.cache,
-            HashMap::with_hasher(self.build_hasher.clone()),
-        );
-        self.deques.clear();
-        self.entry_count = 0;
-
-        // If V::drop panics, `self` is already in a valid empty state.
-        drop(old_cache);
-
-        // Phase 2: best effort capacity restoration for future inserts.
-        let _ = self.cache.try_reserve(old_capacity);
+    /// Like the `contains_key` method, this method is not considered a cache read
+    /// operation. A negatively cached key is treated as missing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 1);
+    ///
+    /// assert!(cache.contains_any(&[&'c', &'a']));
+    /// assert!(!cache.contains_any(&[&'b', &'c']));
+    /// ```
+    ///
+    pub fn contains_any<Q>(&self, keys: &[&Q]) -> bool
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        keys.iter().any(|key| self.has_value(*key))
     }
 
-    /// Discards cached values that satisfy a predicate.
+    /// Returns `true` if inserting a value for the key would be admitted to the
+    /// cache at this moment.
     ///
-    /// `invalidate_entries_if` takes a closure that returns `true` or `false`.
-    /// `invalidate_entries_if` will apply the closure to each cached value,
-    /// and if the closure returns `true`, the value will be invalidated.
+    /// This is a dry run of the admission policy used by `insert`: when the cache
+    /// is full, the estimated frequency of the key is compared with the one of the
+    /// LRU victim. Nothing is modified, so this method is not considered a cache
+    /// read operation. It always returns `true` when the cache has free space or
+    /// already contains the key, or the cache uses the
+    /// [`EvictionPolicy::Clock`][eviction-policy-enum], which admits every key.
     ///
-    /// Like the `invalidate` method, this method does not clear the historic
-    /// popularity estimator of keys so that it retains the client activities of
-    /// trying to retrieve an item.
-    // -----------------------------------------------------------------------
-    // (The followings are not doc comments)
-    // We need this #[allow(...)] to avoid a false Clippy warning about needless
-    // collect to create keys_to_invalidate.
-    // clippy 0.1.52 (9a1dfd2dc5c 2021-04-30) in Rust 1.52.0-beta.7
-    #[allow(clippy::needless_collect)]
-    pub fn invalidate_entries_if(&mut self, mut predicate: impl FnMut(&K, &V) -> bool) {
-        let Self { cache, deques, .. } = self;
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// [eviction-policy-enum]: ../enum.EvictionPolicy.html
+    pub fn would_admit<Q>(&self, key: &Q) -> bool
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.cache.contains_key(key) {
+            return true;
+        }
 
-        // Since we can't do cache.iter() and cache.remove() at the same time,
-        // invalidation needs to run in two steps:
-        // 1. Examine all entries in this cache and collect keys to invalidate.
-        // 2. Remove entries for the keys.
+        let policy_weight = 1;
+        if self.has_enough_capacity(policy_weight, self.entry_count) {
+            return true;
+        }
 
-        let keys_to_invalidate = cache
+        if let Some(max) = self.settings.max_capacity {
+            if policy_weight as u64 > max {
+                return false;
+            }
+        }
+
+        if self.settings.insert_policy == InsertPolicy::RejectWhenFull {
+            return false;
+        }
+
+        if self.settings.eviction_policy == EvictionPolicy::Clock {
+            return true;
+        }
+
+        let candidate_freq = self.frequency_sketch.frequency(self.hash(key));
+        matches!(
+            Self::admit(candidate_freq, &self.deques, &self.frequency_sketch),
+            AdmissionResult::Admitted { .. }
+        )
+    }
+
+    /// Previews what inserting a value for the key would do at this moment:
+    /// whether the key would be admitted, and the exact keys of the entries that
+    /// would be evicted.
+    ///
+    /// Like [`would_admit`](#method.would_admit), this is a dry run of `insert`
+    /// that modifies nothing. It runs the same steps against the current state:
+    /// the eviction of the entries over the max capacity (e.g. after lowering it
+    /// by `set_max_capacity`), then the admission of a new key, which evicts the
+    /// LRU victim if the key is admitted to a full cache. The victims are listed
+    /// in the order they would be evicted. The entries over the max capacity are
+    /// evicted even if the key is rejected.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(2);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    ///
+    /// // 'c' is not popular enough to be admitted to the full cache.
+    /// let preview = cache.preview_admission(&'c');
+    /// assert!(!preview.is_admitted());
+    /// assert!(preview.victims().is_empty());
+    ///
+    /// // 'c' is now more popular than the LRU entry 'a'.
+    /// cache.get(&'c');
+    /// cache.get(&'c');
+    /// let preview = cache.preview_admission(&'c');
+    /// assert!(preview.is_admitted());
+    /// assert_eq!(preview.victims(), &[&'a']);
+    /// ```
+    ///
+    pub fn preview_admission<Q>(&self, key: &Q) -> AdmissionPreview<'_, K>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let is_clock = self.settings.eviction_policy == EvictionPolicy::Clock;
+        // The entries in the order they are evicted, with their reference bits
+        // for the CLOCK policy. The entries demoted from the protected region are
+        // moved from its front to the back of the probation region, so the order
+        // is the same.
+        let mut queue = self
+            .deques
+            .probation
             .iter()
-            .filter(|(key, entry)| (predicate)(key, &entry.value))
-            .map(|(key, _)| Rc::clone(key))
-            .collect::<Vec<_>>();
+            .chain(self.deques.protected.iter())
+            .filter_map(|kh| {
+                self.cache
+                    .get::<Rc<K>>(&kh.key)
+                    .map(|entry| (kh, is_clock && entry.is_referenced()))
+            })
+            .collect::<VecDeque<_>>();
 
-        let mut invalidated = 0u64;
+        let mut victims = Vec::new();
+        for _ in 0..self.weights_to_evict().min(EVICTION_BATCH_SIZE as u64) {
+            match pop_victim(&mut queue) {
+                Some(kh) => victims.push(kh),
+                None => break,
+            }
+        }
 
-        keys_to_invalidate.into_iter().for_each(|k| {
-            if let Some(mut entry) = cache.remove(&k) {
-                let _weight = entry.policy_weight();
-                deques.unlink_ao(&mut entry);
-                invalidated += 1;
+        let policy_weight = 1;
+        let entry_count = self.entry_count - victims.len() as u64;
+        let is_present = self.cache.contains_key(key)
+            && !victims.iter().any(|kh| {
+                let victim_key: &Q = kh.key.borrow();
+                victim_key == key
+            });
+
+        let admitted = if is_present || self.has_enough_capacity(policy_weight, entry_count) {
+            true
+        } else if self
+            .settings
+            .max_capacity
+            .is_some_and(|max| policy_weight as u64 > max)
+            || self.settings.insert_policy == InsertPolicy::RejectWhenFull
+        {
+            false
+        } else {
+            let candidate_freq = self.frequency_sketch.frequency(self.hash(key));
+            match pop_victim(&mut queue) {
+                Some(kh)
+                    if is_clock || candidate_freq > self.frequency_sketch.frequency(kh.hash) =>
+                {
+                    victims.push(kh);
+                    true
+                }
+                _ => false,
             }
-        });
-        self.entry_count -= invalidated;
+        };
+
+        AdmissionPreview {
+            admitted,
+            victims: victims.into_iter().map(|kh| &*kh.key).collect(),
+        }
     }
 
-    /// Creates an iterator visiting all key-value pairs in arbitrary order. The
-    /// iterator element type is `(&K, &V)`.
+    /// Returns an immutable reference of the value corresponding to the key.
     ///
-    /// Unlike the `get` method, visiting entries via an iterator do not update the
-    /// historic popularity estimator or reset idle timers for keys.
+    /// Returns `None` for a negatively cached key. Use
+    /// [`get_state`](#method.get_state) to tell it from an unknown key.
     ///
-    /// # Examples
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.get_state(key) {
+            Lookup::Hit(value) => Some(value),
+            Lookup::NegativeHit | Lookup::Miss => None,
+        }
+    }
+
+    /// Returns mutable references to the values corresponding to the `N` keys,
+    /// or `None` if any of the keys has no value or if the keys are not distinct.
+    ///
+    /// This is the cache analog of `HashMap::get_many_mut`, to update several
+    /// cached values at once. Each key is looked up like the `get` method, so
+    /// the reads are recorded even if `None` is returned. Modifying a value
+    /// through a returned reference is not recorded as an update of the entry.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
     ///
     /// ```rust
     /// use micro_moka::unsync::Cache;
     ///
-    /// let mut cache = Cache::new(100);
-    /// cache.insert("Julia", 14);
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("alice", 100);
+    /// cache.insert("bob", 50);
     ///
-    /// let mut iter = cache.iter();
-    /// let (k, v) = iter.next().unwrap(); // (&K, &V)
-    /// assert_eq!(k, &"Julia");
-    /// assert_eq!(v, &14);
+    /// // Transfer 30 from alice to bob.
+    /// if let Some([alice, bob]) = cache.get_many_mut([&"alice", &"bob"]) {
+    ///     *alice -= 30;
+    ///     *bob += 30;
+    /// }
+    /// assert_eq!(cache.get(&"bob"), Some(&80));
     ///
-    /// assert!(iter.next().is_none());
+    /// assert!(cache.get_many_mut([&"alice", &"alice"]).is_none());
     /// ```
     ///
-    pub fn iter(&self) -> Iter<'_, K, V> {
-        Iter::new(self, self.cache.iter())
+    pub fn get_many_mut<Q, const N: usize>(&mut self, keys: [&Q; N]) -> Option<[&mut V; N]>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut all_hit = true;
+        for key in keys {
+            all_hit &= matches!(self.get_state(key), Lookup::Hit(_));
+        }
+        if !all_hit {
+            return None;
+        }
+
+        let mut entries = [std::ptr::null_mut::<ValueEntry<K, V>>(); N];
+        for (i, key) in keys.iter().enumerate() {
+            let entry: *mut _ = self.cache.get_mut(*key)?;
+            if entries[..i].contains(&entry) {
+                // Duplicate keys.
+                return None;
+            }
+            entries[i] = entry;
+        }
+
+        // The pointers are to distinct entries, and the hash map is not modified
+        // while the returned references borrow `self` mutably.
+        Some(entries.map(|entry| unsafe { (*entry).value.as_mut().unwrap() }))
     }
-}
 
-//
-// private
-//
-impl<K, V, S> Cache<K, V, S>
-where
-    K: Hash + Eq,
-    S: BuildHasher + Clone,
-{
-    #[inline]
-    fn hash<Q>(&self, key: &Q) -> u64
+    /// Looks up the key, telling apart a cached value, a negatively cached key
+    /// (see [`insert_absent`](#method.insert_absent)) and an unknown key.
+    ///
+    /// This is a cache read operation like the `get` method. A negative hit is
+    /// recorded as an access to the tombstone.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::{Cache, Lookup};
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert(1, "one");
+    /// cache.insert_absent(2);
+    ///
+    /// assert_eq!(cache.get_state(&1), Lookup::Hit(&"one"));
+    /// assert_eq!(cache.get_state(&2), Lookup::NegativeHit);
+    /// assert_eq!(cache.get_state(&3), Lookup::Miss);
+    /// ```
+    ///
+    pub fn get_state<Q>(&mut self, key: &Q) -> Lookup<&V>
     where
         Rc<K>: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.build_hasher.hash_one(key)
+        self.lookup(key, true)
+    }
+
+    /// Returns an immutable reference of the value corresponding to the key,
+    /// refreshing its recency but not its popularity.
+    ///
+    /// Unlike the `get` method, this method moves the entry to the back of the
+    /// LRU order without incrementing the historic popularity estimator. So the
+    /// entry is not evicted soon, but the read does not make it harder for other
+    /// keys to be admitted. Otherwise it is recorded like a `get`.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn get_touch_lru_only<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.lookup(key, false) {
+            Lookup::Hit(value) => Some(value),
+            Lookup::NegativeHit | Lookup::Miss => None,
+        }
+    }
+
+    fn lookup<Q>(&mut self, key: &Q, increment_frequency: bool) -> Lookup<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if increment_frequency {
+            self.frequency_sketch.increment(self.hash(key));
+        }
+
+        if let Some(entry) = self.cache.get_mut(key) {
+            // A negative hit is a hit, as the absence was cached.
+            self.hit_rate.record(true);
+            Self::record_hit(
+                self.settings.eviction_policy,
+                self.settings.promotion_threshold,
+                &mut self.deques,
+                entry,
+            );
+            entry.force();
+            if entry.value.is_some() {
+                entry.increment_access_count();
+            }
+            if let (Some(cost_fn), Some(value), Some(node)) =
+                (&self.cost_fn, &entry.value, entry.access_order_q_node())
+            {
+                // The node in the access-order queue holds the key of the entry.
+                let key = unsafe { &node.decompose_non_null().as_ref().element.key };
+                self.total_cost_served = self.total_cost_served.saturating_add(cost_fn(key, value));
+            }
+            match &entry.value {
+                Some(value) => Lookup::Hit(value),
+                None => Lookup::NegativeHit,
+            }
+        } else {
+            self.hit_rate.record(false);
+            Lookup::Miss
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the key like the `get`
+    /// method, but only if the version of the value is at least `min_version`.
+    ///
+    /// A value with an older version is stale, so the read is recorded as a
+    /// miss. The value stays in the cache until it is replaced, e.g. by
+    /// [`insert_versioned`](#method.insert_versioned). The values inserted
+    /// without a version have version zero.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn get_if_version_at_least<Q>(&mut self, key: &Q, min_version: u64) -> Option<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let is_recent = self
+            .cache
+            .get(key)
+            .is_some_and(|entry| entry.value_version() >= min_version);
+        if !is_recent {
+            self.frequency_sketch.increment(self.hash(key));
+            self.hit_rate.record(false);
+            return None;
+        }
+        self.get(key)
+    }
+
+    /// Returns an immutable reference of the value corresponding to the key,
+    /// without counting the read as an access.
+    ///
+    /// Unlike the `get` method, this method does not update the historic
+    /// popularity estimator nor the LRU order, so it never influences which
+    /// entries are admitted or evicted. This is useful when a read should not
+    /// count as popularity, e.g. an administrator inspecting the values. It does
+    /// evict the entries that are over the max capacity, like write operations,
+    /// unless lazy eviction is enabled by the
+    /// [`lazy_eviction`][lazy-eviction-method] method of the `CacheBuilder`.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// [lazy-eviction-method]: ./struct.CacheBuilder.html#method.lazy_eviction
+    pub fn get_quiet<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.evict_lru_entries_on_read();
+        self.cache.get(key).and_then(|entry| entry.value.as_ref())
+    }
+
+    /// Returns the exact number of read hits on the value for the key, or `None`
+    /// if the cache has no value for the key.
+    ///
+    /// The count is incremented by each read operation returning the value
+    /// (`get`, `get_state`, `get_guard`, `get_touch_lru_only` and
+    /// `get_or_insert_default`), but not by
+    /// `get_quiet` or `contains_key`. It is reset to zero when the value is
+    /// replaced, e.g. by `insert` or `compare_and_swap`.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.get(&'a');
+    /// cache.get(&'a');
+    ///
+    /// assert_eq!(cache.access_count(&'a'), Some(2));
+    /// assert_eq!(cache.access_count(&'b'), None);
+    /// ```
+    ///
+    pub fn access_count<Q>(&self, key: &Q) -> Option<u64>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache
+            .get(key)
+            .filter(|entry| entry.value.is_some())
+            .map(|entry| entry.access_count())
+    }
+
+    /// Returns the region of the cache holding the entry for the key, or `None`
+    /// if there is no value for the key.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::{unsync::Cache, EntryRegion};
+    ///
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .promotion_threshold(2)
+    ///     .build();
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'a');
+    /// cache.get(&'a');
+    ///
+    /// assert_eq!(cache.region_of(&'a'), Some(EntryRegion::MainProtected));
+    /// assert_eq!(cache.region_of(&'b'), Some(EntryRegion::MainProbation));
+    /// assert_eq!(cache.region_of(&'c'), None);
+    /// ```
+    ///
+    pub fn region_of<Q>(&self, key: &Q) -> Option<EntryRegion>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache
+            .get(key)
+            .filter(|entry| entry.value.is_some())
+            .and_then(|entry| entry.access_order_q_node())
+            .and_then(|node| CacheRegion::from(node.decompose_tag()).entry_region())
     }
 
-    fn record_hit(deques: &mut Deques<K>, entry: &mut ValueEntry<K, V>) {
-        deques.move_to_back_ao(entry)
-    }
+    /// Returns a [`ValueRef`][value-ref-struct] to the value corresponding to the
+    /// key, which dereferences to the value.
+    ///
+    /// This is a cache read operation like the `get` method. The cache hit is
+    /// recorded eagerly when this method is called, so dropping the returned
+    /// `ValueRef` has no side effects.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// [value-ref-struct]: ./struct.ValueRef.html
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("Julia", "Julia Robinson".to_string());
+    ///
+    /// let name = cache.get_guard(&"Julia").unwrap();
+    /// assert_eq!(name.len(), 14);
+    /// assert!(name.starts_with("Julia"));
+    /// ```
+    ///
+    pub fn get_guard<Q>(&mut self, key: &Q) -> Option<ValueRef<'_, V>>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).map(ValueRef::new)
+    }
+
+    /// Primes the historic popularity estimator with the given keys, as if each
+    /// of them was read once, without inserting any value.
+    ///
+    /// This is useful for warming up the admission policy when the access pattern
+    /// is known ahead of time: values inserted later for the primed keys are more
+    /// likely to be admitted. Passing the same key multiple times increases its
+    /// estimated popularity multiple times.
+    ///
+    /// The estimator is enabled by this method if it has not been yet. It is only
+    /// available for a cache bounded by `max_capacity`, so this method does
+    /// nothing on an unbounded cache.
+    pub fn prime_frequencies(&mut self, keys: impl IntoIterator<Item = K>) {
+        if !self.frequency_sketch_enabled {
+            self.enable_frequency_sketch();
+        }
+
+        for key in keys {
+            let hash = self.hash(&key);
+            self.frequency_sketch.increment(hash);
+        }
+    }
+
+    /// Records one read of each cached key in the historic popularity estimator,
+    /// giving the current entries a baseline popularity.
+    ///
+    /// The entries inserted before the estimator was enabled, or carried over by
+    /// [`rehash`](#method.rehash), have no recorded popularity, so the admission
+    /// policy treats them like unknown keys. Calling this method after warming up
+    /// or importing the entries makes them harder to displace by cold keys. The
+    /// popularity already recorded is kept. The tombstones of negatively cached
+    /// keys are included.
+    ///
+    /// Like [`prime_frequencies`](#method.prime_frequencies), the estimator is
+    /// enabled by this method if it has not been yet, and this method does
+    /// nothing on an unbounded cache.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(2);
+    /// cache.insert('a', "alice");
+    /// cache.rebuild_frequency_sketch();
+    /// cache.insert('b', "bob");
+    ///
+    /// // 'c' is as popular as 'b', but not as popular as 'a'.
+    /// cache.get(&'c');
+    /// cache.insert('c', "cindy");
+    /// assert!(!cache.contains_key(&'c'));
+    /// ```
+    ///
+    pub fn rebuild_frequency_sketch(&mut self) {
+        if !self.frequency_sketch_enabled {
+            self.enable_frequency_sketch();
+        }
+        if !self.frequency_sketch_enabled {
+            return;
+        }
+
+        let Self {
+            cache,
+            deques,
+            frequency_sketch,
+            ..
+        } = self;
+        let nodes = deques
+            .window
+            .iter()
+            .chain(deques.probation.iter())
+            .chain(deques.protected.iter());
+        for kh in nodes {
+            // Skip orphaned nodes whose entries were already removed.
+            if cache.contains_key(&kh.key) {
+                frequency_sketch.increment(kh.hash);
+            }
+        }
+    }
+
+    /// Groups the keys of the cached values by their popularity estimated by the
+    /// historic popularity estimator, in the range `0..=15`.
+    ///
+    /// The keys in each group are ordered from the next to be evicted. The
+    /// tombstones of negatively cached keys are skipped. Returns an empty map if
+    /// the estimator is not enabled. This is useful to judge whether the
+    /// estimator discriminates the entries well, e.g. if most keys end up in the
+    /// same group.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.rebuild_frequency_sketch();
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'b');
+    ///
+    /// let groups = cache.group_by_frequency();
+    /// assert_eq!(groups[&0], vec![&'a']);
+    /// assert_eq!(groups[&1], vec![&'b']);
+    /// ```
+    ///
+    pub fn group_by_frequency(&self) -> BTreeMap<u8, Vec<&K>> {
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        if !self.frequency_sketch_enabled {
+            return groups;
+        }
+
+        let nodes = self
+            .deques
+            .window
+            .iter()
+            .chain(self.deques.probation.iter())
+            .chain(self.deques.protected.iter());
+        for kh in nodes {
+            let has_value = self
+                .cache
+                .get(&kh.key)
+                .is_some_and(|entry| entry.value.is_some() || entry.is_pending());
+            if has_value {
+                let freq = self.frequency_sketch.frequency(kh.hash);
+                groups.entry(freq).or_default().push(&*kh.key);
+            }
+        }
+        groups
+    }
+
+    /// Replays an access log to warm up the cache, as if each `(key, value)` was
+    /// read with `get` and, on a miss, inserted with `insert`.
+    ///
+    /// Unlike inserting the entries one by one, a repeated key is recorded as a
+    /// hit rather than an update, so both the contents and the historic
+    /// popularity estimator end up like after the real access trace. The value
+    /// of a repeated key is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.replay_accesses(vec![('a', 1), ('b', 2), ('a', 3)]);
+    ///
+    /// assert_eq!(cache.entry_count(), 2);
+    /// // The repeat of 'a' was a hit, keeping the first value.
+    /// assert_eq!(cache.access_count(&'a'), Some(1));
+    /// assert_eq!(cache.get(&'a'), Some(&1));
+    /// ```
+    ///
+    pub fn replay_accesses(&mut self, log: impl IntoIterator<Item = (K, V)>) {
+        for (key, value) in log {
+            if self.get(&key).is_none() {
+                self.insert(key, value);
+            }
+        }
+    }
+
+    /// Loads the entries restored from a snapshot, with the number of reads of
+    /// each key recorded in the snapshot as a hint of its popularity.
+    ///
+    /// For each `(key, value, frequency)`, the historic popularity estimator is
+    /// incremented `frequency` times for the key, and then the entry is inserted
+    /// like the `insert` method. So the popular keys keep their priority in the
+    /// admission policy across restarts, unlike a cold load where every key
+    /// starts unknown. The estimator saturates at 15.
+    ///
+    /// Like [`prime_frequencies`](#method.prime_frequencies), the estimator is
+    /// enabled by this method if it has not been yet. On an unbounded cache, the
+    /// hints are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(2);
+    /// cache.load_with_frequencies(vec![('a', "alice", 5), ('b', "bob", 5)]);
+    ///
+    /// // 'c' was read once, so it is less popular than the loaded keys.
+    /// cache.get(&'c');
+    /// cache.insert('c', "cindy");
+    /// assert!(!cache.contains_key(&'c'));
+    /// ```
+    ///
+    pub fn load_with_frequencies(&mut self, entries: impl IntoIterator<Item = (K, V, u8)>) {
+        if !self.frequency_sketch_enabled {
+            self.enable_frequency_sketch();
+        }
+
+        for (key, value, frequency) in entries {
+            let hash = self.hash(&key);
+            for _ in 0..frequency {
+                self.frequency_sketch.increment(hash);
+            }
+            self.insert(key, value);
+        }
+    }
+
+    /// Inserts a key-value pair into the cache.
+    ///
+    /// If the cache has this key present, the value is updated.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.insert_entry(key, ValueEntry::new(value));
+    }
+
+    /// Inserts a key into the cache with a value that is computed on the first
+    /// read.
+    ///
+    /// The slot is inserted like the `insert` method, but `init` is not run until
+    /// the first read operation recording an access to the key (e.g. `get` or
+    /// `get_state`). The computed value replaces the slot, so `init` runs at most
+    /// once and the later reads return the value directly.
+    ///
+    /// Until then, `contains_key` returns `true` for the key, but the reads not
+    /// recording an access (e.g. `get_quiet` or `iter`) see no value, and
+    /// removing the slot does not run `init`. The methods passing the values to
+    /// a closure (e.g. `invalidate_entries_if`, `drain_filter`, `evict_by_score`
+    /// and `partition`) run it first, and so do `compare_and_swap` and replacing
+    /// the slot with another value, which return the computed value as the
+    /// current or old one. The slot is not copied by `clone_with_capacity`, as
+    /// `init` cannot be cloned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert_lazy('a', || "alice".to_uppercase());
+    /// assert!(cache.contains_key(&'a'));
+    ///
+    /// // The value is computed here.
+    /// assert_eq!(cache.get(&'a'), Some(&"ALICE".to_string()));
+    /// ```
+    ///
+    pub fn insert_lazy(&mut self, key: K, init: impl FnOnce() -> V + 'static) {
+        self.insert_entry(key, ValueEntry::new_lazy(Box::new(init)));
+    }
+
+    /// Inserts a key-value pair into the cache like the `insert` method, storing
+    /// the version of the value alongside it.
+    ///
+    /// This is for a cache mirroring external data with a version field. Use
+    /// [`get_if_version_at_least`](#method.get_if_version_at_least) to read the
+    /// value only if it is recent enough. The values inserted by the other
+    /// methods have version zero.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert_versioned('a', "alice", 1);
+    ///
+    /// assert_eq!(cache.get_if_version_at_least(&'a', 1), Some(&"alice"));
+    /// assert_eq!(cache.get_if_version_at_least(&'a', 2), None);
+    /// ```
+    ///
+    pub fn insert_versioned(&mut self, key: K, value: V, version: u64) {
+        self.insert_entry(key, ValueEntry::new_versioned(value, version));
+    }
+
+    /// Inserts a key-value pair into the cache, bypassing the admission policy.
+    ///
+    /// If the key is new and the cache is full, the least recently used entry is
+    /// evicted unconditionally to make room, instead of letting the admission
+    /// policy (or `InsertPolicy::RejectWhenFull`) reject the new key. This
+    /// guarantees that the key is cached afterward, unless the max capacity is
+    /// zero. It is useful for a value that will be read right away, e.g. one
+    /// just written to the backing store.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(1);
+    /// cache.insert('a', "alice");
+    /// cache.get(&'a');
+    ///
+    /// // 'b' is less popular than 'a', but is inserted anyway.
+    /// cache.force_insert('b', "bob");
+    /// assert!(cache.contains_key(&'b'));
+    /// assert!(!cache.contains_key(&'a'));
+    /// ```
+    ///
+    pub fn force_insert(&mut self, key: K, value: V) {
+        self.evict_lru_entries();
+        if !self.cache.contains_key(&key) && !self.has_enough_capacity(1, self.entry_count) {
+            // Make room, so that the insert does not go through the admission.
+            if let Some((key, entry)) = self.pop_lru_entry() {
+                if let Some(value) = entry.value {
+                    self.notify_evicted(vec![(key, value)]);
+                }
+            }
+        }
+        self.insert(key, value);
+    }
+
+    /// Inserts a key-value pair into the cache like the `insert` method, and
+    /// returns the outcome of the insertion.
+    ///
+    /// See [`UpsertResult`][upsert-result-enum] for the possible outcomes. A
+    /// rejected value is handed back to the caller.
+    ///
+    /// [upsert-result-enum]: ./enum.UpsertResult.html
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::{Cache, UpsertResult};
+    ///
+    /// let mut cache = Cache::new(1);
+    /// assert_eq!(cache.insert_detailed('a', "alice"), UpsertResult::Inserted);
+    /// assert_eq!(
+    ///     cache.insert_detailed('a', "anne"),
+    ///     UpsertResult::Updated("alice")
+    /// );
+    /// assert_eq!(
+    ///     cache.insert_detailed('b', "bob"),
+    ///     UpsertResult::AdmissionRejected("bob")
+    /// );
+    /// ```
+    ///
+    pub fn insert_detailed(&mut self, key: K, value: V) -> UpsertResult<V> {
+        self.insert_rc_entry(Rc::new(key), ValueEntry::new(value))
+            .map(|value| value.expect("Got a tombstone"))
+    }
+
+    /// Records the key as known to be absent (negative caching), by inserting a
+    /// tombstone for it.
+    ///
+    /// This is useful for a cache in front of a data source, to avoid repeated
+    /// lookups of keys that do not exist there. `get_state` returns
+    /// [`Lookup::NegativeHit`][lookup-enum] for the key, while `get` and
+    /// `contains_key` treat it as absent.
+    ///
+    /// A tombstone is an entry like the others: it counts toward the capacity,
+    /// goes through admission, and can be evicted. If the cache has a value for
+    /// the key, the value is replaced by the tombstone.
+    ///
+    /// If negative caching is disabled by the
+    /// [`negative_caching`][negative-caching-method] method of the `CacheBuilder`,
+    /// this method discards the entry for the key like `invalidate`.
+    ///
+    /// [lookup-enum]: ./enum.Lookup.html
+    /// [negative-caching-method]: ./struct.CacheBuilder.html#method.negative_caching
+    pub fn insert_absent(&mut self, key: K) {
+        if self.settings.negative_caching {
+            self.insert_entry(key, ValueEntry::new_tombstone());
+        } else {
+            self.invalidate(&key);
+        }
+    }
+
+    /// Inserts a key-value pair into the cache like the `insert` method, and
+    /// returns the entries evicted as a side effect.
+    ///
+    /// The evicted entries are the LRU victim displaced by admitting this entry
+    /// and the entries evicted to bring the cache back under its max capacity
+    /// (e.g. after lowering it by `set_max_capacity`). Evicted tombstones of
+    /// negatively cached keys are not returned, as they have no value.
+    ///
+    /// This is useful for a write-behind cache, which has to flush the evicted
+    /// values.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(2);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    ///
+    /// // The cache is full and 'c' is not popular enough to be admitted.
+    /// assert!(cache.insert_and_collect_evicted('c', "cindy").is_empty());
+    /// assert!(!cache.contains_key(&'c'));
+    ///
+    /// // Make room by lowering the capacity.
+    /// cache.set_max_capacity(1);
+    /// let evicted = cache.insert_and_collect_evicted('b', "bill");
+    /// assert_eq!(evicted, vec![('a', "alice")]);
+    /// ```
+    ///
+    pub fn insert_and_collect_evicted(&mut self, key: K, value: V) -> Vec<(K, V)>
+    where
+        K: Clone,
+    {
+        let mut evicted = Vec::new();
+        self.insert_entry_with(key, ValueEntry::new(value), &mut |key, entry| {
+            if let Some(value) = entry.value {
+                evicted.push((Rc::unwrap_or_clone(key), value));
+            }
+        });
+        evicted
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key, inserting
+    /// `V::default()` on a miss.
+    ///
+    /// This is the cache analog of `HashMap::entry(key).or_default()`. The lookup
+    /// is a cache read operation like the `get` method, and the default value
+    /// goes through the admission policy like the `insert` method. Returns `None`
+    /// if the default value was not admitted. A negatively cached key is a miss.
+    ///
+    /// Modifying the value through the returned reference is not recorded as an
+    /// update of the entry.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache: Cache<&str, Vec<i32>> = Cache::new(10);
+    ///
+    /// cache.get_or_insert_default("evens").unwrap().push(2);
+    /// cache.get_or_insert_default("evens").unwrap().push(4);
+    ///
+    /// assert_eq!(cache.get(&"evens"), Some(&vec![2, 4]));
+    /// ```
+    ///
+    pub fn get_or_insert_default(&mut self, key: K) -> Option<&mut V>
+    where
+        V: Default,
+    {
+        if let Lookup::Hit(_) = self.get_state(&key) {
+            return self
+                .cache
+                .get_mut(&key)
+                .and_then(|entry| entry.value.as_mut());
+        }
+
+        let key = Rc::new(key);
+        self.insert_rc_entry(Rc::clone(&key), ValueEntry::new(V::default()));
+        self.cache
+            .get_mut(&key)
+            .and_then(|entry| entry.value.as_mut())
+    }
+
+    /// Replaces the value for the key with `new`, only if the current value is
+    /// equal to `expected`.
+    ///
+    /// Returns `Ok(())` if the value was replaced. The replacement is an update
+    /// of the entry, like the `insert` method on an existing key, so it also runs
+    /// the maintenance of a write operation. Otherwise, returns `Err` with a
+    /// clone of the current value, or `Err(None)` if the cache has no value for
+    /// the key (including a negatively cached key). `Err(None)` is also returned
+    /// in the rare case that the maintenance evicts the key first (e.g. from a
+    /// cache over its capacity with lazy eviction) and the new value is not
+    /// admitted.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("counter", 1);
+    ///
+    /// assert_eq!(cache.compare_and_swap(&"counter", &1, 2), Ok(()));
+    /// assert_eq!(cache.compare_and_swap(&"counter", &1, 3), Err(Some(2)));
+    /// assert_eq!(cache.compare_and_swap(&"missing", &1, 3), Err(None));
+    /// assert_eq!(cache.get(&"counter"), Some(&2));
+    /// ```
+    ///
+    pub fn compare_and_swap<Q>(&mut self, key: &Q, expected: &V, new: V) -> Result<(), Option<V>>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: PartialEq + Clone,
+    {
+        let Some(entry) = self.cache.get_mut(key) else {
+            return Err(None);
+        };
+        entry.force();
+        match &entry.value {
+            Some(value) if value == expected => (),
+            value => return Err(value.clone()),
+        }
+
+        let (key, _) = self.cache.get_key_value(key).unwrap();
+        match self.insert_rc_entry(Rc::clone(key), ValueEntry::new(new)) {
+            UpsertResult::Inserted | UpsertResult::Updated(_) => Ok(()),
+            // The maintenance evicted the key before the update, and the new
+            // value was not admitted.
+            UpsertResult::AdmissionRejected(_) | UpsertResult::TooLarge(_) => Err(None),
+        }
+    }
+
+    /// Moves the value for the key `from` to the key `to`, keeping its position
+    /// in the LRU order and its estimated popularity.
+    ///
+    /// Unlike removing the value and inserting it again, the entry stays where
+    /// it is in the eviction order, and the popularity recorded for `from` is
+    /// carried over to `to`, so the renamed entry does not go through the
+    /// admission policy. Returns `false` and does nothing if the cache has no
+    /// value for `from`, or if it already has an entry for `to` (including a
+    /// negatively cached key).
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("alice", 1);
+    /// cache.insert("bob", 2);
+    ///
+    /// assert!(cache.rename(&"alice", "alicia"));
+    /// assert_eq!(cache.get(&"alicia"), Some(&1));
+    /// assert!(!cache.contains_key(&"alice"));
+    ///
+    /// assert!(!cache.rename(&"bob", "alicia"));
+    /// assert!(!cache.rename(&"carol", "cindy"));
+    /// ```
+    ///
+    pub fn rename<Q>(&mut self, from: &Q, to: K) -> bool
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.has_value(from) || self.cache.contains_key::<K>(&to) {
+            return false;
+        }
+
+        // Hash the new key before taking the entry out of the map, so that a
+        // panicking `Hash` implementation cannot lose the entry.
+        let to = Rc::new(to);
+        let hash = self.hash::<Rc<K>>(&to);
+        let (_, mut entry) = self.cache.remove_entry(from).unwrap();
+
+        // Update the nodes in place to keep their positions.
+        let mut old_hash = None;
+        if let Some(node) = entry.access_order_q_node() {
+            let kh = unsafe { &mut node.decompose_non_null().as_mut().element };
+            old_hash = Some(kh.hash);
+            *kh = KeyHashDate::new(Rc::clone(&to), hash);
+        }
+        if let Some(mut node) = entry.write_order_q_node() {
+            let kh = unsafe { &mut node.as_mut().element };
+            *kh = KeyHashDate::new(Rc::clone(&to), hash);
+        }
+
+        if let Some(old_hash) = old_hash {
+            let old_freq = self.frequency_sketch.frequency(old_hash);
+            for _ in self.frequency_sketch.frequency(hash)..old_freq {
+                self.frequency_sketch.increment(hash);
+            }
+        }
+        if let Some(estimator) = &mut self.distinct_keys {
+            estimator.add(hash);
+        }
+
+        self.version += 1;
+        entry.set_modified_at_version(self.version);
+        self.cache.insert(to, entry);
+        true
+    }
+
+    fn insert_entry(&mut self, key: K, entry: ValueEntry<K, V>) {
+        self.insert_rc_entry(Rc::new(key), entry);
+    }
+
+    /// Inserts the entry, passing the values evicted in the course to the
+    /// eviction listener in a single batch.
+    fn insert_rc_entry(&mut self, key: Rc<K>, entry: ValueEntry<K, V>) -> UpsertResult<Option<V>> {
+        if self.eviction_listener.is_none() {
+            return self.insert_rc_entry_with(key, entry, &mut |_, _| {});
+        }
+
+        let mut batch = Vec::new();
+        let result = self.insert_rc_entry_with(key, entry, &mut |key, entry| {
+            if let Some(value) = entry.value {
+                batch.push((key, value));
+            }
+        });
+        self.notify_evicted(batch);
+        result
+    }
+
+    /// Passes the evicted entries to the eviction listener, if any.
+    fn notify_evicted(&self, batch: Vec<(Rc<K>, V)>) {
+        if let Some(listener) = &self.eviction_listener {
+            if !batch.is_empty() {
+                listener(batch);
+            }
+        }
+    }
+
+    /// Inserts the entry, calling `on_evict` for each entry evicted in the course.
+    /// A rejected value is returned in the result.
+    fn insert_entry_with(
+        &mut self,
+        key: K,
+        entry: ValueEntry<K, V>,
+        on_evict: &mut impl FnMut(Rc<K>, ValueEntry<K, V>),
+    ) -> UpsertResult<Option<V>> {
+        self.insert_rc_entry_with(Rc::new(key), entry, on_evict)
+    }
+
+    fn insert_rc_entry_with(
+        &mut self,
+        key: Rc<K>,
+        entry: ValueEntry<K, V>,
+        on_evict: &mut impl FnMut(Rc<K>, ValueEntry<K, V>),
+    ) -> UpsertResult<Option<V>> {
+        if !self.defer_maintenance(true) {
+            self.evict_lru_entries_with(on_evict);
+        }
+        let policy_weight = 1;
+        if self.distinct_keys.is_some() {
+            let hash = self.hash(&key);
+            if let Some(estimator) = &mut self.distinct_keys {
+                estimator.add(hash);
+            }
+        }
+
+        let result = if let Some(old_entry) = self.cache.insert(Rc::clone(&key), entry) {
+            match self.handle_update(key, policy_weight, old_entry) {
+                Some(old_value) => UpsertResult::Updated(Some(old_value)),
+                // The old entry was a tombstone.
+                None => UpsertResult::Inserted,
+            }
+        } else {
+            let hash = self.hash(&key);
+            self.handle_insert(key, hash, policy_weight, on_evict)
+        };
+        if matches!(
+            result,
+            UpsertResult::AdmissionRejected(_) | UpsertResult::TooLarge(_)
+        ) {
+            self.total_rejected += 1;
+        }
+
+        self.debug_assert_entry_count();
+        result
+    }
+
+    /// Discards any cached value for the key.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn invalidate<Q>(&mut self, key: &Q)
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.run_scheduled_maintenance();
+
+        if let Some(mut entry) = self.cache.remove(key) {
+            self.deques.unlink_ao(&mut entry);
+            self.deques.unlink_wo(&mut entry);
+            self.entry_count -= 1;
+            self.version += 1;
+        }
+
+        self.debug_assert_entry_count();
+    }
+
+    /// Discards any cached value for the key, returning the cached value.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.run_scheduled_maintenance();
+
+        let value = if let Some(mut entry) = self.cache.remove(key) {
+            self.deques.unlink_ao(&mut entry);
+            self.deques.unlink_wo(&mut entry);
+            self.entry_count -= 1;
+            self.version += 1;
+            entry.value
+        } else {
+            None
+        };
+
+        self.debug_assert_entry_count();
+        value
+    }
+
+    /// Discards any cached value for the key, returning the stored key and the
+    /// cached value, like `HashMap::remove_entry`.
+    ///
+    /// A negatively cached key is removed like the `remove` method does, and
+    /// `None` is returned.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("Julia", 14);
+    ///
+    /// assert_eq!(cache.remove_entry(&"Julia"), Some(("Julia", 14)));
+    /// assert_eq!(cache.remove_entry(&"Julia"), None);
+    /// ```
+    ///
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Clone,
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.run_scheduled_maintenance();
+
+        let removed = if let Some((key, mut entry)) = self.cache.remove_entry(key) {
+            self.deques.unlink_ao(&mut entry);
+            self.deques.unlink_wo(&mut entry);
+            self.entry_count -= 1;
+            self.version += 1;
+            // The node of the deque was dropped, so the key is not cloned unless
+            // an `Rc` from `iter_rc` is still alive.
+            entry.value.map(|value| (Rc::unwrap_or_clone(key), value))
+        } else {
+            None
+        };
+
+        self.debug_assert_entry_count();
+        removed
+    }
+
+    /// Takes the value out of the cache, returning it.
+    ///
+    /// Unlike the `remove` method, this method leaves a tombstone for the key in
+    /// place of the value, as [`insert_absent`](#method.insert_absent) does. The
+    /// tombstone keeps the position of the entry in the LRU order, and a
+    /// following insert for the key updates the tombstone without going through
+    /// admission. Taking the value of a negatively cached key returns `None`.
+    ///
+    /// If negative caching is disabled by the
+    /// [`negative_caching`][negative-caching-method] method of the `CacheBuilder`,
+    /// this method behaves like `remove`.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// [negative-caching-method]: ./struct.CacheBuilder.html#method.negative_caching
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::{Cache, Lookup};
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    ///
+    /// assert_eq!(cache.take(&'a'), Some("alice"));
+    /// assert_eq!(cache.get_state(&'a'), Lookup::NegativeHit);
+    /// assert_eq!(cache.take(&'a'), None);
+    /// ```
+    ///
+    pub fn take<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !self.settings.negative_caching {
+            return self.remove(key);
+        }
+
+        let entry = self.cache.get_mut(key)?;
+        entry.force();
+        let value = entry.value.take();
+        if value.is_some() {
+            self.version += 1;
+        }
+        value
+    }
+
+    /// Discards all cached values.
+    ///
+    /// Like the `invalidate` method, this method does not clear the historic
+    /// popularity estimator of keys so that it retains the client activities of
+    /// trying to retrieve an item.
+    pub fn invalidate_all(&mut self) {
+        // Phase 1: swap out the cache before resetting internal state so that
+        // a panic in V::drop leaves `self` in a consistent (empty) state.
+        let old_capacity = self.cache.capacity();
+        let old_cache = std::mem::replace(
+            &mut self.cache,
+            HashMap::with_hasher(self.build_hasher.clone()),
+        );
+        self.deques.clear();
+        if self.entry_count > 0 {
+            self.version += 1;
+        }
+        self.entry_count = 0;
+
+        // If V::drop panics, `self` is already in a valid empty state.
+        drop(old_cache);
+
+        // Phase 2: best effort capacity restoration for future inserts.
+        let _ = self.cache.try_reserve(old_capacity);
+    }
+
+    /// Discards all cached values except the ones for which `keep` returns `true`.
+    ///
+    /// This is the inverse of [`invalidate_entries_if`](#method.invalidate_entries_if),
+    /// useful to clear a cache while keeping a few pinned entries. Tombstones of
+    /// negatively cached keys are not passed to the closure, and are discarded.
+    /// The pending values inserted by [`insert_lazy`](#method.insert_lazy) are
+    /// computed to be passed to the closure.
+    ///
+    /// Like the `invalidate_all` method, the internal state is updated before the
+    /// discarded values are dropped, so a panic in `V::drop` leaves the cache in a
+    /// consistent state. The historic popularity estimator is not cleared.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert("config", 1);
+    /// cache.insert("page-1", 2);
+    /// cache.insert("page-2", 3);
+    ///
+    /// cache.invalidate_all_except(|key, _| *key == "config");
+    /// assert_eq!(cache.entry_count(), 1);
+    /// assert!(cache.contains_key(&"config"));
+    /// ```
+    ///
+    pub fn invalidate_all_except<F>(&mut self, keep: F)
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        let Self { cache, deques, .. } = self;
+        cache.values_mut().for_each(ValueEntry::force);
+
+        let keys_to_invalidate = cache
+            .iter()
+            .filter(|(key, entry)| !entry.value.as_ref().is_some_and(|value| keep(key, value)))
+            .map(|(key, _)| Rc::clone(key))
+            .collect::<Vec<_>>();
+
+        // Unlink the entries first, and drop them after the internal state is
+        // updated.
+        let mut invalidated = Vec::with_capacity(keys_to_invalidate.len());
+        for key in keys_to_invalidate {
+            if let Some(mut entry) = cache.remove(&key) {
+                deques.unlink_ao(&mut entry);
+                deques.unlink_wo(&mut entry);
+                invalidated.push(entry);
+            }
+        }
+
+        self.entry_count -= invalidated.len() as u64;
+        if !invalidated.is_empty() {
+            self.version += 1;
+        }
+        self.debug_assert_entry_count();
+
+        // If V::drop panics, `self` is already in a consistent state.
+        drop(invalidated);
+    }
+
+    /// Discards cached values that satisfy a predicate.
+    ///
+    /// `invalidate_entries_if` takes a closure that returns `true` or `false`.
+    /// `invalidate_entries_if` will apply the closure to each cached value,
+    /// and if the closure returns `true`, the value will be invalidated.
+    /// Tombstones of negatively cached keys are not passed to the closure.
+    ///
+    /// Like the `invalidate` method, this method does not clear the historic
+    /// popularity estimator of keys so that it retains the client activities of
+    /// trying to retrieve an item.
+    // -----------------------------------------------------------------------
+    // (The followings are not doc comments)
+    // We need this #[allow(...)] to avoid a false Clippy warning about needless
+    // collect to create keys_to_invalidate.
+    // clippy 0.1.52 (9a1dfd2dc5c 2021-04-30) in Rust 1.52.0-beta.7
+    #[allow(clippy::needless_collect)]
+    pub fn invalidate_entries_if(&mut self, mut predicate: impl FnMut(&K, &V) -> bool) {
+        let Self { cache, deques, .. } = self;
+
+        // Since we can't do cache.iter() and cache.remove() at the same time,
+        // invalidation needs to run in two steps:
+        // 1. Examine all entries in this cache and collect keys to invalidate.
+        // 2. Remove entries for the keys.
+
+        // Run the initializers of the pending values, so that the closure sees
+        // them.
+        cache.values_mut().for_each(ValueEntry::force);
+        let keys_to_invalidate = cache
+            .iter()
+            .filter(|(key, entry)| {
+                entry
+                    .value
+                    .as_ref()
+                    .is_some_and(|value| (predicate)(key, value))
+            })
+            .map(|(key, _)| Rc::clone(key))
+            .collect::<Vec<_>>();
+
+        let mut invalidated = 0u64;
+
+        keys_to_invalidate.into_iter().for_each(|k| {
+            if let Some(mut entry) = cache.remove(&k) {
+                let _weight = entry.policy_weight();
+                deques.unlink_ao(&mut entry);
+                deques.unlink_wo(&mut entry);
+                invalidated += 1;
+            }
+        });
+        self.entry_count -= invalidated;
+        if invalidated > 0 {
+            self.version += 1;
+        }
+        self.debug_assert_entry_count();
+    }
+
+    /// Discards the cached values that the closure marks as stale, and returns
+    /// the number of entries discarded.
+    ///
+    /// This is the same mechanism as
+    /// [`invalidate_entries_if`](#method.invalidate_entries_if), returning the
+    /// count. It is meant for cache coherency, e.g. discarding the values stored
+    /// from an older generation than the current one of their keys in an
+    /// external map. Tombstones of negatively cached keys are not passed to the
+    /// closure.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    /// use std::collections::HashMap;
+    ///
+    /// // The current generation of each key.
+    /// let generations = HashMap::from([('a', 2), ('b', 1)]);
+    ///
+    /// // The values are (generation, data).
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', (1, "alice"));
+    /// cache.insert('b', (1, "bob"));
+    ///
+    /// let stale = cache.invalidate_stale(|k, (generation, _)| *generation < generations[k]);
+    /// assert_eq!(stale, 1);
+    /// assert!(!cache.contains_key(&'a'));
+    /// ```
+    ///
+    pub fn invalidate_stale<F>(&mut self, is_stale: F) -> u64
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        let entry_count = self.entry_count;
+        self.invalidate_entries_if(is_stale);
+        entry_count - self.entry_count
+    }
+
+    /// Removes the entries for which the closure returns `true`, and returns them.
+    ///
+    /// The closure can also modify the values of the entries that it keeps.
+    /// Unlike `invalidate_entries_if`, the entries are examined and removed in a
+    /// single pass over the internal hash map. Tombstones of negatively cached
+    /// keys are not passed to the closure, and are kept.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 1);
+    /// cache.insert('b', 2);
+    /// cache.insert('c', 3);
+    ///
+    /// let mut drained = cache.drain_filter(|_, v| *v % 2 == 1);
+    /// drained.sort_unstable();
+    /// assert_eq!(drained, vec![('a', 1), ('c', 3)]);
+    /// assert_eq!(cache.entry_count(), 1);
+    /// ```
+    ///
+    pub fn drain_filter<F>(&mut self, mut f: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        K: Clone,
+    {
+        let Self {
+            cache,
+            deques,
+            entry_count,
+            version,
+            ..
+        } = self;
+        let mut drained = Vec::new();
+
+        // The deques are borrowed apart from the hash map, so the nodes can be
+        // unlinked while retaining. The counters are updated as the entries are
+        // removed, to keep them consistent if the closure panics.
+        cache.retain(|key, entry| {
+            entry.force();
+            let Some(value) = entry.value.as_mut() else {
+                return true;
+            };
+            if !f(key, value) {
+                return true;
+            }
+
+            deques.unlink_ao(entry);
+            deques.unlink_wo(entry);
+            *entry_count -= 1;
+            if drained.is_empty() {
+                *version += 1;
+            }
+            drained.push((Rc::clone(key), entry.value.take().unwrap()));
+            false
+        });
+        self.debug_assert_entry_count();
+
+        // The hash map and the deques no longer hold the keys, so they can be
+        // unwrapped without cloning.
+        drained
+            .into_iter()
+            .map(|(key, value)| (Rc::unwrap_or_clone(key), value))
+            .collect()
+    }
+
+    /// Returns the key of the entry that will be evicted next, i.e. the least
+    /// recently used one, or `None` if the cache is empty.
+    ///
+    /// This does not change the LRU order. The key may be the one of a tombstone
+    /// of a negatively cached key. Under `EvictionPolicy::Clock`, the returned key
+    /// is the one under the clock hand; if it has been referenced, it will be
+    /// given a second chance and a later entry will be evicted instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// assert_eq!(cache.next_eviction_key(), None);
+    ///
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'a');
+    /// assert_eq!(cache.next_eviction_key(), Some(&'b'));
+    /// ```
+    ///
+    pub fn next_eviction_key(&self) -> Option<&K> {
+        self.deques
+            .victim_deque()
+            .peek_front()
+            .map(|node| &*node.element.key)
+    }
+
+    /// Returns the position of the entry for the key in the eviction order of
+    /// the probation region, where `0` is the least recently used entry to be
+    /// evicted next.
+    ///
+    /// This walks the probation region from the LRU end, so it takes time
+    /// linear to the rank. It does not change the LRU order. The tombstones of
+    /// negatively cached keys are counted, as they are evicted like the other
+    /// entries. Returns `None` if the cache has no value for the key, or if the
+    /// entry has been promoted to the protected region (see
+    /// [`promotion_threshold`][promotion-threshold-method]).
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    /// cache.get(&'a');
+    ///
+    /// assert_eq!(cache.recency_rank(&'b'), Some(0));
+    /// assert_eq!(cache.recency_rank(&'c'), Some(1));
+    /// assert_eq!(cache.recency_rank(&'a'), Some(2));
+    /// assert_eq!(cache.recency_rank(&'d'), None);
+    /// ```
+    ///
+    /// [promotion-threshold-method]: ./struct.CacheBuilder.html#method.promotion_threshold
+    pub fn recency_rank<Q>(&self, key: &Q) -> Option<usize>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, entry) = self.cache.get_key_value(key)?;
+        entry.value.as_ref()?;
+        let node = entry.access_order_q_node()?;
+        if CacheRegion::MainProbation != node.decompose_tag() {
+            return None;
+        }
+        self.deques
+            .probation
+            .iter()
+            .position(|kh| Rc::ptr_eq(&kh.key, key))
+    }
+
+    /// Returns the keys of the cached values in the order they were inserted,
+    /// from the oldest one.
+    ///
+    /// The order is independent of the access order used for the eviction.
+    /// Updating the value of an existing key does not change its position. The
+    /// tombstones of negatively cached keys are skipped.
+    ///
+    /// Returns an empty `Vec` unless the cache was built with
+    /// [`track_insertion_order`][track-insertion-order-method] enabled.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .track_insertion_order(true)
+    ///     .build();
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'a');
+    /// assert_eq!(cache.insertion_order(), vec![&'a', &'b']);
+    /// ```
+    ///
+    /// [track-insertion-order-method]: ./struct.CacheBuilder.html#method.track_insertion_order
+    pub fn insertion_order(&self) -> Vec<&K> {
+        self.deques
+            .write_order
+            .iter()
+            .filter(|kh| {
+                self.cache
+                    .get(&kh.key)
+                    .is_some_and(|entry| entry.value.is_some())
+            })
+            .map(|kh| &*kh.key)
+            .collect()
+    }
+
+    /// Returns the entries inserted or updated after the given
+    /// [`version`](#method.version) of the cache, from the least recently
+    /// modified one.
+    ///
+    /// This supports an incremental sync to a downstream consumer: record the
+    /// version, and later ask for what changed since then. The removed entries
+    /// and the tombstones of negatively cached keys are not returned. Modifying
+    /// a value through a mutable reference (e.g. `iter_ordered_mut`) is not
+    /// recorded as an update.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// let version = cache.version();
+    ///
+    /// cache.insert('c', "cindy");
+    /// cache.insert('a', "anne");
+    /// assert_eq!(
+    ///     cache.entries_since(version),
+    ///     vec![(&'c', &"cindy"), (&'a', &"anne")]
+    /// );
+    /// ```
+    ///
+    pub fn entries_since(&self, version: u64) -> Vec<(&K, &V)> {
+        let mut entries = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| entry.modified_at_version() > version)
+            .filter_map(|(key, entry)| {
+                let value = entry.value.as_ref()?;
+                Some((entry.modified_at_version(), &**key, value))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(modified_at, _, _)| *modified_at);
+        entries
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect()
+    }
+
+    /// Returns up to `n` of the most recently used entries, from the most
+    /// recently used one.
+    ///
+    /// This does not change the LRU order and is bounded by `n`, apart from
+    /// skipping the tombstones of negatively cached keys. Only the probation
+    /// region is visited, so the entries promoted to the protected region (see
+    /// [`promotion_threshold`][promotion-threshold-method]) are not returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    /// cache.get(&'a');
+    ///
+    /// assert_eq!(cache.recent(2), vec![(&'a', &"alice"), (&'c', &"cindy")]);
+    /// ```
+    ///
+    /// [promotion-threshold-method]: ./struct.CacheBuilder.html#method.promotion_threshold
+    pub fn recent(&self, n: usize) -> Vec<(&K, &V)> {
+        self.deques
+            .probation
+            .iter()
+            .rev()
+            .filter_map(|kh| {
+                self.cache
+                    .get(&kh.key)
+                    .and_then(|entry| entry.value.as_ref())
+                    .map(|value| (&*kh.key, value))
+            })
+            .take(n)
+            .collect()
+    }
+
+    /// Returns up to `n` of the least recently used entries, in the order they
+    /// will be evicted.
+    ///
+    /// This does not change the LRU order and is bounded by `n`, apart from
+    /// skipping the tombstones of negatively cached keys. It is useful for
+    /// flushing the coldest values of a write-behind cache. Under
+    /// `EvictionPolicy::Clock`, the entries are in the order of the clock hand;
+    /// the referenced ones will be given a second chance.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    /// cache.get(&'a');
+    ///
+    /// assert_eq!(cache.least_recent(2), vec![(&'b', &"bob"), (&'c', &"cindy")]);
+    /// ```
+    ///
+    pub fn least_recent(&self, n: usize) -> Vec<(&K, &V)> {
+        self.deques
+            .probation
+            .iter()
+            .chain(self.deques.protected.iter())
+            .filter_map(|kh| {
+                self.cache
+                    .get(&kh.key)
+                    .and_then(|entry| entry.value.as_ref())
+                    .map(|value| (&*kh.key, value))
+            })
+            .take(n)
+            .collect()
+    }
+
+    /// Returns clones of all the entries in the order they will be evicted, from
+    /// the least recently used one.
+    ///
+    /// Inserting the entries in this order into an empty cache approximately
+    /// restores their eviction priority, e.g. when persisting a cache. Unlike
+    /// `iter`, which visits the entries in an arbitrary order, this walks the
+    /// access-order queues. The tombstones of negatively cached keys are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.get(&'a');
+    ///
+    /// let entries = cache.to_vec_by_recency();
+    /// assert_eq!(entries, vec![('b', "bob"), ('a', "alice")]);
+    ///
+    /// let mut restored = Cache::new(10);
+    /// for (key, value) in entries {
+    ///     restored.insert(key, value);
+    /// }
+    /// assert_eq!(restored.next_eviction_key(), Some(&'b'));
+    /// ```
+    ///
+    pub fn to_vec_by_recency(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.least_recent(usize::MAX)
+            .into_iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Evicts up to `n` entries from the least recently used end of the cache,
+    /// regardless of the max capacity. Returns the number of entries actually
+    /// evicted, which is less than `n` if the cache holds fewer entries.
+    ///
+    /// This is useful for shedding memory on demand, e.g. from a memory-pressure
+    /// callback of the OS or runtime.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    ///
+    /// assert_eq!(cache.evict_n(2), 2);
+    /// assert_eq!(cache.entry_count(), 1);
+    /// assert!(cache.contains_key(&'c'));
+    ///
+    /// assert_eq!(cache.evict_n(5), 1);
+    /// assert_eq!(cache.entry_count(), 0);
+    /// ```
+    ///
+    pub fn evict_n(&mut self, n: u64) -> u64 {
+        let collect = self.eviction_listener.is_some();
+        let mut batch = Vec::new();
+        let mut evicted = 0;
+        while evicted < n {
+            let Some((key, entry)) = self.pop_lru_entry() else {
+                break;
+            };
+            evicted += 1;
+            if let (true, Some(value)) = (collect, entry.value) {
+                batch.push((key, value));
+            }
+        }
+        self.notify_evicted(batch);
+        evicted
+    }
+
+    /// Evicts the `count` entries with the lowest scores given by the closure,
+    /// regardless of the LRU order and the max capacity. Returns the number of
+    /// entries evicted, which is less than `count` if the cache holds fewer
+    /// entries.
+    ///
+    /// This is useful for application-specific eviction, e.g. evicting the
+    /// entries with the oldest timestamps stored in the values. Entries with the
+    /// same score are evicted in an arbitrary order. Tombstones of negatively
+    /// cached keys are not scored, and are never evicted by this method.
+    ///
+    /// The closure is called once for every entry, so this method takes
+    /// `O(n log n)` time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 30);
+    /// cache.insert('b', 10);
+    /// cache.insert('c', 20);
+    ///
+    /// assert_eq!(cache.evict_by_score(1, |_, v| *v), 1);
+    /// assert!(!cache.contains_key(&'b'));
+    /// ```
+    ///
+    pub fn evict_by_score<F>(&mut self, count: usize, score: F) -> u64
+    where
+        F: Fn(&K, &V) -> i64,
+    {
+        self.cache.values_mut().for_each(ValueEntry::force);
+        let mut scored = self
+            .cache
+            .iter()
+            .filter_map(|(key, entry)| {
+                let value = entry.value.as_ref()?;
+                Some((score(key, value), Rc::clone(key)))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_unstable_by_key(|(score, _)| *score);
+
+        let collect = self.eviction_listener.is_some();
+        let mut batch = Vec::new();
+        let mut evicted = 0;
+        for (_, key) in scored.into_iter().take(count) {
+            if let Some(mut entry) = self.cache.remove(&key) {
+                self.deques.unlink_ao(&mut entry);
+                self.deques.unlink_wo(&mut entry);
+                evicted += 1;
+                if let (true, Some(value)) = (collect, entry.value) {
+                    batch.push((key, value));
+                }
+            }
+        }
+
+        self.entry_count -= evicted;
+        self.total_evicted += evicted;
+        if evicted > 0 {
+            self.version += 1;
+        }
+        self.debug_assert_entry_count();
+        self.notify_evicted(batch);
+        evicted
+    }
+
+    /// Evicts entries from the least recently used end of the cache until it
+    /// holds at most `target` entries, regardless of the max capacity. Returns
+    /// the number of entries evicted.
+    ///
+    /// The max capacity is unchanged, so the cache can grow back to it afterward.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    ///
+    /// assert_eq!(cache.trim_to(1), 2);
+    /// assert!(cache.contains_key(&'c'));
+    /// assert_eq!(cache.trim_to(5), 0);
+    /// ```
+    ///
+    pub fn trim_to(&mut self, target: u64) -> u64 {
+        self.evict_n(self.entry_count.saturating_sub(target))
+    }
+
+    /// Returns `true` if a read operation found the cache over its max capacity
+    /// and deferred the eviction, because lazy eviction is enabled by the
+    /// [`lazy_eviction`][lazy-eviction-method] method of the `CacheBuilder`.
+    ///
+    /// The flag is cleared once the cache is back within its max capacity, by an
+    /// insert or by [`run_pending_maintenance`](#method.run_pending_maintenance).
+    ///
+    /// [lazy-eviction-method]: ./struct.CacheBuilder.html#method.lazy_eviction
+    pub fn is_maintenance_due(&self) -> bool {
+        self.maintenance_due
+    }
+
+    /// Evicts all the entries over the max capacity from the least recently used
+    /// end of the cache. Returns the number of entries evicted.
+    ///
+    /// Unlike the write operations, which evict up to a batch of entries each,
+    /// this method brings the cache back within its max capacity at once. With
+    /// lazy eviction, call it off the hot path when
+    /// [`is_maintenance_due`](#method.is_maintenance_due) returns `true`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::builder()
+    ///     .max_capacity(10)
+    ///     .lazy_eviction(true)
+    ///     .build();
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.set_max_capacity(1);
+    ///
+    /// // The read does not evict, but marks the maintenance as due.
+    /// assert_eq!(cache.get_quiet(&'a'), Some(&"alice"));
+    /// assert!(cache.is_maintenance_due());
+    ///
+    /// assert_eq!(cache.run_pending_maintenance(), 1);
+    /// assert_eq!(cache.entry_count(), 1);
+    /// assert!(!cache.is_maintenance_due());
+    /// ```
+    ///
+    pub fn run_pending_maintenance(&mut self) -> u64 {
+        let evicted = self.evict_n(self.weights_to_evict());
+        self.maintenance_due = false;
+        evicted
+    }
+
+    /// Evicts the given fraction of the current entries from the least recently
+    /// used end of the cache in one shot, and returns the evicted entries.
+    ///
+    /// This is a hook for a global memory monitor to shrink the cache
+    /// proportionally under memory pressure. The number of entries to evict is
+    /// rounded up. A `fraction` of `1.0` or more clears the cache, and `0.0` or
+    /// less (or NaN) does nothing. Evicted tombstones of negatively cached keys
+    /// are not returned, as they have no value.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    ///
+    /// let evicted = cache.on_memory_pressure(0.5);
+    /// assert_eq!(evicted, vec![('a', "alice"), ('b', "bob")]);
+    /// assert_eq!(cache.entry_count(), 1);
+    /// ```
+    ///
+    pub fn on_memory_pressure(&mut self, fraction: f64) -> Vec<(K, V)>
+    where
+        K: Clone,
+    {
+        let n = if fraction >= 1.0 {
+            self.entry_count
+        } else if fraction > 0.0 {
+            (self.entry_count as f64 * fraction).ceil() as u64
+        } else {
+            0
+        };
+
+        let mut evicted = Vec::new();
+        for _ in 0..n {
+            let Some((key, entry)) = self.pop_lru_entry() else {
+                break;
+            };
+            if let Some(value) = entry.value {
+                evicted.push((Rc::unwrap_or_clone(key), value));
+            }
+        }
+
+        self.debug_assert_entry_count();
+        evicted
+    }
+
+    /// Rebuilds the internal access-order queues, re-allocating their nodes in the
+    /// current order.
+    ///
+    /// After heavy insert and eviction churn, the nodes of the queues can be
+    /// scattered in memory, hurting the locality of traversals during admission
+    /// and eviction. This maintenance operation does not change the contents of
+    /// the cache or the eviction order.
+    ///
+    /// The queues never free the memory of their nodes while they live, so after
+    /// the cache has been shrunk (e.g. by `set_max_capacity`, `trim_to` or
+    /// `evict_n`) they still hold the memory for the peak number of entries.
+    /// Compacting re-allocates only the nodes of the current entries and
+    /// releases the rest.
+    pub fn compact(&mut self) {
+        self.compact_deque(CacheRegion::Window);
+        self.compact_deque(CacheRegion::MainProbation);
+        self.compact_deque(CacheRegion::MainProtected);
+
+        let old_deq =
+            std::mem::replace(&mut self.deques.write_order, Deque::new(CacheRegion::Other));
+        for kh in old_deq.iter() {
+            if let Some(entry) = self.cache.get_mut(&kh.key) {
+                self.deques
+                    .push_back_wo(KeyHashDate::new(Rc::clone(&kh.key), kh.hash), entry);
+            }
+        }
+    }
+
+    /// Drops the nodes of the internal queues that do not belong to a live entry,
+    /// returning the number of the nodes dropped.
+    ///
+    /// A node is orphaned when its key is missing in the hash map, or when the
+    /// entry of the key points to another node. Orphaned nodes should never exist,
+    /// so this maintenance operation is intended for debugging. It also
+    /// resynchronizes the entry count with the number of entries in the hash map.
+    pub fn prune_orphans(&mut self) -> u64 {
+        let cache = &self.cache;
+        let is_live_ao = |entry: &ValueEntry<K, V>, node| {
+            entry
+                .access_order_q_node()
+                .is_some_and(|tagged| tagged.decompose_non_null() == node)
+        };
+        let pruned = Self::prune_deque(&mut self.deques.window, cache, is_live_ao)
+            + Self::prune_deque(&mut self.deques.probation, cache, is_live_ao)
+            + Self::prune_deque(&mut self.deques.protected, cache, is_live_ao)
+            + Self::prune_deque(&mut self.deques.write_order, cache, |entry, node| {
+                entry.write_order_q_node() == Some(node)
+            });
+        self.entry_count = self.cache.len() as u64;
+        pruned
+    }
+
+    /// Returns the keys in the window region of the cache, from the least recently
+    /// used to the most recently used.
+    ///
+    /// The window region is reserved for the W-TinyLFU policy. At this time, Micro
+    /// Moka admits all entries directly to the main probation region and does not
+    /// use the window, so the returned `Vec` is always empty.
+    pub fn window_keys(&self) -> Vec<&K> {
+        self.deques.window.iter().map(|kh| &*kh.key).collect()
+    }
+
+    /// Verifies the consistency of the internal data structures, returning a
+    /// description of the first violation found.
+    ///
+    /// This checks that the entry count matches both the number of entries in the
+    /// hash map and the total length of the access-order queues, that every entry
+    /// points to a node in the queue of its region, and that every node refers
+    /// back to a live entry. When the insertion order is tracked, the same is
+    /// checked for the write-order queue. It walks all entries, so it is intended
+    /// for tests, fuzzing and debugging rather than production paths.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let map_len = self.cache.len() as u64;
+        if self.entry_count != map_len {
+            return Err(format!(
+                "entry_count ({}) does not match the number of entries in the map ({})",
+                self.entry_count, map_len
+            ));
+        }
+
+        let deqs = [
+            ("window", &self.deques.window),
+            ("probation", &self.deques.probation),
+            ("protected", &self.deques.protected),
+        ];
+        let deqs_len = deqs.iter().map(|(_, deq)| deq.len() as u64).sum::<u64>();
+        if self.entry_count != deqs_len {
+            return Err(format!(
+                "entry_count ({}) does not match the total length of the deques ({})",
+                self.entry_count, deqs_len
+            ));
+        }
+
+        for (deq_name, deq) in deqs {
+            for kh in deq.iter() {
+                let Some(entry) = self.cache.get(&kh.key) else {
+                    return Err(format!(
+                        "a node in the {} deque refers to a key missing in the map",
+                        deq_name
+                    ));
+                };
+                let Some(tagged_node) = entry.access_order_q_node() else {
+                    return Err(format!(
+                        "an entry in the map has no node but a node in the {} deque refers to it",
+                        deq_name
+                    ));
+                };
+                let (node, tag) = tagged_node.decompose();
+                if deq.region() != tag {
+                    return Err(format!(
+                        "an entry in the map has a node tagged with region {:?} but the node is in the {} deque",
+                        CacheRegion::from(tag),
+                        deq_name
+                    ));
+                }
+                let element = unsafe { std::ptr::addr_of!((*node.as_ptr()).element) };
+                if !std::ptr::eq(element, kh) {
+                    return Err(format!(
+                        "an entry in the map does not point to the node in the {} deque referring to it",
+                        deq_name
+                    ));
+                }
+            }
+        }
+
+        if self.settings.track_insertion_order {
+            let wo_len = self.deques.write_order.len() as u64;
+            if self.entry_count != wo_len {
+                return Err(format!(
+                    "entry_count ({}) does not match the length of the write-order deque ({})",
+                    self.entry_count, wo_len
+                ));
+            }
+
+            for kh in self.deques.write_order.iter() {
+                let Some(entry) = self.cache.get(&kh.key) else {
+                    return Err(
+                        "a node in the write-order deque refers to a key missing in the map".into(),
+                    );
+                };
+                let Some(node) = entry.write_order_q_node() else {
+                    return Err(
+                        "an entry in the map has no write-order node but a node in the write-order deque refers to it"
+                            .into(),
+                    );
+                };
+                let element = unsafe { std::ptr::addr_of!((*node.as_ptr()).element) };
+                if !std::ptr::eq(element, kh) {
+                    return Err(
+                        "an entry in the map does not point to the node in the write-order deque referring to it"
+                            .into(),
+                    );
+                }
+            }
+        }
+
+        // All nodes in the deques are referred by distinct entries, and the number
+        // of nodes equals the number of entries. Therefore, every entry has a node.
+        Ok(())
+    }
+
+    /// Creates an iterator visiting all key-value pairs in arbitrary order. The
+    /// iterator element type is `(&K, &V)`. Tombstones of negatively cached keys
+    /// are skipped.
+    ///
+    /// Unlike the `get` method, visiting entries via an iterator do not update the
+    /// historic popularity estimator or reset idle timers for keys.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(100);
+    /// cache.insert("Julia", 14);
+    ///
+    /// let mut iter = cache.iter();
+    /// let (k, v) = iter.next().unwrap(); // (&K, &V)
+    /// assert_eq!(k, &"Julia");
+    /// assert_eq!(v, &14);
+    ///
+    /// assert!(iter.next().is_none());
+    /// ```
+    ///
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self, self.cache.iter())
+    }
+
+    /// Creates an iterator visiting all key-value pairs in arbitrary order, like
+    /// the `iter` method, but yielding the keys as `Rc<K>`.
+    ///
+    /// The keys are stored in `Rc`s internally, so each yielded key is a cheap
+    /// clone of the stored `Rc` sharing the same key. This allows to hold onto
+    /// the keys past the borrow of the cache, without `K: Clone` or deep clones of
+    /// large keys.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    /// use std::rc::Rc;
+    ///
+    /// let mut cache = Cache::new(100);
+    /// cache.insert("Julia".to_string(), 14);
+    ///
+    /// let keys: Vec<Rc<String>> = cache.iter_rc().map(|(k, _)| k).collect();
+    /// cache.invalidate_all();
+    /// assert_eq!(*keys[0], "Julia");
+    /// ```
+    ///
+    pub fn iter_rc(&self) -> impl Iterator<Item = (Rc<K>, &V)> + '_ {
+        // Skip the tombstones of negatively cached keys.
+        self.cache
+            .iter()
+            .filter_map(|(k, entry)| entry.value.as_ref().map(|v| (Rc::clone(k), v)))
+    }
+
+    /// Creates an iterator visiting all key-value pairs in the order they would be
+    /// evicted, from the least recently used to the most recently used, with
+    /// mutable references to the values.
+    ///
+    /// The entries in the probation region are visited before the ones in the
+    /// protected region. This is useful for maintenance that must respect the
+    /// recency, e.g. decaying the values of the hottest entries last.
+    ///
+    /// Visiting an entry is not recorded as an access; the popularity estimator
+    /// and the LRU order are not updated. The order is computed upfront, which
+    /// takes `O(n log n)` time and an allocation proportional to the entry count.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 0);
+    /// cache.insert('b', 0);
+    /// cache.insert('c', 0);
+    /// cache.get(&'a');
+    ///
+    /// for (i, (_, v)) in cache.iter_ordered_mut().enumerate() {
+    ///     *v = i;
+    /// }
+    /// assert_eq!(cache.get(&'b'), Some(&0));
+    /// assert_eq!(cache.get(&'c'), Some(&1));
+    /// assert_eq!(cache.get(&'a'), Some(&2));
+    /// ```
+    ///
+    pub fn iter_ordered_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> + '_ {
+        // Rank the keys by their position in the access-order queues. The nodes
+        // share the `Rc` of the key with the hash map, so the pointer of the key
+        // identifies the entry. The values cannot be looked up one at a time
+        // while yielding `&mut V`s, so sort the mutable references by the ranks
+        // instead.
+        let deqs = [
+            &self.deques.window,
+            &self.deques.probation,
+            &self.deques.protected,
+        ];
+        let ranks = deqs
+            .iter()
+            .flat_map(|deq| deq.iter())
+            .enumerate()
+            .map(|(rank, kh)| (Rc::as_ptr(&kh.key), rank))
+            .collect::<HashMap<_, _>>();
+
+        let mut entries = self
+            .cache
+            .iter_mut()
+            .filter_map(|(k, entry)| {
+                // Skip the tombstones of negatively cached keys.
+                let v = entry.value.as_mut()?;
+                let rank = ranks.get(&Rc::as_ptr(k)).copied().unwrap_or(usize::MAX);
+                Some((rank, &**k, v))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(rank, _, _)| *rank);
+
+        entries.into_iter().map(|(_, k, v)| (k, v))
+    }
+
+    /// Returns a batch of up to 100 cloned entries, starting from the position
+    /// of the given cursor (or from the beginning if `None`), together with a
+    /// cursor to resume from. The returned cursor is `None` when the iteration
+    /// is complete.
+    ///
+    /// Unlike `iter`, this does not hold a borrow of the cache between batches,
+    /// so a large cache can be iterated in chunks across multiple calls.
+    ///
+    /// The cursor is a position in the iteration order of the internal hash
+    /// map. If the cache is mutated between the calls, entries may be skipped or
+    /// returned more than once.
+    ///
+    /// # Performance
+    ///
+    /// The cursor is not a real resume point: each call walks the hash map
+    /// from the beginning up to the cursor position. So a single batch costs
+    /// O(position), and a complete pass over `n` entries costs O(n² / 100). For
+    /// a full pass over a large cache that can be borrowed for the whole time,
+    /// prefer `iter`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(1000);
+    /// for i in 0..250 {
+    ///     cache.insert(i, i * 10);
+    /// }
+    ///
+    /// let mut entries = Vec::new();
+    /// let mut cursor = None;
+    /// loop {
+    ///     let (batch, next) = cache.iter_from(cursor);
+    ///     entries.extend(batch);
+    ///     match next {
+    ///         Some(next) => cursor = Some(next),
+    ///         None => break,
+    ///     }
+    /// }
+    /// assert_eq!(entries.len(), 250);
+    /// ```
+    ///
+    pub fn iter_from(&self, cursor: Option<Cursor>) -> (Vec<(K, V)>, Option<Cursor>)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let start = cursor.map_or(0, |c| c.position());
+        let mut iter = self.cache.iter().skip(start);
+        let mut batch = Vec::with_capacity(ITER_FROM_BATCH_SIZE);
+        let mut position = start;
+
+        while batch.len() < ITER_FROM_BATCH_SIZE {
+            let Some((key, entry)) = iter.next() else {
+                return (batch, None);
+            };
+            position += 1;
+            // Skip the tombstones of negatively cached keys.
+            if let Some(value) = &entry.value {
+                batch.push((K::clone(key), value.clone()));
+            }
+        }
+
+        if position < self.cache.len() {
+            (batch, Some(Cursor::new(position)))
+        } else {
+            (batch, None)
+        }
+    }
+
+    /// Consumes the cache and returns a read-only [`FrozenCache`][frozen-struct]
+    /// with its values.
+    ///
+    /// The access-order queues and the historic popularity estimator are dropped,
+    /// so the frozen cache has no bookkeeping per read. This is useful once the
+    /// cache has been warmed up and no longer changes. The tombstones of
+    /// negatively cached keys are dropped, and the pending values inserted by
+    /// [`insert_lazy`](#method.insert_lazy) are computed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    ///
+    /// let frozen = cache.freeze();
+    /// assert_eq!(frozen.get(&'a'), Some(&"alice"));
+    /// assert_eq!(frozen.len(), 2);
+    /// ```
+    ///
+    /// [frozen-struct]: ./struct.FrozenCache.html
+    pub fn freeze(self) -> FrozenCache<K, V, S> {
+        let Self {
+            cache,
+            deques,
+            build_hasher,
+            ..
+        } = self;
+        // Release the keys held by the nodes.
+        drop(deques);
+
+        let mut map = HashMap::with_capacity_and_hasher(cache.len(), build_hasher);
+        map.extend(cache.into_iter().filter_map(|(key, mut entry)| {
+            entry.force();
+            entry.value.map(|value| (key, value))
+        }));
+        FrozenCache::new(map)
+    }
+
+    /// Consumes the cache and rebuilds it with the given hasher, keeping the
+    /// entries, their LRU order, the max capacity and the insert policy.
+    ///
+    /// This is useful to switch to a different hash function, e.g. when the keys
+    /// cause many hash collisions. The hashes of the keys are recomputed with the
+    /// new hasher, so the historic popularity estimator starts over.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    /// use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+    ///
+    /// let mut cache = Cache::new(100);
+    /// cache.insert("Julia", 14);
+    ///
+    /// let mut cache = cache.rehash(BuildHasherDefault::<DefaultHasher>::default());
+    /// assert_eq!(cache.get(&"Julia"), Some(&14));
+    /// ```
+    ///
+    pub fn rehash<S2>(mut self, hasher: S2) -> Cache<K, V, S2>
+    where
+        S2: BuildHasher + Clone,
+    {
+        let mut new = Cache::with_everything(
+            self.settings.clone(),
+            Some(self.cache.len()),
+            None,
+            self.eviction_listener.take(),
+            self.cost_fn.take(),
+            hasher,
+        );
+        // The hashes change, so the recorded popularity is meaningless.
+        new.frequency_sketch = std::mem::take(&mut self.frequency_sketch);
+        new.frequency_sketch.reset();
+
+        let old_deqs = std::mem::take(&mut self.deques);
+        let regions = [
+            (CacheRegion::Window, &old_deqs.window),
+            (CacheRegion::MainProbation, &old_deqs.probation),
+            (CacheRegion::MainProtected, &old_deqs.protected),
+        ];
+
+        for (region, deq) in regions {
+            for kh in deq.iter() {
+                // Skip orphaned nodes whose entries were already removed.
+                if let Some(entry) = self.cache.remove(&kh.key) {
+                    let key = Rc::clone(&kh.key);
+                    let hash = new.hash(&key);
+                    let mut entry = entry.into_unlinked();
+                    new.deques.push_back_ao(
+                        region,
+                        KeyHashDate::new(Rc::clone(&key), hash),
+                        &mut entry,
+                    );
+                    new.cache.insert(key, entry);
+                    new.entry_count += 1;
+                    // The hashes change, so only the current keys can be counted.
+                    if let Some(estimator) = &mut new.distinct_keys {
+                        estimator.add(hash);
+                    }
+                }
+            }
+        }
+
+        for kh in old_deqs.write_order.iter() {
+            let hash = new.hash(&kh.key);
+            if let Some(entry) = new.cache.get_mut(&kh.key) {
+                new.deques
+                    .push_back_wo(KeyHashDate::new(Rc::clone(&kh.key), hash), entry);
+            }
+        }
+
+        // Keep a preallocated sketch enabled.
+        if self.frequency_sketch_enabled || new.should_enable_frequency_sketch() {
+            new.enable_frequency_sketch();
+        }
+        new.hit_rate = self.hit_rate.clone();
+        new.version = self.version;
+        new.total_evicted = self.total_evicted;
+        new.total_rejected = self.total_rejected;
+        new.total_cost_served = self.total_cost_served;
+        new.debug_assert_entry_count();
+        new
+    }
+
+    /// Clones the cache into a new cache with the given max capacity (`None` for
+    /// unbounded), keeping the other settings, the LRU order of the entries and
+    /// the state of the historic popularity estimator.
+    ///
+    /// If the new max capacity is smaller than the number of entries, only the
+    /// most recently used entries that fit are copied. The pending values
+    /// inserted by [`insert_lazy`](#method.insert_lazy) are not copied, as their
+    /// initializers cannot be cloned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', "alice");
+    /// cache.insert('b', "bob");
+    /// cache.insert('c', "cindy");
+    ///
+    /// let mut small = cache.clone_with_capacity(Some(2));
+    /// assert_eq!(small.entry_count(), 2);
+    /// assert!(!small.contains_key(&'a'));
+    /// assert_eq!(cache.entry_count(), 3);
+    /// ```
+    ///
+    pub fn clone_with_capacity(&self, max_capacity: Option<u64>) -> Cache<K, V, S>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let settings = CacheSettings {
+            max_capacity,
+            ..self.settings.clone()
+        };
+        let mut new = Cache::with_everything(
+            settings,
+            None,
+            None,
+            self.eviction_listener.clone(),
+            self.cost_fn.clone(),
+            self.build_hasher.clone(),
+        );
+
+        // The nodes from the least recently used to the most recently used.
+        let deqs = [
+            (CacheRegion::Window, &self.deques.window),
+            (CacheRegion::MainProbation, &self.deques.probation),
+            (CacheRegion::MainProtected, &self.deques.protected),
+        ];
+        let nodes = deqs
+            .iter()
+            .flat_map(|(region, deq)| deq.iter().map(move |kh| (*region, kh)))
+            .filter_map(|(region, kh)| {
+                // Skip orphaned nodes whose entries were already removed, and
+                // the pending values, as their initializers cannot be cloned.
+                let entry = self.cache.get(&kh.key).filter(|e| !e.is_pending())?;
+                Some((region, kh, entry))
+            })
+            .collect::<Vec<_>>();
+        let skip = max_capacity.map_or(0, |max| (nodes.len() as u64).saturating_sub(max) as usize);
+
+        for (region, kh, entry) in nodes.into_iter().skip(skip) {
+            let Some(mut entry) = entry.clone_unlinked() else {
+                continue;
+            };
+            let key = Rc::new(K::clone(&kh.key));
+            new.deques.push_back_ao(
+                region,
+                KeyHashDate::new(Rc::clone(&key), kh.hash),
+                &mut entry,
+            );
+            new.cache.insert(key, entry);
+            new.entry_count += 1;
+        }
+
+        for kh in self.deques.write_order.iter() {
+            // Skip the entries that were not copied.
+            let Some((key, _)) = new.cache.get_key_value(&*kh.key) else {
+                continue;
+            };
+            let key = Rc::clone(key);
+            let entry = new.cache.get_mut(&key).unwrap();
+            new.deques
+                .push_back_wo(KeyHashDate::new(Rc::clone(&key), kh.hash), entry);
+        }
+
+        // The hashes are the same as the hasher is cloned.
+        new.frequency_sketch = self.frequency_sketch.clone();
+        new.hit_rate = self.hit_rate.clone();
+        new.distinct_keys = self.distinct_keys.clone();
+        // Keep the versions at which the entries were modified meaningful.
+        new.version = self.version;
+        if self.frequency_sketch_enabled {
+            new.frequency_sketch_enabled = true;
+            if let Some(max) = max_capacity {
+                // Grow the sketch if the new capacity is larger. This is a no-op
+                // otherwise.
+                new.do_enable_frequency_sketch(max);
+            }
+        } else if new.should_enable_frequency_sketch() {
+            new.enable_frequency_sketch();
+        }
+        new.debug_assert_entry_count();
+        new
+    }
+
+    /// Consumes the cache and splits it into two caches: the first with the
+    /// entries for which `pred` returns `true`, and the second with the others.
+    ///
+    /// Both caches inherit the max capacity, the other settings, the hasher and
+    /// the historic popularity estimator. The entries are inserted into them from
+    /// the least recently used, going through the normal admission. So if the
+    /// cache was over its max capacity (e.g. after lowering it by
+    /// `set_max_capacity`), some entries may be evicted or rejected by either
+    /// half, and the evicted ones are passed to the eviction listener of the
+    /// cache. The tombstones of negatively cached keys are dropped, and the
+    /// pending values inserted by [`insert_lazy`](#method.insert_lazy) are
+    /// computed to be passed to `pred`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use micro_moka::unsync::Cache;
+    ///
+    /// let mut cache = Cache::new(10);
+    /// cache.insert('a', 1);
+    /// cache.insert('b', 2);
+    /// cache.insert('c', 3);
+    ///
+    /// let (mut odd, mut even) = cache.partition(|_, v| v % 2 == 1);
+    /// assert_eq!(odd.entry_count(), 2);
+    /// assert_eq!(even.get(&'b'), Some(&2));
+    /// ```
+    ///
+    pub fn partition<F>(mut self, pred: F) -> (Self, Self)
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        let make_half = || {
+            let settings = CacheSettings {
+                // The insertion order is restored below.
+                track_insertion_order: false,
+                ..self.settings.clone()
+            };
+            let mut half = Cache::with_everything(
+                settings,
+                None,
+                None,
+                self.eviction_listener.clone(),
+                self.cost_fn.clone(),
+                self.build_hasher.clone(),
+            );
+            // The hashes are the same as the hasher is cloned.
+            half.frequency_sketch = self.frequency_sketch.clone();
+            half.frequency_sketch_enabled = self.frequency_sketch_enabled;
+            half.distinct_keys = self.distinct_keys.clone();
+            half
+        };
+        let (mut left, mut right) = (make_half(), make_half());
+
+        let old_deqs = std::mem::take(&mut self.deques);
+        let nodes = old_deqs
+            .window
+            .iter()
+            .chain(old_deqs.probation.iter())
+            .chain(old_deqs.protected.iter());
+        for kh in nodes {
+            // Skip orphaned nodes whose entries were already removed.
+            let Some((key, mut entry)) = self.cache.remove_entry(&kh.key) else {
+                continue;
+            };
+            entry.force();
+            let Some(value) = &entry.value else {
+                continue;
+            };
+            let half = if pred(&key, value) {
+                &mut left
+            } else {
+                &mut right
+            };
+            let modified_at_version = entry.modified_at_version();
+            half.insert_rc_entry(Rc::clone(&key), entry.into_unlinked());
+            // The insert is not a modification of the value.
+            if let Some(entry) = half.cache.get_mut(&key) {
+                entry.set_modified_at_version(modified_at_version);
+            }
+        }
+        left.version = self.version;
+        right.version = self.version;
+
+        if self.settings.track_insertion_order {
+            left.settings.track_insertion_order = true;
+            right.settings.track_insertion_order = true;
+            for kh in old_deqs.write_order.iter() {
+                let half = if left.cache.contains_key(&kh.key) {
+                    &mut left
+                } else {
+                    &mut right
+                };
+                if let Some(entry) = half.cache.get_mut(&kh.key) {
+                    half.deques
+                        .push_back_wo(KeyHashDate::new(Rc::clone(&kh.key), kh.hash), entry);
+                }
+            }
+        }
+
+        left.debug_assert_entry_count();
+        right.debug_assert_entry_count();
+        (left, right)
+    }
+}
+
+//
+// private
+//
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone,
+{
+    #[inline]
+    fn hash<Q>(&self, key: &Q) -> u64
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.build_hasher.hash_one(key)
+    }
+
+    fn has_value<Q>(&self, key: &Q) -> bool
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache
+            .get(key)
+            .is_some_and(|entry| entry.value.is_some() || entry.is_pending())
+    }
+
+    fn record_hit(
+        policy: EvictionPolicy,
+        promotion_threshold: Option<u8>,
+        deques: &mut Deques<K>,
+        entry: &mut ValueEntry<K, V>,
+    ) {
+        match policy {
+            EvictionPolicy::TinyLfu => {
+                if let Some(threshold) = promotion_threshold {
+                    let in_probation = entry
+                        .access_order_q_node()
+                        .is_some_and(|node| CacheRegion::MainProbation == node.decompose_tag());
+                    if in_probation && entry.increment_hits() >= threshold {
+                        deques.move_to_region_ao(CacheRegion::MainProtected, entry);
+                        return;
+                    }
+                }
+                deques.move_to_back_ao(entry)
+            }
+            // Only set the reference bit. The eviction sweep will give the entry
+            // a second chance.
+            EvictionPolicy::Clock => entry.set_referenced(),
+        }
+    }
+
+    /// Advances the hand of the CLOCK eviction policy: moves the entries at the
+    /// front of the probation deque whose reference bit is set to the back,
+    /// clearing the bit, until the front entry has a clear bit. The front entry is
+    /// then the eviction victim.
+    fn sweep_clock_hand(deq: &mut Deque<KeyHashDate<K>>, cache: &mut CacheStore<K, V, S>) {
+        // Every entry is visited at most once, as its bit is cleared on the way.
+        for _ in 0..deq.len() {
+            let Some(node) = deq.peek_front_ptr() else {
+                break;
+            };
+            let key = unsafe { &node.as_ref().element.key };
+            let referenced = cache
+                .get_mut(key)
+                .is_some_and(|entry| entry.take_referenced());
+            if !referenced {
+                break;
+            }
+            unsafe { deq.move_to_back(node) };
+        }
+    }
+
+    /// Checks that `entry_count` is in sync with the hash map. This is a no-op
+    /// unless debug assertions are enabled.
+    #[inline]
+    fn debug_assert_entry_count(&self) {
+        debug_assert_eq!(
+            self.entry_count as usize,
+            self.cache.len(),
+            "entry_count is out of sync with the map"
+        );
+    }
+
+    fn has_enough_capacity(&self, candidate_weight: u32, ws: u64) -> bool {
+        self.settings
+            .max_capacity
+            // Avoid overflowing when the limit is close to `u64::MAX`.
+            .map(|limit| {
+                ws.checked_add(candidate_weight as u64)
+                    .is_some_and(|sum| sum <= limit)
+            })
+            .unwrap_or(true)
+    }
+
+    fn weights_to_evict(&self) -> u64 {
+        self.settings
+            .max_capacity
+            .map(|limit| self.entry_count.saturating_sub(limit))
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    fn should_enable_frequency_sketch(&self) -> bool {
+        // The CLOCK policy does not use the popularity estimator.
+        if self.frequency_sketch_enabled || self.settings.eviction_policy == EvictionPolicy::Clock {
+            false
+        } else if let Some(max_cap) = self.settings.max_capacity {
+            let threshold = self
+                .settings
+                .frequency_sketch_activation
+                .map_or(max_cap / 2, |fraction| (max_cap as f64 * fraction) as u64);
+            self.entry_count >= threshold
+        } else {
+            false
+        }
+    }
+
+    /// Reserves room for at least `additional` more entries in the hash map,
+    /// returning an error instead of panicking if the allocation fails.
+    pub(crate) fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.cache.try_reserve(additional)
+    }
+
+    /// Enables the frequency sketch regardless of the entry count, unless the
+    /// policy does not use it.
+    pub(crate) fn preallocate_frequency_sketch(&mut self) {
+        if self.settings.eviction_policy != EvictionPolicy::Clock {
+            self.enable_frequency_sketch();
+        }
+    }
+
+    #[inline]
+    fn enable_frequency_sketch(&mut self) {
+        if let Some(max_cap) = self.settings.max_capacity {
+            self.do_enable_frequency_sketch(max_cap);
+        }
+    }
+
+    #[cfg(test)]
+    fn enable_frequency_sketch_for_testing(&mut self) {
+        if let Some(max_cap) = self.settings.max_capacity {
+            self.do_enable_frequency_sketch(max_cap);
+        }
+    }
+
+    #[inline]
+    fn do_enable_frequency_sketch(&mut self, cache_capacity: u64) {
+        let capacity = self
+            .settings
+            .expected_key_cardinality
+            .map_or(cache_capacity, |cardinality| {
+                cardinality.max(cache_capacity)
+            });
+        self.frequency_sketch.ensure_capacity(capacity);
+        self.frequency_sketch_enabled = true;
+    }
+
+    #[inline]
+    fn handle_insert(
+        &mut self,
+        key: Rc<K>,
+        hash: u64,
+        policy_weight: u32,
+        on_evict: &mut impl FnMut(Rc<K>, ValueEntry<K, V>),
+    ) -> UpsertResult<Option<V>> {
+        debug_assert_eq!(policy_weight, 1);
+        let has_free_space = self.has_enough_capacity(policy_weight, self.entry_count);
+        let (cache, deqs, freq) = (&mut self.cache, &mut self.deques, &self.frequency_sketch);
+
+        if has_free_space {
+            // Add the candidate to the deque.
+            let key = Rc::clone(&key);
+            let entry = cache.get_mut(&key).unwrap();
+            deqs.push_back_ao(
+                CacheRegion::MainProbation,
+                KeyHashDate::new(Rc::clone(&key), hash),
+                entry,
+            );
+            if self.settings.track_insertion_order {
+                deqs.push_back_wo(KeyHashDate::new(Rc::clone(&key), hash), entry);
+            }
+            self.entry_count += 1;
+            self.version += 1;
+            entry.set_modified_at_version(self.version);
+            // self.saturating_add_to_total_weight(policy_weight as u64);
+
+            if self.should_enable_frequency_sketch() {
+                self.enable_frequency_sketch();
+            }
+
+            return UpsertResult::Inserted;
+        }
+
+        if let Some(max) = self.settings.max_capacity {
+            if policy_weight as u64 > max {
+                // The candidate is too big to fit in the cache. Reject it.
+                let entry = cache.remove(&Rc::clone(&key)).unwrap();
+                return UpsertResult::TooLarge(entry.value);
+            }
+        }
+
+        if self.settings.insert_policy == InsertPolicy::RejectWhenFull {
+            // Keep the existing entries and reject the candidate.
+            let entry = cache.remove(&key).unwrap();
+            return UpsertResult::AdmissionRejected(entry.value);
+        }
+
+        // Remove the victim from the hash map.
+        let (vic_key, mut vic_entry) = loop {
+            let admission = match self.settings.eviction_policy {
+                EvictionPolicy::TinyLfu => Self::admit(freq.frequency(hash), deqs, freq),
+                EvictionPolicy::Clock => {
+                    // Admit the candidate, evicting the entry under the clock hand.
+                    Self::sweep_clock_hand(&mut deqs.probation, cache);
+                    match deqs.probation.peek_front_ptr() {
+                        Some(victim_node) => AdmissionResult::Admitted { victim_node },
+                        None => AdmissionResult::Rejected,
+                    }
+                }
+            };
+
+            let AdmissionResult::Admitted { victim_node } = admission else {
+                // Remove the candidate from the cache.
+                let entry = cache.remove(&key).unwrap();
+                return UpsertResult::AdmissionRejected(entry.value);
+            };
+            if let Some(victim) = cache.remove_entry(unsafe { &victim_node.as_ref().element.key }) {
+                break victim;
+            }
+            assert!(
+                self.settings.skip_orphaned_victims,
+                "Cannot remove a victim from the hash map"
+            );
+            // The victim node is orphaned. Drop it and pick the next victim. The
+            // victim deque is the same one, as the deques were not modified.
+            unsafe { deqs.victim_deque_mut().unlink_and_drop(victim_node) };
+        };
+
+        // Remove the victim from the deque.
+        deqs.unlink_ao(&mut vic_entry);
+        deqs.unlink_wo(&mut vic_entry);
+        self.entry_count -= 1;
+        self.total_evicted += 1;
+        on_evict(vic_key, vic_entry);
+
+        // Add the candidate to the deque.
+        let entry = cache.get_mut(&key).unwrap();
+        let key = Rc::clone(&key);
+        deqs.push_back_ao(
+            CacheRegion::MainProbation,
+            KeyHashDate::new(Rc::clone(&key), hash),
+            entry,
+        );
+        if self.settings.track_insertion_order {
+            deqs.push_back_wo(KeyHashDate::new(Rc::clone(&key), hash), entry);
+        }
+
+        self.entry_count += 1;
+        self.version += 1;
+        entry.set_modified_at_version(self.version);
+        // Self::saturating_sub_from_total_weight(self, victims_weight);
+        // Self::saturating_add_to_total_weight(self, policy_weight as u64);
+
+        if self.should_enable_frequency_sketch() {
+            self.enable_frequency_sketch();
+        }
+
+        UpsertResult::Inserted
+    }
+
+    /// Performs admission explained in the paper:
+    /// [Lightweight Robust Size Aware Cache Management][size-aware-cache-paper]
+    /// by Gil Einziger, Ohad Eytan, Roy Friedman, Ben Manes.
+    ///
+    /// [size-aware-cache-paper]: https://arxiv.org/abs/2105.08770
+    ///
+    /// In Micro Moka all policy weights are 1, so admission compares the candidate
+    /// with the single LRU victim in probation.
+    ///
+    #[inline]
+    fn admit(candidate_freq: u8, deqs: &Deques<K>, freq: &Estimator) -> AdmissionResult<K> {
+        let Some(victim_node) = deqs.victim_deque().peek_front_ptr() else {
+            return AdmissionResult::Rejected;
+        };
+        let victim_hash = unsafe { victim_node.as_ref() }.element.hash;
+        let victim_freq = freq.frequency(victim_hash);
+
+        // TODO: Implement some randomness to mitigate hash DoS attack.
+        // See Caffeine's implementation.
+
+        if candidate_freq > victim_freq {
+            AdmissionResult::Admitted { victim_node }
+        } else {
+            AdmissionResult::Rejected
+        }
+    }
+
+    /// Returns the old value, or `None` if the old entry was a tombstone.
+    fn handle_update(
+        &mut self,
+        key: Rc<K>,
+        policy_weight: u32,
+        mut old_entry: ValueEntry<K, V>,
+    ) -> Option<V> {
+        let entry = self.cache.get_mut(&key).unwrap();
+        entry.replace_deq_nodes_with(&mut old_entry);
+        entry.set_policy_weight(policy_weight);
+
+        if self.settings.update_refreshes_recency {
+            Self::record_hit(
+                self.settings.eviction_policy,
+                self.settings.promotion_threshold,
+                &mut self.deques,
+                entry,
+            );
+        }
+        self.version += 1;
+        entry.set_modified_at_version(self.version);
+
+        // self.saturating_sub_from_total_weight(old_policy_weight as u64);
+        // self.saturating_add_to_total_weight(policy_weight as u64);
+
+        // A pending value is computed to be returned as the old value. This runs
+        // last, so that the cache is consistent if the initializer panics.
+        old_entry.force();
+        old_entry.value.take()
+    }
+
+    /// Evicts the entries over the max capacity like `evict_lru_entries`, or
+    /// only marks the maintenance as due if the eviction is lazy or scheduled by
+    /// the maintenance interval.
+    #[inline]
+    fn evict_lru_entries_on_read(&mut self) {
+        if !self.settings.lazy_eviction && self.settings.maintenance_interval.is_none() {
+            self.evict_lru_entries();
+        } else if self.weights_to_evict() > 0 {
+            self.maintenance_due = true;
+        }
+    }
+
+    /// Runs the maintenance for a write operation like `evict_lru_entries`,
+    /// unless it is deferred by the maintenance interval.
+    #[inline]
+    fn run_scheduled_maintenance(&mut self) {
+        if !self.defer_maintenance(false) {
+            self.evict_lru_entries();
+        }
+    }
+
+    /// Counts a write operation towards the maintenance interval, and returns
+    /// `true` if the maintenance is not due for it yet. The entries over the
+    /// max capacity are then only marked as due.
+    ///
+    /// An insert into a cache over its max capacity always runs the maintenance
+    /// to restore the bound.
+    fn defer_maintenance(&mut self, is_insert: bool) -> bool {
+        let Some(interval) = self.settings.maintenance_interval else {
+            return false;
+        };
+        self.ops_since_maintenance += 1;
+        let must_evict = is_insert && self.weights_to_evict() > 0;
+        if self.ops_since_maintenance < interval && !must_evict {
+            if self.weights_to_evict() > 0 {
+                self.maintenance_due = true;
+            }
+            return true;
+        }
+        self.ops_since_maintenance = 0;
+        false
+    }
+
+    #[inline]
+    fn evict_lru_entries(&mut self) {
+        if self.eviction_listener.is_none() {
+            self.evict_lru_entries_with(&mut |_, _| {});
+            return;
+        }
+
+        let mut batch = Vec::new();
+        self.evict_lru_entries_with(&mut |key, entry| {
+            if let Some(value) = entry.value {
+                batch.push((key, value));
+            }
+        });
+        self.notify_evicted(batch);
+    }
+
+    /// Evicts entries while the cache is over its max capacity, up to
+    /// `EVICTION_BATCH_SIZE` entries, calling `on_evict` for each evicted entry.
+    #[inline]
+    fn evict_lru_entries_with(&mut self, on_evict: &mut impl FnMut(Rc<K>, ValueEntry<K, V>)) {
+        self.demote_from_protected();
+
+        let weights_to_evict = self.weights_to_evict();
+        let is_clock = self.settings.eviction_policy == EvictionPolicy::Clock;
+        let mut evicted_count = 0u64;
+        let mut evicted_policy_weight = 0u64;
+
+        {
+            let (deqs, cache) = (&mut self.deques, &mut self.cache);
+
+            for _ in 0..EVICTION_BATCH_SIZE {
+                if evicted_policy_weight >= weights_to_evict {
+                    break;
+                }
+
+                if is_clock {
+                    Self::sweep_clock_hand(&mut deqs.probation, cache);
+                }
+
+                let victims = deqs.victim_deque_mut();
+                // clippy::map_clone will give us a false positive warning here.
+                // Version: clippy 0.1.77 (f2048098a1c 2024-02-09) in Rust 1.77.0-beta.2
+                #[allow(clippy::map_clone)]
+                let key = victims
+                    .peek_front()
+                    .map(|node| Rc::clone(&node.element.key));
+
+                if key.is_none() {
+                    break;
+                }
+                let key = key.unwrap();
+
+                if let Some(mut entry) = cache.remove(&key) {
+                    let weight = entry.policy_weight();
+                    deqs.unlink_ao(&mut entry);
+                    deqs.unlink_wo(&mut entry);
+                    evicted_count += 1;
+                    evicted_policy_weight = evicted_policy_weight.saturating_add(weight as u64);
+                    on_evict(key, entry);
+                } else {
+                    victims.pop_front();
+                }
+            }
+        }
+
+        self.entry_count -= evicted_count;
+        self.total_evicted += evicted_count;
+        // self.saturating_sub_from_total_weight(evicted_policy_weight);
+        if evicted_count > 0 {
+            self.version += 1;
+        }
+        if self.weights_to_evict() == 0 {
+            self.maintenance_due = false;
+        }
+        self.debug_assert_entry_count();
+    }
+
+    fn compact_deque(&mut self, region: CacheRegion) {
+        let deq = match region {
+            CacheRegion::Window => &mut self.deques.window,
+            CacheRegion::MainProbation => &mut self.deques.probation,
+            CacheRegion::MainProtected => &mut self.deques.protected,
+            CacheRegion::Other => unreachable!(),
+        };
+        // The new deque allocates its nodes in a fresh slab, so the nodes will be
+        // packed in a few contiguous chunks.
+        let mut old_deq = std::mem::replace(deq, Deque::new(region));
+
+        for kh in &mut old_deq {
+            // Skip orphaned nodes whose entries were already removed.
+            if let Some(entry) = self.cache.get_mut(&kh.key) {
+                self.deques.push_back_ao(
+                    region,
+                    KeyHashDate::new(Rc::clone(&kh.key), kh.hash),
+                    entry,
+                );
+            }
+        }
+    }
+
+    fn prune_deque(
+        deq: &mut Deque<KeyHashDate<K>>,
+        cache: &CacheStore<K, V, S>,
+        is_live: impl Fn(&ValueEntry<K, V>, NonNull<DeqNode<KeyHashDate<K>>>) -> bool,
+    ) -> u64 {
+        let mut pruned = 0;
+        let mut next = deq.peek_front_ptr();
+        while let Some(node) = next {
+            next = DeqNode::next_node_ptr(node);
+            let key = unsafe { &node.as_ref().element.key };
+            if !cache.get(key).is_some_and(|entry| is_live(entry, node)) {
+                // Safety: The node is in this deque, and `next` was read before
+                // dropping it.
+                unsafe { deq.unlink_and_drop(node) };
+                pruned += 1;
+            }
+        }
+        pruned
+    }
+
+    /// Removes the entry at the front (LRU end) of the probation deque (or the
+    /// protected deque if probation is empty) from both the hash map and the
+    /// deque, and returns it. Under the CLOCK policy, the
+    /// referenced entries at the front are given a second chance first.
+    fn pop_lru_entry(&mut self) -> Option<(Rc<K>, ValueEntry<K, V>)> {
+        let is_clock = self.settings.eviction_policy == EvictionPolicy::Clock;
+        let (deqs, cache) = (&mut self.deques, &mut self.cache);
+
+        loop {
+            if is_clock {
+                Self::sweep_clock_hand(&mut deqs.probation, cache);
+            }
+
+            let victims = deqs.victim_deque_mut();
+            // clippy::map_clone will give us a false positive warning here.
+            #[allow(clippy::map_clone)]
+            let key = victims
+                .peek_front()
+                .map(|node| Rc::clone(&node.element.key))?;
+
+            if let Some(mut entry) = cache.remove(&key) {
+                deqs.unlink_ao(&mut entry);
+                deqs.unlink_wo(&mut entry);
+                self.entry_count -= 1;
+                self.total_evicted += 1;
+                self.version += 1;
+                return Some((key, entry));
+            } else {
+                victims.pop_front();
+            }
+        }
+    }
+
+    /// Returns the max number of entries in the protected region, or `None` if
+    /// the region is not used.
+    fn protected_capacity(&self) -> Option<u64> {
+        self.settings.promotion_threshold?;
+        if let Some(capacity) = self.settings.protected_capacity {
+            return Some(capacity);
+        }
+        // 80% of the max capacity, like Caffeine.
+        self.settings.max_capacity.map(|max| max - max.div_ceil(5))
+    }
+
+    /// Demotes the LRU entries of the protected region to the back of the
+    /// probation region, while the protected region is over its capacity.
+    fn demote_from_protected(&mut self) {
+        let Some(capacity) = self.protected_capacity() else {
+            return;
+        };
+        let (deqs, cache) = (&mut self.deques, &mut self.cache);
+
+        while deqs.protected.len() as u64 > capacity {
+            // clippy::map_clone will give us a false positive warning here.
+            #[allow(clippy::map_clone)]
+            let Some(key) = deqs
+                .protected
+                .peek_front()
+                .map(|node| Rc::clone(&node.element.key))
+            else {
+                break;
+            };
+
+            if let Some(entry) = cache.get_mut(&key) {
+                // The entry has to earn its promotion again.
+                entry.reset_hits();
+                deqs.move_to_region_ao(CacheRegion::MainProbation, entry);
+            } else {
+                deqs.protected.pop_front();
+            }
+        }
+    }
+}
+
+//
+// for testing
+//
+#[cfg(test)]
+impl<K, V, S> Cache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone,
+{
+}
+
+/// The result of [`Cache::get_state`](./struct.Cache.html#method.get_state).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lookup<V> {
+    /// The cache has a value for the key.
+    Hit(V),
+    /// The key is negatively cached, i.e. known to be absent.
+    NegativeHit,
+    /// The cache knows nothing about the key.
+    Miss,
+}
+
+/// The outcome of [`Cache::insert_detailed`](./struct.Cache.html#method.insert_detailed).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpsertResult<V> {
+    /// The key was not in the cache (or was negatively cached), and the value was
+    /// inserted.
+    Inserted,
+    /// The key was in the cache, and its value was replaced. Holds the old value.
+    Updated(V),
+    /// The cache was full, and the value was rejected by the admission policy or
+    /// the [`InsertPolicy`][insert-policy-enum]. Holds the rejected value.
+    ///
+    /// [insert-policy-enum]: ../enum.InsertPolicy.html
+    AdmissionRejected(V),
+    /// The value can never fit in the cache (e.g. its max capacity is zero). Holds
+    /// the rejected value.
+    TooLarge(V),
+}
+
+impl<V> UpsertResult<V> {
+    pub(crate) fn map<U>(self, f: impl FnOnce(V) -> U) -> UpsertResult<U> {
+        match self {
+            Self::Inserted => UpsertResult::Inserted,
+            Self::Updated(v) => UpsertResult::Updated(f(v)),
+            Self::AdmissionRejected(v) => UpsertResult::AdmissionRejected(f(v)),
+            Self::TooLarge(v) => UpsertResult::TooLarge(f(v)),
+        }
+    }
+}
+
+/// The entry count and the weighted size of a cache, returned by
+/// [`Cache::size_info`](./struct.Cache.html#method.size_info).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeInfo {
+    entry_count: u64,
+    weighted_size: u64,
+}
+
+impl SizeInfo {
+    /// Returns the number of entries in the cache.
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Returns the total weighted size of the entries in the cache.
+    pub fn weighted_size(&self) -> u64 {
+        self.weighted_size
+    }
+}
+
+/// A summary of the counts and the capacity of a cache, returned by
+/// [`Cache::summary`](./struct.Cache.html#method.summary).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheSummary {
+    entry_count: u64,
+    max_capacity: Option<u64>,
+    total_evicted: u64,
+    total_rejected: u64,
+}
+
+impl CacheSummary {
+    /// Returns the number of entries in the cache.
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Returns the max capacity of the cache, or `None` if it is unbounded.
+    pub fn max_capacity(&self) -> Option<u64> {
+        self.max_capacity
+    }
+
+    /// Returns the number of entries evicted since the creation of the cache.
+    pub fn total_evicted(&self) -> u64 {
+        self.total_evicted
+    }
+
+    /// Returns the number of new entries rejected since the creation of the
+    /// cache.
+    pub fn total_rejected(&self) -> u64 {
+        self.total_rejected
+    }
+}
+
+impl fmt::Display for CacheSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entries: {}/", self.entry_count)?;
+        match self.max_capacity {
+            Some(max) => write!(f, "{}", max)?,
+            None => write!(f, "unbounded")?,
+        }
+        write!(
+            f,
+            ", evicted: {}, rejected: {}",
+            self.total_evicted, self.total_rejected
+        )
+    }
+}
+
+/// The outcome of inserting a key previewed by
+/// [`Cache::preview_admission`](./struct.Cache.html#method.preview_admission).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdmissionPreview<'a, K> {
+    admitted: bool,
+    victims: Vec<&'a K>,
+}
+
+impl<'a, K> AdmissionPreview<'a, K> {
+    /// Returns `true` if the key would be admitted to the cache.
+    pub fn is_admitted(&self) -> bool {
+        self.admitted
+    }
+
+    /// Returns the keys of the entries that would be evicted, in the order they
+    /// would be evicted.
+    pub fn victims(&self) -> &[&'a K] {
+        &self.victims
+    }
+}
+
+// Access-Order Queue Node
+type AoqNode<K> = NonNull<DeqNode<KeyHashDate<K>>>;
+
+enum AdmissionResult<K> {
+    Admitted { victim_node: AoqNode<K> },
+    Rejected,
+}
+
+//
+// private free-standing functions
+//
+
+/// Pops the next victim from a simulated eviction queue, giving the referenced
+/// entries a second chance like the sweep of the clock hand.
+fn pop_victim<'a, K>(
+    queue: &mut VecDeque<(&'a KeyHashDate<K>, bool)>,
+) -> Option<&'a KeyHashDate<K>> {
+    for _ in 0..queue.len() {
+        match queue.front_mut() {
+            Some((kh, referenced)) if *referenced => {
+                let kh = *kh;
+                queue.pop_front();
+                queue.push_back((kh, false));
+            }
+            _ => break,
+        }
+    }
+    queue.pop_front().map(|(kh, _)| kh)
+}
+
+// To see the debug prints, run test as `cargo test -- --nocapture`
+#[cfg(test)]
+mod tests {
+    use super::{Cache, Lookup, UpsertResult};
+
+    #[test]
+    fn basic_single_thread() {
+        let mut cache = Cache::builder().max_capacity(3).build_with_test_hasher();
+        cache.enable_frequency_sketch_for_testing();
+
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        assert_eq!(cache.get(&"a"), Some(&"alice"));
+        assert!(cache.contains_key(&"a"));
+        assert!(cache.contains_key(&"b"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        // counts: a -> 1, b -> 1
+
+        cache.insert("c", "cindy");
+        assert_eq!(cache.get(&"c"), Some(&"cindy"));
+        assert!(cache.contains_key(&"c"));
+        // counts: a -> 1, b -> 1, c -> 1
+
+        assert!(cache.contains_key(&"a"));
+        assert_eq!(cache.get(&"a"), Some(&"alice"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        assert!(cache.contains_key(&"b"));
+        // counts: a -> 2, b -> 2, c -> 1
+        // The hasher has fixed keys, so the estimates are reproducible.
+        let freq = |cache: &Cache<_, _, _>, key| cache.frequency_sketch.frequency(cache.hash(key));
+        assert_eq!(freq(&cache, &"a"), 2);
+        assert_eq!(freq(&cache, &"b"), 2);
+        assert_eq!(freq(&cache, &"c"), 1);
+        assert_eq!(cache.next_eviction_key(), Some(&"c"));
+
+        // "d" should not be admitted because its frequency is too low.
+        cache.insert("d", "david"); //   count: d -> 0
+        assert_eq!(cache.get(&"d"), None); //   d -> 1
+        assert!(!cache.contains_key(&"d"));
+
+        cache.insert("d", "david");
+        assert!(!cache.contains_key(&"d"));
+        assert_eq!(cache.get(&"d"), None); //   d -> 2
+        assert_eq!(freq(&cache, &"d"), 2);
+        assert_eq!(cache.total_rejected(), 2);
+
+        // "d" should be admitted and "c" should be evicted
+        // because d's frequency is higher than c's.
+        assert_eq!(
+            cache.insert_and_collect_evicted("d", "dennis"),
+            vec![("c", "cindy")]
+        );
+        assert_eq!(cache.get(&"a"), Some(&"alice"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        assert_eq!(cache.get(&"c"), None);
+        assert_eq!(cache.get(&"d"), Some(&"dennis"));
+        assert!(cache.contains_key(&"a"));
+        assert!(cache.contains_key(&"b"));
+        assert!(!cache.contains_key(&"c"));
+        assert!(cache.contains_key(&"d"));
+
+        cache.invalidate(&"b");
+        assert_eq!(cache.get(&"b"), None);
+        assert!(!cache.contains_key(&"b"));
+    }
+
+    #[test]
+    fn invalidate_all() {
+        let mut cache = Cache::new(100);
+        cache.enable_frequency_sketch_for_testing();
+
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        cache.insert("c", "cindy");
+        assert_eq!(cache.get(&"a"), Some(&"alice"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        assert_eq!(cache.get(&"c"), Some(&"cindy"));
+        assert!(cache.contains_key(&"a"));
+        assert!(cache.contains_key(&"b"));
+        assert!(cache.contains_key(&"c"));
+
+        cache.invalidate_all();
+
+        cache.insert("d", "david");
+
+        assert!(cache.get(&"a").is_none());
+        assert!(cache.get(&"b").is_none());
+        assert!(cache.get(&"c").is_none());
+        assert_eq!(cache.get(&"d"), Some(&"david"));
+        assert!(!cache.contains_key(&"a"));
+        assert!(!cache.contains_key(&"b"));
+        assert!(!cache.contains_key(&"c"));
+        assert!(cache.contains_key(&"d"));
+    }
+
+    #[test]
+    fn invalidate_entries_if() {
+        use std::collections::HashSet;
+
+        let mut cache = Cache::new(100);
+        cache.enable_frequency_sketch_for_testing();
+
+        cache.insert(0, "alice");
+        cache.insert(1, "bob");
+        cache.insert(2, "alex");
+
+        assert_eq!(cache.get(&0), Some(&"alice"));
+        assert_eq!(cache.get(&1), Some(&"bob"));
+        assert_eq!(cache.get(&2), Some(&"alex"));
+        assert!(cache.contains_key(&0));
+        assert!(cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+
+        let names = ["alice", "alex"].iter().cloned().collect::<HashSet<_>>();
+        cache.invalidate_entries_if(move |_k, &v| names.contains(v));
+
+        cache.insert(3, "alice");
+
+        assert!(cache.get(&0).is_none());
+        assert!(cache.get(&2).is_none());
+        assert_eq!(cache.get(&1), Some(&"bob"));
+        // This should survive as it was inserted after calling invalidate_entries_if.
+        assert_eq!(cache.get(&3), Some(&"alice"));
+
+        assert!(!cache.contains_key(&0));
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert!(cache.contains_key(&3));
+
+        assert_eq!(cache.cache.len(), 2);
+
+        cache.invalidate_entries_if(|_k, &v| v == "alice");
+        cache.invalidate_entries_if(|_k, &v| v == "bob");
+
+        assert!(cache.get(&1).is_none());
+        assert!(cache.get(&3).is_none());
+
+        assert!(!cache.contains_key(&1));
+        assert!(!cache.contains_key(&3));
+
+        assert_eq!(cache.cache.len(), 0);
+    }
+
+    #[cfg_attr(target_pointer_width = "16", ignore)]
+    #[test]
+    fn test_skt_capacity_will_not_overflow() {
+        // power of two
+        let pot = |exp| 2u64.pow(exp);
+
+        let ensure_sketch_len = |max_capacity, len, name| {
+            let mut cache = Cache::<u8, u8>::new(max_capacity);
+            cache.enable_frequency_sketch_for_testing();
+            assert_eq!(cache.frequency_sketch.table_len(), len as usize, "{}", name);
+        };
+
+        if cfg!(target_pointer_width = "32") {
+            let pot24 = pot(24);
+            let pot16 = pot(16);
+            ensure_sketch_len(0, 128, "0");
+            ensure_sketch_len(128, 128, "128");
+            ensure_sketch_len(pot16, pot16, "pot16");
+            // due to ceiling to next_power_of_two
+            ensure_sketch_len(pot16 + 1, pot(17), "pot16 + 1");
+            // due to ceiling to next_power_of_two
+            ensure_sketch_len(pot24 - 1, pot24, "pot24 - 1");
+            ensure_sketch_len(pot24, pot24, "pot24");
+            ensure_sketch_len(pot(27), pot24, "pot(27)");
+            ensure_sketch_len(u32::MAX as u64, pot24, "u32::MAX");
+        } else {
+            // target_pointer_width: 64 or larger.
+            let pot30 = pot(30);
+            let pot16 = pot(16);
+            ensure_sketch_len(0, 128, "0");
+            ensure_sketch_len(128, 128, "128");
+            ensure_sketch_len(pot16, pot16, "pot16");
+            // due to ceiling to next_power_of_two
+            ensure_sketch_len(pot16 + 1, pot(17), "pot16 + 1");
+
+            // The following tests will allocate large memory (~8GiB).
+            // Skip when running on Circle CI.
+            if !cfg!(circleci) {
+                // due to ceiling to next_power_of_two
+                ensure_sketch_len(pot30 - 1, pot30, "pot30- 1");
+                ensure_sketch_len(pot30, pot30, "pot30");
+                ensure_sketch_len(u64::MAX, pot30, "u64::MAX");
+            }
+        };
+    }
+
+    #[test]
+    fn remove_decrements_entry_count() {
+        let mut cache = Cache::new(3);
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        assert_eq!(cache.entry_count(), 2);
+
+        let removed = cache.remove(&"a");
+        assert_eq!(removed, Some("alice"));
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.remove(&"nonexistent");
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.remove(&"b");
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn invalidate_decrements_entry_count() {
+        let mut cache = Cache::new(3);
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        assert_eq!(cache.entry_count(), 2);
+
+        cache.invalidate(&"a");
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.invalidate(&"nonexistent");
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.invalidate(&"b");
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn insert_after_remove_on_full_cache() {
+        let mut cache = Cache::new(2);
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        assert_eq!(cache.entry_count(), 2);
+
+        cache.remove(&"a");
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.insert("c", "cindy");
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.get(&"c"), Some(&"cindy"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn insert_after_invalidate_on_full_cache() {
+        let mut cache = Cache::new(2);
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        assert_eq!(cache.entry_count(), 2);
+
+        cache.invalidate(&"a");
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.insert("c", "cindy");
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.get(&"c"), Some(&"cindy"));
+        assert_eq!(cache.get(&"b"), Some(&"bob"));
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn invalidate_all_panic_safety() {
+        use std::panic::catch_unwind;
+        use std::panic::AssertUnwindSafe;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        static DROP_COUNT: AtomicU32 = AtomicU32::new(0);
+
+        struct PanicOnDrop {
+            id: u32,
+            should_panic: bool,
+        }
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                DROP_COUNT.fetch_add(1, Ordering::Relaxed);
+                if self.should_panic {
+                    panic!("intentional panic in drop for id={}", self.id);
+                }
+            }
+        }
+
+        DROP_COUNT.store(0, Ordering::Relaxed);
+        let mut cache = Cache::new(10);
+        cache.insert(
+            1,
+            PanicOnDrop {
+                id: 1,
+                should_panic: false,
+            },
+        );
+        cache.insert(
+            2,
+            PanicOnDrop {
+                id: 2,
+                should_panic: true,
+            },
+        );
+        cache.insert(
+            3,
+            PanicOnDrop {
+                id: 3,
+                should_panic: false,
+            },
+        );
+        assert_eq!(cache.entry_count(), 3);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            cache.invalidate_all();
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(cache.entry_count(), 0);
+        assert_eq!(cache.cache.len(), 0);
+
+        cache.insert(
+            4,
+            PanicOnDrop {
+                id: 4,
+                should_panic: false,
+            },
+        );
+        assert_eq!(cache.entry_count(), 1);
+        assert!(cache.contains_key(&4));
+    }
+
+    #[test]
+    fn evict_n() {
+        let mut cache = Cache::new(10);
+        for (k, v) in [
+            ('a', "alice"),
+            ('b', "bob"),
+            ('c', "cindy"),
+            ('d', "david"),
+            ('e', "emily"),
+        ] {
+            cache.insert(k, v);
+        }
+        // Make "a" the most recently used entry. "b" and "c" are now the LRU ones.
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+
+        assert_eq!(cache.evict_n(2), 2);
+        assert_eq!(cache.entry_count(), 3);
+        assert!(!cache.contains_key(&'b'));
+        assert!(!cache.contains_key(&'c'));
+        assert!(cache.contains_key(&'a'));
+        assert!(cache.contains_key(&'d'));
+        assert!(cache.contains_key(&'e'));
+
+        // Asking for more than the cache holds evicts everything.
+        assert_eq!(cache.evict_n(10), 3);
+        assert_eq!(cache.entry_count(), 0);
+        assert_eq!(cache.evict_n(1), 0);
+    }
+
+    #[test]
+    fn would_admit() {
+        let mut cache = Cache::new(3);
+        cache.enable_frequency_sketch_for_testing();
+
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        // The cache has free space.
+        assert!(cache.would_admit(&"hot"));
+        assert!(cache.would_admit(&"cold"));
+
+        cache.insert("c", "cindy");
+        // The cache is full now. Make "hot" more popular than any entry.
+        for _ in 0..3 {
+            assert_eq!(cache.get(&"hot"), None);
+        }
+
+        assert!(cache.would_admit(&"hot"));
+        assert!(!cache.would_admit(&"cold"));
+        // Existing keys are always updatable.
+        assert!(cache.would_admit(&"a"));
+
+        // The dry run did not change anything.
+        assert_eq!(cache.entry_count(), 3);
+        cache.insert("cold", "carl");
+        assert!(!cache.contains_key(&"cold"));
+        cache.insert("hot", "henry");
+        assert!(cache.contains_key(&"hot"));
+    }
+
+    #[test]
+    fn get_guard() {
+        let mut cache = Cache::new(3);
+        cache.insert("a", vec![1, 2, 3]);
+        cache.insert("b", vec![4]);
+
+        let guard = cache.get_guard(&"a").unwrap();
+        assert_eq!(guard.len(), 3);
+        assert_eq!(*guard, vec![1, 2, 3]);
+        assert_eq!(guard.into_ref().iter().sum::<i32>(), 6);
+
+        assert!(cache.get_guard(&"z").is_none());
+
+        // The hit was recorded, so "a" is now the most recently used entry.
+        assert_eq!(cache.evict_n(1), 1);
+        assert!(cache.contains_key(&"a"));
+        assert!(!cache.contains_key(&"b"));
+    }
+
+    #[test]
+    fn compact() {
+        let mut cache = Cache::new(10);
+        for i in 0..8 {
+            cache.insert(i, i * 10);
+        }
+        cache.invalidate(&3);
+        cache.get(&0);
+        cache.get(&5);
+
+        let ao_keys = |cache: &mut Cache<i32, i32>| {
+            (&mut cache.deques.probation)
+                .map(|kh| *kh.key)
+                .collect::<Vec<_>>()
+        };
+
+        let before = ao_keys(&mut cache);
+        assert_eq!(before, vec![1, 2, 4, 6, 7, 0, 5]);
+
+        cache.compact();
+        assert_eq!(ao_keys(&mut cache), before);
+        assert_eq!(cache.entry_count(), 7);
+
+        // The entries must point to the new nodes.
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(ao_keys(&mut cache), vec![2, 4, 6, 7, 0, 5, 1]);
+        assert_eq!(cache.remove(&6), Some(60));
+        assert_eq!(cache.evict_n(2), 2);
+        assert_eq!(ao_keys(&mut cache), vec![7, 0, 5, 1]);
+        assert_eq!(cache.entry_count(), 4);
+    }
+
+    #[test]
+    fn prime_frequencies() {
+        let mut cache = Cache::new(3);
+        cache.insert("a", "alice");
+        cache.insert("b", "bob");
+        cache.insert("c", "cindy");
+        // The sketch has been enabled as the cache got half full, but no entry
+        // has been read yet.
+        assert!(cache.frequency_sketch_enabled);
+
+        // An unprimed key is not admitted to the full cache.
+        cache.insert("d", "david");
+        assert!(!cache.contains_key(&"d"));
+
+        cache.prime_frequencies(["e", "e"]);
+        assert!(!cache.contains_key(&"e"));
+        cache.insert("e", "emily");
+        assert_eq!(cache.get(&"e"), Some(&"emily"));
+        assert_eq!(cache.entry_count(), 3);
+    }
+
+    #[test]
+    fn rebuild_frequency_sketch() {
+        use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        let mut cache = cache.rehash(BuildHasherDefault::<DefaultHasher>::default());
+        assert!(!cache.frequency_sketch_enabled);
+
+        cache.rebuild_frequency_sketch();
+        assert!(cache.frequency_sketch_enabled);
+        for key in ['a', 'b'] {
+            let hash = cache.hash(&key);
+            assert!(cache.frequency_sketch.frequency(hash) > 0);
+        }
+        let hash = cache.hash(&'c');
+        assert_eq!(cache.frequency_sketch.frequency(hash), 0);
+
+        // Does nothing on an unbounded cache.
+        let mut cache = Cache::builder().build();
+        cache.insert('a', "alice");
+        cache.rebuild_frequency_sketch();
+        assert!(!cache.frequency_sketch_enabled);
+    }
+
+    #[test]
+    fn prime_frequencies_enables_sketch() {
+        let mut cache = Cache::<i32, ()>::new(100);
+        assert!(!cache.frequency_sketch_enabled);
+        cache.prime_frequencies(vec![1, 2, 3]);
+        assert!(cache.frequency_sketch_enabled);
+        assert_eq!(cache.entry_count(), 0);
+
+        // Priming an unbounded cache is a no-op.
+        let mut cache = Cache::<i32, ()>::builder().build();
+        cache.prime_frequencies(vec![1, 2, 3]);
+        assert!(!cache.frequency_sketch_enabled);
+    }
+
+    #[test]
+    fn window_keys() {
+        let mut cache = Cache::new(10);
+        assert!(cache.window_keys().is_empty());
+
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.get(&'a');
+
+        // New entries go directly to the probation region.
+        assert!(cache.window_keys().is_empty());
+        let probation_keys = cache
+            .deques
+            .probation
+            .iter()
+            .map(|kh| *kh.key)
+            .collect::<Vec<_>>();
+        assert_eq!(probation_keys, ['b', 'a']);
+    }
+
+    #[test]
+    fn negative_caching() {
+        let mut cache = Cache::new(3);
+        cache.insert("a", "alice");
+        cache.insert_absent("b");
+        assert_eq!(cache.entry_count(), 2);
+
+        assert_eq!(cache.get_state(&"a"), Lookup::Hit(&"alice"));
+        assert_eq!(cache.get_state(&"b"), Lookup::NegativeHit);
+        assert_eq!(cache.get_state(&"c"), Lookup::Miss);
+        assert_eq!(cache.get(&"b"), None);
+        assert!(!cache.contains_key(&"b"));
+        assert_eq!(cache.iter().count(), 1);
+
+        // Replace the value of "a" with a tombstone, and vice versa for "b".
+        cache.insert_absent("a");
+        cache.insert("b", "bob");
+        assert_eq!(cache.get_state(&"a"), Lookup::NegativeHit);
+        assert_eq!(cache.get_state(&"b"), Lookup::Hit(&"bob"));
+        assert_eq!(cache.entry_count(), 2);
+
+        // Tombstones are evictable.
+        assert_eq!(cache.evict_n(1), 1);
+        assert_eq!(cache.get_state(&"a"), Lookup::Miss);
+        assert_eq!(cache.entry_count(), 1);
+
+        // Removing a tombstone returns no value.
+        cache.insert_absent("c");
+        assert_eq!(cache.remove(&"c"), None);
+        assert_eq!(cache.get_state(&"c"), Lookup::Miss);
+        assert_eq!(cache.entry_count(), 1);
+    }
+
+    #[test]
+    fn set_max_capacity() {
+        let mut cache = Cache::<u32, u32>::new(200);
+        cache.enable_frequency_sketch_for_testing();
+        assert_eq!(cache.frequency_sketch.table_len(), 256);
+
+        // Raising the capacity grows the sketch.
+        cache.set_max_capacity(1000);
+        assert_eq!(cache.policy().max_capacity(), Some(1000));
+        assert_eq!(cache.frequency_sketch.table_len(), 1024);
+
+        // Lowering it does not shrink the sketch.
+        cache.set_max_capacity(100);
+        assert_eq!(cache.frequency_sketch.table_len(), 1024);
+
+        // Excess entries are evicted by the next write.
+        let mut cache = Cache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.set_max_capacity(4);
+        assert_eq!(cache.entry_count(), 10);
+        cache.invalidate(&100);
+        assert_eq!(cache.entry_count(), 4);
+        assert!((6..10).all(|i| cache.contains_key(&i)));
+
+        // Raising the capacity of a cache without the sketch enables it once the
+        // cache is half full.
+        let mut cache = Cache::new(100);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        assert!(!cache.frequency_sketch_enabled);
+        cache.set_max_capacity(20);
+        assert!(cache.frequency_sketch_enabled);
+        assert_eq!(cache.frequency_sketch.table_len(), 128);
+    }
+
+    #[test]
+    fn check_invariants() {
+        let mut cache = Cache::new(3);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        cache.enable_frequency_sketch_for_testing();
+        for i in 0..10 {
+            cache.insert(i, i);
+            cache.get(&(i / 2));
+        }
+        cache.invalidate(&9);
+        cache.insert_absent(10);
+        cache.compact();
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Corrupt the entry count.
+        cache.entry_count += 1;
+        assert!(cache
+            .check_invariants()
+            .unwrap_err()
+            .starts_with("entry_count"));
+        cache.entry_count -= 1;
+
+        // Remove an entry from the map without unlinking its node.
+        let key = cache.iter().next().map(|(k, _)| *k).unwrap();
+        let entry = cache.cache.remove(&key).unwrap();
+        cache.entry_count -= 1;
+        assert_eq!(
+            cache.check_invariants(),
+            Err("entry_count (2) does not match the total length of the deques (3)".into())
+        );
+        cache.entry_count += 1;
+        assert_eq!(
+            cache.check_invariants(),
+            Err("entry_count (3) does not match the number of entries in the map (2)".into())
+        );
+
+        // Re-insert the entry with a detached node.
+        let mut entry = entry;
+        let node = entry.take_access_order_q_node();
+        cache.cache.insert(std::rc::Rc::new(key), entry);
+        assert_eq!(
+            cache.check_invariants(),
+            Err(
+                "an entry in the map has no node but a node in the probation deque refers to it"
+                    .into()
+            )
+        );
+        cache
+            .cache
+            .get_mut(&key)
+            .unwrap()
+            .set_access_order_q_node(node);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn check_invariants_write_order() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .track_insertion_order(true)
+            .build();
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        cache.invalidate(&3);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Detach the write-order node of an entry.
+        let node = cache.cache.get_mut(&4).unwrap().take_write_order_q_node();
+        assert_eq!(
+            cache.check_invariants(),
+            Err("an entry in the map has no write-order node but a node in the write-order deque refers to it".into())
+        );
+
+        // Point the entry to the node of another entry.
+        let other = cache.cache.get(&0).unwrap().write_order_q_node();
+        cache
+            .cache
+            .get_mut(&4)
+            .unwrap()
+            .set_write_order_q_node(other);
+        assert_eq!(
+            cache.check_invariants(),
+            Err("an entry in the map does not point to the node in the write-order deque referring to it".into())
+        );
+        cache
+            .cache
+            .get_mut(&4)
+            .unwrap()
+            .set_write_order_q_node(node);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Unlink a node without removing its entry.
+        let mut entry = cache.cache.remove(&4).unwrap();
+        cache.deques.unlink_wo(&mut entry);
+        cache.cache.insert(std::rc::Rc::new(4), entry);
+        assert_eq!(
+            cache.check_invariants(),
+            Err("entry_count (4) does not match the length of the write-order deque (3)".into())
+        );
+    }
+
+    #[test]
+    fn repeated_get_of_mru_entry() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+
+        for _ in 0..5 {
+            assert_eq!(cache.get(&'b'), Some(&"bob"));
+            let keys = cache
+                .deques
+                .probation
+                .iter()
+                .map(|kh| *kh.key)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, ['a', 'b']);
+            assert_eq!(cache.check_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn reject_when_full() {
+        use crate::InsertPolicy;
+
+        let mut cache = Cache::builder()
+            .max_capacity(3)
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .build();
+        cache.enable_frequency_sketch_for_testing();
+
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+
+        // Even a popular key is rejected once the cache is full.
+        for _ in 0..5 {
+            cache.get(&'d');
+        }
+        assert!(!cache.would_admit(&'d'));
+        cache.insert('d', "david");
+        assert!(!cache.contains_key(&'d'));
+        assert_eq!(cache.entry_count(), 3);
+        assert!(cache.contains_key(&'a'));
+        assert!(cache.contains_key(&'b'));
+        assert!(cache.contains_key(&'c'));
+
+        // Updates are still allowed.
+        cache.insert('a', "anne");
+        assert_eq!(cache.get(&'a'), Some(&"anne"));
+
+        // A new key is accepted after making room.
+        cache.invalidate(&'b');
+        cache.insert('d', "david");
+        assert_eq!(cache.get(&'d'), Some(&"david"));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn insert_and_collect_evicted() {
+        let mut cache = Cache::new(3);
+        cache.enable_frequency_sketch_for_testing();
+
+        assert!(cache.insert_and_collect_evicted('a', "alice").is_empty());
+        assert!(cache.insert_and_collect_evicted('b', "bob").is_empty());
+        assert!(cache.insert_and_collect_evicted('c', "cindy").is_empty());
+        cache.get(&'a');
+        cache.get(&'b');
+        cache.get(&'c');
+        // LRU order: a, b, c
+
+        // Make 'd' popular enough to displace the LRU entry 'a'.
+        for _ in 0..3 {
+            cache.get(&'d');
+        }
+        let evicted = cache.insert_and_collect_evicted('d', "david");
+        assert_eq!(evicted, vec![('a', "alice")]);
+        assert!(cache.contains_key(&'d'));
+
+        // An update evicts nothing.
+        assert!(cache.insert_and_collect_evicted('b', "bill").is_empty());
+        // LRU order: c, d, b
+
+        // Entries evicted to get back under the lowered capacity are returned too.
+        cache.set_max_capacity(1);
+        let evicted = cache.insert_and_collect_evicted('b', "bob");
+        assert_eq!(evicted, vec![('c', "cindy"), ('d', "david")]);
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn insert_detailed() {
+        let mut cache = Cache::new(2);
+        cache.enable_frequency_sketch_for_testing();
+
+        assert_eq!(cache.insert_detailed('a', "alice"), UpsertResult::Inserted);
+        assert_eq!(cache.insert_detailed('b', "bob"), UpsertResult::Inserted);
+        assert_eq!(
+            cache.insert_detailed('a', "anne"),
+            UpsertResult::Updated("alice")
+        );
+
+        // Replacing a tombstone is an insertion.
+        cache.insert_absent('b');
+        assert_eq!(cache.insert_detailed('b', "bill"), UpsertResult::Inserted);
+
+        // 'c' is not popular enough to be admitted to the full cache.
+        assert_eq!(
+            cache.insert_detailed('c', "cindy"),
+            UpsertResult::AdmissionRejected("cindy")
+        );
+        cache.get(&'c');
+        assert_eq!(cache.insert_detailed('c', "cindy"), UpsertResult::Inserted);
+        assert_eq!(cache.entry_count(), 2);
+
+        let mut cache = Cache::new(0);
+        assert_eq!(
+            cache.insert_detailed('a', "alice"),
+            UpsertResult::TooLarge("alice")
+        );
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn insert_detailed_reject_when_full() {
+        use crate::InsertPolicy;
+
+        let mut cache = Cache::builder()
+            .max_capacity(1)
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .build();
+        assert_eq!(cache.insert_detailed('a', "alice"), UpsertResult::Inserted);
+        assert_eq!(
+            cache.insert_detailed('b', "bob"),
+            UpsertResult::AdmissionRejected("bob")
+        );
+    }
+
+    #[test]
+    fn iter_from() {
+        use std::collections::HashSet;
+
+        let mut cache = Cache::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i.to_string());
+        }
+        // Tombstones are skipped.
+        cache.invalidate(&0);
+        cache.insert_absent(0);
+        assert_eq!(cache.entry_count(), 1000);
+
+        let mut seen = HashSet::new();
+        let mut cursor = None;
+        let mut batches = 0;
+        loop {
+            let (batch, next) = cache.iter_from(cursor);
+            assert!(batch.len() <= super::ITER_FROM_BATCH_SIZE);
+            for (k, v) in batch {
+                assert_eq!(k.to_string(), v);
+                assert!(seen.insert(k), "key {} was returned twice", k);
+            }
+            batches += 1;
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(batches, 10);
+        assert_eq!(seen, (1..1000).collect::<HashSet<_>>());
+
+        // An empty cache.
+        let cache = Cache::<u32, u32>::new(10);
+        assert_eq!(cache.iter_from(None), (vec![], None));
+    }
+
+    #[test]
+    fn rehash() {
+        use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+
+        let mut cache = Cache::new(10);
+        for i in 0..5 {
+            cache.insert(i, i * 10);
+        }
+        cache.insert_absent(5);
+        // Make 0 the most recently used.
+        cache.get(&0);
+
+        let mut cache = cache.rehash(BuildHasherDefault::<DefaultHasher>::default());
+        assert_eq!(cache.check_invariants(), Ok(()));
+        assert_eq!(cache.policy().max_capacity(), Some(10));
+        assert_eq!(cache.entry_count(), 6);
+        for i in 0..5 {
+            assert_eq!(cache.get(&i), Some(&(i * 10)));
+        }
+        assert_eq!(cache.get_state(&5), Lookup::NegativeHit);
+
+        // The hashes stored in the deque are computed by the new hasher.
+        for kh in cache.deques.probation.iter() {
+            assert_eq!(kh.hash, cache.hash(&kh.key));
+        }
+
+        // The LRU order is kept.
+        let mut cache = Cache::new(10);
+        for i in 0..5 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(&0);
+        let mut cache = cache.rehash(BuildHasherDefault::<DefaultHasher>::default());
+        cache.evict_n(1);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&0));
+    }
+
+    #[test]
+    fn on_memory_pressure() {
+        let mut cache = Cache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.entry_count(), 10);
+
+        assert!(cache.on_memory_pressure(0.0).is_empty());
+        assert!(cache.on_memory_pressure(f64::NAN).is_empty());
+        assert_eq!(cache.entry_count(), 10);
+
+        // The 5 LRU entries are evicted.
+        let evicted = cache.on_memory_pressure(0.5);
+        assert_eq!(evicted, (0..5).map(|i| (i, i * 10)).collect::<Vec<_>>());
+        assert_eq!(cache.entry_count(), 5);
+        for i in 5..10 {
+            assert!(cache.contains_key(&i));
+        }
+
+        // Rounded up.
+        assert_eq!(cache.on_memory_pressure(0.1), vec![(5, 50)]);
+        assert_eq!(cache.entry_count(), 4);
+
+        assert_eq!(cache.on_memory_pressure(1.5).len(), 4);
+        assert_eq!(cache.entry_count(), 0);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+
+        // Match.
+        assert_eq!(cache.compare_and_swap(&'a', &"alice", "anne"), Ok(()));
+        assert_eq!(cache.get(&'a'), Some(&"anne"));
+        // The swapped entry became the most recently used.
+        cache.evict_n(1);
+        assert!(!cache.contains_key(&'b'));
+        assert!(cache.contains_key(&'a'));
+
+        // Mismatch.
+        assert_eq!(
+            cache.compare_and_swap(&'a', &"alice", "amy"),
+            Err(Some("anne"))
+        );
+        assert_eq!(cache.get(&'a'), Some(&"anne"));
+
+        // Missing and negatively cached keys.
+        assert_eq!(cache.compare_and_swap(&'b', &"bob", "bill"), Err(None));
+        cache.insert_absent('c');
+        assert_eq!(cache.compare_and_swap(&'c', &"cindy", "cathy"), Err(None));
+        assert_eq!(cache.get_state(&'c'), Lookup::NegativeHit);
+    }
+
+    #[test]
+    fn compare_and_swap_runs_maintenance() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .lazy_eviction(true)
+            .build();
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.set_max_capacity(4);
+        assert_eq!(cache.entry_count(), 10);
+
+        // Like an insert, the swap evicts the entries deferred by lazy eviction.
+        assert_eq!(cache.compare_and_swap(&9, &9, 90), Ok(()));
+        assert_eq!(cache.entry_count(), 4);
+        assert_eq!(cache.get(&9), Some(&90));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn get_quiet() {
+        let mut cache = Cache::new(10);
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert_absent('c');
+
+        assert_eq!(cache.get_quiet(&'a'), Some(&"alice"));
+        assert_eq!(cache.get_quiet(&'c'), None);
+        assert_eq!(cache.get_quiet(&'d'), None);
+
+        // Neither the popularity nor the LRU order was updated.
+        assert_eq!(cache.frequency_sketch.frequency(cache.hash(&'a')), 0);
+        cache.evict_n(1);
+        assert!(!cache.contains_key(&'a'));
+        assert!(cache.contains_key(&'b'));
+
+        // It evicts the entries over the max capacity.
+        cache.set_max_capacity(1);
+        assert_eq!(cache.get_quiet(&'b'), None);
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.get_state(&'c'), Lookup::NegativeHit);
+    }
+
+    #[test]
+    fn clock_eviction_policy() {
+        use crate::EvictionPolicy;
+
+        let mut cache = Cache::builder()
+            .max_capacity(3)
+            .eviction_policy(EvictionPolicy::Clock)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+
+        // Reading 'a' only sets its reference bit. It does not relink the node.
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+        let front = cache.deques.probation.peek_front().unwrap();
+        assert_eq!(*front.element.key, 'a');
+
+        // 'd' is always admitted. 'a' gets a second chance, and 'b' is evicted.
+        cache.insert('d', "david");
+        assert_eq!(cache.entry_count(), 3);
+        assert!(cache.contains_key(&'a'));
+        assert!(!cache.contains_key(&'b'));
+        assert!(cache.contains_key(&'c'));
+        assert!(cache.contains_key(&'d'));
+
+        // The bit of 'a' was cleared by the sweep, so 'a' is not spared twice.
+        cache.insert('e', "emily");
+        assert!(!cache.contains_key(&'c'));
+        cache.insert('f', "frank");
+        assert!(!cache.contains_key(&'a'));
+
+        // evict_n also gives second chances.
+        cache.get(&'d');
+        assert_eq!(cache.evict_n(1), 1);
+        assert!(cache.contains_key(&'d'));
+        assert!(!cache.contains_key(&'e'));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn version() {
+        let mut cache = Cache::new(2);
+        cache.enable_frequency_sketch_for_testing();
+        let mut version = cache.version();
+
+        let mut assert_bumped = |cache: &Cache<char, &str>, bumped: bool| {
+            assert_eq!(cache.version() != version, bumped);
+            version = cache.version();
+        };
+
+        cache.insert('a', "alice");
+        assert_bumped(&cache, true);
+        cache.insert('a', "anne");
+        assert_bumped(&cache, true);
+        cache.insert('b', "bob");
+        assert_bumped(&cache, true);
+
+        // Reads do not bump the version.
+        cache.get(&'a');
+        cache.get_quiet(&'b');
+        cache.contains_key(&'a');
+        cache.iter().for_each(drop);
+        assert_bumped(&cache, false);
+
+        // A rejected insert does not.
+        cache.insert('c', "cindy");
+        assert!(!cache.contains_key(&'c'));
+        assert_bumped(&cache, false);
+
+        // An admitted insert evicting the LRU entry does.
+        cache.get(&'c');
+        cache.get(&'c');
+        cache.insert('c', "cindy");
+        assert!(cache.contains_key(&'c'));
+        assert_bumped(&cache, true);
+
+        // Evictions and invalidations do.
+        cache.evict_n(1);
+        assert_bumped(&cache, true);
+        cache.invalidate(&'z');
+        assert_bumped(&cache, false);
+        cache.invalidate(&'c');
+        assert_bumped(&cache, true);
+        cache.insert('d', "david");
+        assert_bumped(&cache, true);
+        cache.invalidate_all();
+        assert_bumped(&cache, true);
+    }
+
+    #[test]
+    fn max_capacity_of_u64_max() {
+        let mut cache = Cache::new(u64::MAX);
+        cache.insert('a', "alice");
+        assert!(cache.would_admit(&'b'));
+        cache.insert('b', "bob");
+        assert_eq!(cache.entry_count(), 2);
+
+        // Simulate a weighted size close to the limit.
+        assert!(cache.has_enough_capacity(1, u64::MAX - 1));
+        assert!(!cache.has_enough_capacity(1, u64::MAX));
+        assert!(!cache.has_enough_capacity(u32::MAX, u64::MAX - 1));
+        assert_eq!(cache.weights_to_evict(), 0);
+
+        assert_eq!(crate::common::sketch_capacity(u64::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn get_or_insert_default() {
+        let mut cache: Cache<char, u32> = Cache::new(2);
+        cache.enable_frequency_sketch_for_testing();
+
+        *cache.get_or_insert_default('a').unwrap() += 1;
+        *cache.get_or_insert_default('a').unwrap() += 1;
+        assert_eq!(cache.get(&'a'), Some(&2));
+
+        // A negatively cached key is a miss.
+        cache.insert_absent('b');
+        assert_eq!(cache.get_or_insert_default('b'), Some(&mut 0));
+
+        // The default value goes through the admission policy. 'a' is popular,
+        // 'c' is not.
+        assert_eq!(cache.get_or_insert_default('c'), None);
+        assert!(!cache.contains_key(&'c'));
+        assert_eq!(cache.entry_count(), 2);
+    }
+
+    #[test]
+    fn promotion_threshold() {
+        use crate::common::CacheRegion;
+
+        fn region_of(cache: &Cache<char, &str>, key: char) -> CacheRegion {
+            let node = cache.cache.get(&key).unwrap().access_order_q_node();
+            node.unwrap().decompose_tag().into()
+        }
+
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(3)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        assert_eq!(region_of(&cache, 'a'), CacheRegion::MainProbation);
+
+        // Accessed twice, 'a' stays in probation.
+        cache.get(&'a');
+        cache.get(&'a');
+        assert_eq!(region_of(&cache, 'a'), CacheRegion::MainProbation);
+
+        // The third access promotes it to protected.
+        cache.get(&'a');
+        assert_eq!(region_of(&cache, 'a'), CacheRegion::MainProtected);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Probation entries are evicted first, and then protected ones.
+        assert_eq!(cache.evict_n(1), 1);
+        assert!(cache.contains_key(&'a'));
+        assert!(!cache.contains_key(&'b'));
+        assert_eq!(cache.evict_n(1), 1);
+        assert_eq!(cache.entry_count(), 0);
+
+        // The protected region is capped at 80% of the max capacity. The LRU
+        // protected entries are demoted by the following writes.
+        let mut cache = Cache::builder()
+            .max_capacity(5)
+            .promotion_threshold(1)
+            .build();
+        for (key, name) in [
+            ('a', "alice"),
+            ('b', "bob"),
+            ('c', "cindy"),
+            ('d', "david"),
+            ('e', "emily"),
+        ] {
+            cache.insert(key, name);
+            cache.get(&key);
+        }
+        cache.invalidate(&'z');
+        assert_eq!(cache.deques.protected.len(), 4);
+        assert_eq!(region_of(&cache, 'a'), CacheRegion::MainProbation);
+        assert_eq!(region_of(&cache, 'b'), CacheRegion::MainProtected);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn promotion_hits_reset_on_demotion() {
+        use crate::common::CacheRegion;
+
+        fn region_and_hits(cache: &Cache<char, &str>, key: char) -> (CacheRegion, u8) {
+            let entry = cache.cache.get(&key).unwrap();
+            let region = entry.access_order_q_node().unwrap().decompose_tag();
+            (region.into(), entry.info.hits)
+        }
+
+        // The protected region holds up to 4 entries.
+        let mut cache = Cache::builder()
+            .max_capacity(5)
+            .promotion_threshold(2)
+            .build();
+        for (key, name) in [
+            ('a', "alice"),
+            ('b', "bob"),
+            ('c', "cindy"),
+            ('d', "david"),
+            ('e', "emily"),
+        ] {
+            cache.insert(key, name);
+            assert_eq!(
+                region_and_hits(&cache, key),
+                (CacheRegion::MainProbation, 0)
+            );
+            cache.get(&key);
+            assert_eq!(
+                region_and_hits(&cache, key),
+                (CacheRegion::MainProbation, 1)
+            );
+            cache.get(&key);
+            assert_eq!(
+                region_and_hits(&cache, key),
+                (CacheRegion::MainProtected, 2)
+            );
+        }
+
+        // A write demotes 'a', the LRU protected entry, resetting its hits.
+        cache.invalidate(&'z');
+        assert_eq!(
+            region_and_hits(&cache, 'a'),
+            (CacheRegion::MainProbation, 0)
+        );
+
+        // It has to earn the promotion again.
+        cache.get(&'a');
+        assert_eq!(
+            region_and_hits(&cache, 'a'),
+            (CacheRegion::MainProbation, 1)
+        );
+        cache.get(&'a');
+        assert_eq!(
+            region_and_hits(&cache, 'a'),
+            (CacheRegion::MainProtected, 2)
+        );
+
+        cache.invalidate(&'z');
+        assert_eq!(
+            region_and_hits(&cache, 'b'),
+            (CacheRegion::MainProbation, 0)
+        );
+        assert_eq!(
+            region_and_hits(&cache, 'a'),
+            (CacheRegion::MainProtected, 2)
+        );
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn contains_all_and_any() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', 1);
+        cache.insert('b', 2);
+        cache.insert_absent('c');
+
+        // All present.
+        assert!(cache.contains_all(&[&'a', &'b']));
+        assert!(cache.contains_any(&[&'a', &'b']));
+        // One missing.
+        assert!(!cache.contains_all(&[&'a', &'b', &'d']));
+        assert!(cache.contains_any(&[&'d', &'b']));
+        // A negatively cached key is missing.
+        assert!(!cache.contains_all(&[&'a', &'c']));
+        assert!(!cache.contains_any(&[&'c', &'d']));
+        // No keys.
+        assert!(cache.contains_all::<char>(&[]));
+        assert!(!cache.contains_any::<char>(&[]));
+    }
+
+    #[test]
+    fn trim_to() {
+        let mut cache = Cache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i * 10);
+        }
+        // Make 0 the most recently used.
+        cache.get(&0);
+
+        assert_eq!(cache.trim_to(3), 7);
+        assert_eq!(cache.entry_count(), 3);
+        for i in [0, 8, 9] {
+            assert!(cache.contains_key(&i));
+        }
+        assert_eq!(cache.trim_to(3), 0);
+
+        // The max capacity is unchanged.
+        assert_eq!(cache.policy().max_capacity(), Some(10));
+        for i in 10..17 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.entry_count(), 10);
+    }
+
+    #[test]
+    fn iter_rc() {
+        use std::rc::Rc;
+
+        let mut cache = Cache::new(10);
+        cache.insert("alice".to_string(), 1);
+        cache.insert("bob".to_string(), 2);
+        cache.insert_absent("cindy".to_string());
+
+        let mut entries = cache.iter_rc().collect::<Vec<_>>();
+        entries.sort_unstable();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(*entries[0].0, "alice");
+        assert_eq!(entries[1], (Rc::new("bob".to_string()), &2));
+
+        // The keys alias the stored keys.
+        for (key, _) in &entries {
+            let (stored, _) = cache.cache.get_key_value(key).unwrap();
+            assert!(Rc::ptr_eq(key, stored));
+        }
+    }
+
+    #[test]
+    fn expected_key_cardinality() {
+        let mut cache = Cache::builder()
+            .max_capacity(100)
+            .expected_key_cardinality(10_000)
+            .build();
+        cache.insert(0, 0);
+        cache.enable_frequency_sketch_for_testing();
+        // Sized for 10,000 keys rather than for the 100 entries.
+        assert_eq!(cache.frequency_sketch.table_len(), 16_384);
+
+        // Never smaller than for the max capacity.
+        let mut cache = Cache::builder()
+            .max_capacity(1000)
+            .expected_key_cardinality(10)
+            .build();
+        cache.insert(0, 0);
+        cache.enable_frequency_sketch_for_testing();
+        assert_eq!(cache.frequency_sketch.table_len(), 1024);
+    }
+
+    #[test]
+    fn take() {
+        let mut cache = Cache::new(2);
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        let node = cache.cache.get(&'a').unwrap().access_order_q_node();
+
+        assert_eq!(cache.take(&'a'), Some("alice"));
+        assert_eq!(cache.get_state(&'a'), Lookup::NegativeHit);
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.take(&'a'), None);
+        assert_eq!(cache.take(&'c'), None);
+
+        // The cache is full, and a new key 'c' is not admitted.
+        cache.insert('c', "cindy");
+        assert!(!cache.contains_key(&'c'));
+
+        // Re-inserting the taken key reuses the node of the tombstone.
+        cache.insert('a', "anne");
+        assert_eq!(cache.get(&'a'), Some(&"anne"));
+        assert_eq!(cache.cache.get(&'a').unwrap().access_order_q_node(), node);
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn take_without_negative_caching() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .negative_caching(false)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+
+        assert_eq!(cache.take(&'a'), Some("alice"));
+        assert_eq!(cache.get_state(&'a'), Lookup::Miss);
+        assert_eq!(cache.entry_count(), 1);
+
+        cache.insert_absent('b');
+        assert_eq!(cache.get_state(&'b'), Lookup::Miss);
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn clone_with_capacity() {
+        let mut cache = Cache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i.to_string());
+        }
+        // Make 0 and 1 the most recently used.
+        cache.get(&0);
+        cache.get(&1);
+
+        let mut small = cache.clone_with_capacity(Some(5));
+        assert_eq!(small.check_invariants(), Ok(()));
+        assert_eq!(small.policy().max_capacity(), Some(5));
+        assert_eq!(small.entry_count(), 5);
+        for i in [7, 8, 9, 0, 1] {
+            assert_eq!(small.get_quiet(&i), Some(&i.to_string()));
+        }
+
+        // The LRU order is kept.
+        small.evict_n(1);
+        assert!(!small.contains_key(&7));
+
+        // The popularity estimator is copied.
+        assert!(cache.frequency_sketch_enabled);
+        assert!(small.frequency_sketch_enabled);
+        let hash = cache.hash(&0);
+        assert_eq!(
+            small.frequency_sketch.frequency(hash),
+            cache.frequency_sketch.frequency(hash)
+        );
+
+        // The original cache is unchanged.
+        assert_eq!(cache.entry_count(), 10);
+
+        let large = cache.clone_with_capacity(None);
+        assert_eq!(large.entry_count(), 10);
+        assert_eq!(large.policy().max_capacity(), None);
+    }
+
+    #[test]
+    fn access_count() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        assert_eq!(cache.access_count(&'a'), Some(0));
+
+        for _ in 0..5 {
+            cache.get(&'a');
+        }
+        assert_eq!(cache.access_count(&'a'), Some(5));
+
+        // Not counted.
+        cache.get_quiet(&'a');
+        cache.contains_key(&'a');
+        assert_eq!(cache.access_count(&'a'), Some(5));
+
+        // Reset on update.
+        cache.insert('a', "anne");
+        assert_eq!(cache.access_count(&'a'), Some(0));
+        cache.get_state(&'a');
+        assert_eq!(cache.access_count(&'a'), Some(1));
+        assert_eq!(cache.compare_and_swap(&'a', &"anne", "amy"), Ok(()));
+        assert_eq!(cache.access_count(&'a'), Some(0));
+
+        // No value.
+        cache.insert_absent('b');
+        cache.get(&'b');
+        assert_eq!(cache.access_count(&'b'), None);
+        assert_eq!(cache.access_count(&'c'), None);
+    }
+
+    #[test]
+    fn target_load_factor() {
+        let cache = Cache::<u32, u32>::builder().max_capacity(1000).build();
+        let default_capacity = cache.cache.capacity();
+
+        let cache = Cache::<u32, u32>::builder()
+            .max_capacity(1000)
+            .target_load_factor(0.25)
+            .build();
+        let reserved = cache.cache.capacity();
+        assert!(reserved > default_capacity);
+        assert!(reserved >= 4000);
+
+        // The larger of the initial capacity and the one for the load factor.
+        let cache = Cache::<u32, u32>::builder()
+            .max_capacity(1000)
+            .initial_capacity(1000)
+            .target_load_factor(0.5)
+            .build();
+        assert!(cache.cache.capacity() >= 2000);
+    }
+
+    #[test]
+    fn invalidate_all_except() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', ("alice", false));
+        cache.insert('b', ("bob", true));
+        cache.insert('c', ("cindy", false));
+        cache.insert_absent('d');
+
+        cache.invalidate_all_except(|_, (_, pinned)| *pinned);
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.get(&'b'), Some(&("bob", true)));
+        assert_eq!(cache.get_state(&'d'), Lookup::Miss);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn invalidate_all_except_panic_safety() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        struct PanicOnDrop(bool);
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                if self.0 {
+                    panic!("panic on drop");
+                }
+            }
+        }
+
+        let mut cache = Cache::new(10);
+        cache.insert(0, PanicOnDrop(false));
+        cache.insert(1, PanicOnDrop(true));
+        cache.insert(2, PanicOnDrop(false));
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            cache.invalidate_all_except(|key, _| *key == 0);
+        }));
+        assert!(result.is_err());
+        assert_eq!(cache.entry_count(), 1);
+        assert!(cache.contains_key(&0));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn iter_ordered_mut() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(2)
+            .build();
+        for c in ['a', 'b', 'c', 'd'] {
+            cache.insert(c, Vec::new());
+        }
+        cache.insert_absent('e');
+        // Promote 'b' to protected, and make 'a' the MRU in probation.
+        cache.get(&'b');
+        cache.get(&'b');
+        cache.get(&'a');
+
+        // Record the visiting order into the values.
+        for (i, (_, v)) in cache.iter_ordered_mut().enumerate() {
+            v.push(i);
+        }
+        assert_eq!(cache.get_quiet(&'c'), Some(&vec![0]));
+        assert_eq!(cache.get_quiet(&'d'), Some(&vec![1]));
+        assert_eq!(cache.get_quiet(&'a'), Some(&vec![2]));
+        assert_eq!(cache.get_quiet(&'b'), Some(&vec![3]));
+
+        // The order is not changed by the visit.
+        let keys = cache
+            .iter_ordered_mut()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!['c', 'd', 'a', 'b']);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn next_eviction_key() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(1)
+            .build();
+        assert_eq!(cache.next_eviction_key(), None);
+
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        assert_eq!(cache.next_eviction_key(), Some(&'a'));
+
+        // Accessing 'a' promotes it to protected, leaving 'b' at the LRU end.
+        cache.get(&'a');
+        assert_eq!(cache.next_eviction_key(), Some(&'b'));
+        // Peeking does not change the order.
+        assert_eq!(cache.next_eviction_key(), Some(&'b'));
+
+        assert_eq!(cache.evict_n(2), 2);
+        // The probation region is empty, so the victim is taken from protected.
+        assert_eq!(cache.next_eviction_key(), Some(&'a'));
+        cache.evict_n(1);
+        assert_eq!(cache.next_eviction_key(), None);
+    }
+
+    #[test]
+    fn frequency_estimator() {
+        use crate::FrequencyEstimator;
+        use std::{
+            cell::RefCell,
+            collections::{hash_map::RandomState, HashMap},
+            rc::Rc,
+        };
+
+        // Records the calls, and estimates every key as never seen.
+        #[derive(Clone, Default)]
+        struct AlwaysZero {
+            increments: Rc<RefCell<HashMap<u64, u32>>>,
+            capacity: Rc<RefCell<Option<u64>>>,
+        }
+
+        impl FrequencyEstimator for AlwaysZero {
+            fn increment(&mut self, hash: u64) {
+                *self.increments.borrow_mut().entry(hash).or_default() += 1;
+            }
+
+            fn frequency(&self, _hash: u64) -> u8 {
+                0
+            }
+
+            fn ensure_capacity(&mut self, cap: u64) {
+                *self.capacity.borrow_mut() = Some(cap);
+            }
+
+            fn reset(&mut self) {
+                self.increments.borrow_mut().clear();
+            }
+        }
+
+        let estimator = AlwaysZero::default();
+        let mut cache = Cache::builder()
+            .max_capacity(4)
+            .expected_key_cardinality(100)
+            .frequency_estimator(estimator.clone())
+            .build();
+
+        cache.insert('a', "alice");
+        assert_eq!(*estimator.capacity.borrow(), None);
+        cache.insert('b', "bob");
+        // The estimator is enabled when the cache is half full.
+        assert_eq!(*estimator.capacity.borrow(), Some(100));
+        cache.insert('c', "cindy");
+        cache.insert('d', "david");
+
+        cache.get(&'a');
+        cache.get(&'a');
+        assert_eq!(estimator.increments.borrow()[&cache.hash(&'a')], 2);
+
+        // A new key is as popular as the victim, so it is rejected and the
+        // contents stay as is, however often the new key is read.
+        cache.get(&'e');
+        cache.insert('e', "emily");
+        assert!(!cache.contains_key(&'e'));
+        assert_eq!(cache.entry_count(), 4);
+
+        // The estimator is reset on rehashing.
+        let cache = cache.rehash(RandomState::default());
+        assert!(estimator.increments.borrow().is_empty());
+        assert_eq!(cache.entry_count(), 4);
+    }
+
+    #[test]
+    fn capacity_remaining() {
+        let mut cache = Cache::new(10);
+        assert_eq!(cache.capacity_remaining(), Some(10));
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        assert_eq!(cache.capacity_remaining(), Some(7));
+
+        cache.set_max_capacity(2);
+        assert_eq!(cache.capacity_remaining(), Some(0));
+
+        let mut cache = Cache::builder().build();
+        cache.insert('a', "alice");
+        assert_eq!(cache.capacity_remaining(), None);
+    }
+
+    #[test]
+    fn lazy_eviction() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .lazy_eviction(true)
+            .build();
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.set_max_capacity(5);
+        assert!(!cache.is_maintenance_due());
+
+        // Reads do not shrink the over-capacity cache.
+        assert_eq!(cache.get_quiet(&0), Some(&0));
+        assert_eq!(cache.get(&1), Some(&1));
+        assert!(cache.contains_key(&2));
+        assert_eq!(cache.entry_count(), 10);
+        assert!(cache.is_maintenance_due());
+
+        // An insert does.
+        cache.insert(10, 10);
+        assert_eq!(cache.entry_count(), 5);
+        assert!(!cache.is_maintenance_due());
+
+        cache.set_max_capacity(2);
+        cache.get_quiet(&10);
+        assert_eq!(cache.entry_count(), 5);
+        assert!(cache.is_maintenance_due());
+        assert_eq!(cache.run_pending_maintenance(), 3);
+        assert_eq!(cache.entry_count(), 2);
+        assert!(!cache.is_maintenance_due());
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn total_evicted_and_rejected() {
+        use crate::InsertPolicy;
+
+        let mut cache = Cache::new(2);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.get(&'a');
+        cache.get(&'b');
+        assert_eq!(cache.total_evicted(), 0);
+        assert_eq!(cache.total_rejected(), 0);
+
+        // Rejected by the admission policy.
+        cache.insert('c', "cindy");
+        assert_eq!(cache.total_rejected(), 1);
+
+        // Admitted, evicting 'a'.
+        for _ in 0..3 {
+            cache.get(&'d');
+        }
+        cache.insert('d', "david");
+        assert_eq!(cache.total_evicted(), 1);
+        assert_eq!(cache.total_rejected(), 1);
+
+        // Evicted over the max capacity.
+        cache.set_max_capacity(1);
+        cache.insert('b', "bill");
+        assert_eq!(cache.total_evicted(), 2);
+        cache.evict_n(1);
+        assert_eq!(cache.total_evicted(), 3);
+
+        // Invalidations are not counted.
+        cache.insert('e', "emily");
+        cache.invalidate(&'e');
+        assert_eq!(cache.total_evicted(), 3);
+
+        // Rejected when full.
+        let mut cache = Cache::builder()
+            .max_capacity(1)
+            .insert_policy(InsertPolicy::RejectWhenFull)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        assert_eq!(cache.total_rejected(), 1);
+        assert_eq!(cache.total_evicted(), 0);
+    }
+
+    #[test]
+    fn drain_filter() {
+        let mut cache = Cache::new(10);
+        for i in 0..6 {
+            cache.insert(i, i * 10);
+        }
+        cache.insert_absent(6);
+        let version = cache.version();
+
+        // Drain the odd keys, and double the values of the others.
+        let mut drained = cache.drain_filter(|k, v| {
+            if k % 2 == 1 {
+                true
+            } else {
+                *v *= 2;
+                false
+            }
+        });
+        drained.sort_unstable();
+        assert_eq!(drained, vec![(1, 10), (3, 30), (5, 50)]);
+        assert_eq!(cache.entry_count(), 4);
+        assert_ne!(cache.version(), version);
+        assert_eq!(cache.get(&2), Some(&40));
+        assert_eq!(cache.get_state(&6), Lookup::NegativeHit);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        let version = cache.version();
+        assert!(cache.drain_filter(|_, _| false).is_empty());
+        assert_eq!(cache.version(), version);
+    }
+
+    #[test]
+    fn frequency_sketch_activation() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .frequency_sketch_activation(0.0)
+            .build();
+        assert!(!cache.frequency_sketch_enabled);
+        cache.insert('a', "alice");
+        assert!(cache.frequency_sketch_enabled);
+
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .frequency_sketch_activation(0.9)
+            .build();
+        for i in 0..8 {
+            cache.insert(i, i);
+        }
+        assert!(!cache.frequency_sketch_enabled);
+        cache.insert(8, 8);
+        assert!(cache.frequency_sketch_enabled);
+    }
+
+    #[test]
+    fn replay_accesses() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .frequency_sketch_activation(0.0)
+            .build();
+        let trace = vec![
+            ('a', "alice"),
+            ('b', "bob"),
+            ('a', "anne"),
+            ('c', "cindy"),
+            ('a', "amy"),
+        ];
+        let version = cache.version();
+        cache.replay_accesses(trace);
+
+        assert_eq!(cache.entry_count(), 3);
+        // The repeats were hits, not updates.
+        assert_eq!(cache.get_quiet(&'a'), Some(&"alice"));
+        assert_eq!(cache.access_count(&'a'), Some(2));
+        assert_eq!(cache.access_count(&'b'), Some(0));
+        assert_eq!(cache.version(), version + 3);
+
+        // The hot key gained popularity.
+        let freq = |c| cache.frequency_sketch.frequency(cache.hash(&c));
+        assert!(freq('a') > freq('b'));
+    }
+
+    #[test]
+    fn summary() {
+        let mut cache = Cache::new(2);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.get(&'a');
+        cache.get(&'b');
+        cache.insert('c', "cindy");
+        cache.evict_n(1);
+
+        let summary = cache.summary();
+        assert_eq!(summary.entry_count(), 1);
+        assert_eq!(summary.max_capacity(), Some(2));
+        assert_eq!(summary.total_evicted(), 1);
+        assert_eq!(summary.total_rejected(), 1);
+        assert_eq!(summary.to_string(), "entries: 1/2, evicted: 1, rejected: 1");
+        assert_eq!(
+            format!("{:?}", summary),
+            "CacheSummary { entry_count: 1, max_capacity: Some(2), \
+             total_evicted: 1, total_rejected: 1 }"
+        );
+
+        let mut cache = Cache::builder().build();
+        cache.insert('a', "alice");
+        assert_eq!(
+            cache.summary().to_string(),
+            "entries: 1/unbounded, evicted: 0, rejected: 0"
+        );
+    }
+
+    #[test]
+    fn evict_by_score() {
+        let mut cache = Cache::new(10);
+        let timestamps = [('a', 50), ('b', 10), ('c', 40), ('d', 20), ('e', 30)];
+        for (key, timestamp) in timestamps {
+            cache.insert(key, timestamp);
+        }
+        cache.insert_absent('f');
+
+        // Evict the two oldest ones.
+        assert_eq!(cache.evict_by_score(2, |_, v| *v), 2);
+        assert_eq!(cache.entry_count(), 4);
+        assert!(!cache.contains_key(&'b'));
+        assert!(!cache.contains_key(&'d'));
+        assert!(cache.contains_all(&[&'a', &'c', &'e']));
+        assert_eq!(cache.total_evicted(), 2);
+
+        // The tombstone is not evicted.
+        assert_eq!(cache.evict_by_score(10, |_, v| *v), 3);
+        assert_eq!(cache.get_state(&'f'), Lookup::NegativeHit);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn invalidate_stale() {
+        let mut cache = Cache::new(20);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.insert_absent(10);
+
+        assert_eq!(cache.invalidate_stale(|_, v| v % 3 == 0), 4);
+        assert_eq!(cache.entry_count(), 7);
+        assert!(!cache.contains_key(&9));
+        assert!(cache.contains_key(&8));
+
+        assert_eq!(cache.invalidate_stale(|_, _| false), 0);
+        assert_eq!(cache.get_state(&10), Lookup::NegativeHit);
+    }
+
+    #[test]
+    fn iter_nth_and_last() {
+        let mut cache = Cache::new(10);
+        for i in 0..5 {
+            cache.insert(i, i * 10);
+        }
+        cache.insert_absent(5);
+
+        let entries = cache.iter().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(cache.iter().count(), 5);
+        assert_eq!(cache.iter().nth(2), Some(entries[2]));
+        assert_eq!(cache.iter().nth(5), None);
+        assert_eq!(cache.iter().last(), Some(entries[4]));
+
+        let mut iter = cache.iter();
+        assert_eq!(iter.nth(1), Some(entries[1]));
+        assert_eq!(iter.next(), Some(entries[2]));
+        assert_eq!(iter.size_hint().0, 0);
+    }
+
+    #[test]
+    fn force_insert() {
+        let mut cache = Cache::new(3);
+        cache.enable_frequency_sketch_for_testing();
+        for c in ['a', 'b', 'c'] {
+            cache.insert(c, c);
+            cache.get(&c);
+        }
+
+        // A cold key is rejected by insert.
+        cache.insert('d', 'd');
+        assert!(!cache.contains_key(&'d'));
+
+        // But not by force_insert, which evicts the LRU entry.
+        cache.force_insert('d', 'd');
+        assert!(cache.contains_key(&'d'));
+        assert!(!cache.contains_key(&'a'));
+        assert_eq!(cache.entry_count(), 3);
+        assert_eq!(cache.total_evicted(), 1);
+
+        // Updating an existing key evicts nothing.
+        cache.force_insert('b', 'B');
+        assert_eq!(cache.get(&'b'), Some(&'B'));
+        assert_eq!(cache.entry_count(), 3);
+        assert_eq!(cache.total_evicted(), 1);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        let mut cache = Cache::new(0);
+        cache.force_insert('a', 'a');
+        assert_eq!(cache.entry_count(), 0);
+    }
+
+    #[test]
+    fn remove_entry() {
+        let mut cache = Cache::new(10);
+        cache.insert("alice".to_string(), 1);
+        cache.insert("bob".to_string(), 2);
+        cache.insert_absent("cindy".to_string());
+
+        assert_eq!(
+            cache.remove_entry(&"alice".to_string()),
+            Some(("alice".to_string(), 1))
+        );
+        assert_eq!(cache.remove_entry(&"alice".to_string()), None);
+        assert_eq!(cache.remove_entry(&"david".to_string()), None);
+        assert_eq!(cache.entry_count(), 2);
+
+        // The tombstone is removed.
+        assert_eq!(cache.remove_entry(&"cindy".to_string()), None);
+        assert_eq!(cache.get_state(&"cindy".to_string()), Lookup::Miss);
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn get_many_mut() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', 1);
+        cache.insert('b', 2);
+        cache.insert('c', 3);
+        cache.insert_absent('d');
+
+        let [a, c] = cache.get_many_mut([&'a', &'c']).unwrap();
+        *a += 10;
+        *c += 30;
+        assert_eq!(cache.get_quiet(&'a'), Some(&11));
+        assert_eq!(cache.get_quiet(&'b'), Some(&2));
+        assert_eq!(cache.get_quiet(&'c'), Some(&33));
+        // The reads were recorded.
+        assert_eq!(cache.access_count(&'a'), Some(1));
+        assert_eq!(cache.access_count(&'b'), Some(0));
+
+        assert_eq!(cache.get_many_mut([&'a', &'e']), None);
+        assert_eq!(cache.get_many_mut([&'a', &'d']), None);
+        assert_eq!(cache.get_many_mut::<char, 0>([]), Some([]));
+    }
+
+    #[test]
+    fn get_many_mut_duplicate_keys() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', 1);
+        cache.insert('b', 2);
+
+        assert_eq!(cache.get_many_mut([&'a', &'a']), None);
+        assert_eq!(cache.get_many_mut([&'a', &'b', &'a']), None);
+        assert_eq!(cache.get_many_mut([&'b']), Some([&mut 2]));
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut cache = Cache::builder()
+            .max_capacity(3)
+            .track_insertion_order(true)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        assert_eq!(cache.insertion_order(), vec![&'a', &'b', &'c']);
+
+        // Accessing or updating the entries does not change the order.
+        cache.get(&'c');
+        cache.get(&'a');
+        cache.get(&'b');
+        cache.insert('a', "alex");
+        assert_eq!(cache.insertion_order(), vec![&'a', &'b', &'c']);
+        assert_eq!(cache.next_eviction_key(), Some(&'c'));
+
+        // A removed key is dropped from the order, and goes to the end when it
+        // is inserted again.
+        cache.invalidate(&'b');
+        cache.insert('b', "bill");
+        assert_eq!(cache.insertion_order(), vec![&'a', &'c', &'b']);
+
+        let mut cache = cache.clone_with_capacity(Some(2));
+        assert_eq!(cache.insertion_order(), vec![&'a', &'b']);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Compacting keeps the order, and the entries point to the new nodes.
+        cache.compact();
+        assert_eq!(cache.insertion_order(), vec![&'a', &'b']);
+        cache.invalidate(&'a');
+        cache.insert('c', "cindy");
+        assert_eq!(cache.insertion_order(), vec![&'b', &'c']);
+
+        // Not tracked by default.
+        let mut cache = Cache::new(3);
+        cache.insert('a', "alice");
+        assert!(cache.insertion_order().is_empty());
+    }
+
+    #[test]
+    fn insert_lazy() {
+        use std::{cell::Cell, rc::Rc};
+
+        let mut cache = Cache::new(10);
+        let runs = Rc::new(Cell::new(0));
+
+        let r = Rc::clone(&runs);
+        cache.insert_lazy('a', move || {
+            r.set(r.get() + 1);
+            "alice"
+        });
+        assert_eq!(runs.get(), 0);
+        assert!(cache.contains_key(&'a'));
+        assert_eq!(cache.get_quiet(&'a'), None);
+        assert_eq!(runs.get(), 0);
+
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+        assert_eq!(runs.get(), 1);
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+        assert_eq!(runs.get(), 1);
+
+        // Replacing a pending slot runs the initializer to get the old value,
+        // so the update is not reported as an insert.
+        let r = Rc::clone(&runs);
+        cache.insert_lazy('b', move || {
+            r.set(r.get() + 1);
+            "bob"
+        });
+        assert_eq!(
+            cache.insert_detailed('b', "bill"),
+            UpsertResult::Updated("bob")
+        );
+        assert_eq!(runs.get(), 2);
+        assert_eq!(cache.get(&'b'), Some(&"bill"));
+        assert_eq!(runs.get(), 2);
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.check_invariants(), Ok(()));
+
+        // Removing a pending slot does not run the initializer.
+        let r = Rc::clone(&runs);
+        cache.insert_lazy('c', move || {
+            r.set(r.get() + 1);
+            "cindy"
+        });
+        cache.invalidate(&'c');
+        assert_eq!(runs.get(), 2);
+    }
+
+    #[test]
+    fn compare_and_swap_lazy_value() {
+        let mut cache = Cache::new(10);
+        cache.insert_lazy('a', || 1);
+        assert_eq!(cache.compare_and_swap(&'a', &0, 2), Err(Some(1)));
+
+        cache.insert_lazy('b', || 1);
+        assert_eq!(cache.compare_and_swap(&'b', &1, 2), Ok(()));
+        assert_eq!(cache.get(&'b'), Some(&2));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn preview_admission() {
+        use crate::EvictionPolicy;
+
+        let mut cache = Cache::new(3);
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        cache.get(&'a');
+
+        // The cache is full and 'd' is not popular enough.
+        let preview = cache.preview_admission(&'d');
+        assert!(!preview.is_admitted());
+        assert!(preview.victims().is_empty());
+        assert!(cache.preview_admission(&'b').is_admitted());
+
+        cache.get(&'d');
+        cache.get(&'d');
+        let preview = cache.preview_admission(&'d');
+        assert!(preview.is_admitted());
+        assert_eq!(preview.victims(), &[&'b']);
+        let evicted = cache.insert_and_collect_evicted('d', "david");
+        assert_eq!(evicted, vec![('b', "bob")]);
+
+        // The entries over a lowered capacity are evicted even if the key is
+        // rejected.
+        cache.set_max_capacity(2);
+        let preview = cache.preview_admission(&'e');
+        assert!(!preview.is_admitted());
+        assert_eq!(preview.victims(), &[&'c']);
+        let evicted = cache.insert_and_collect_evicted('e', "emily");
+        assert_eq!(evicted, vec![('c', "cindy")]);
+
+        // The CLOCK policy skips the referenced entries.
+        let mut cache = Cache::builder()
+            .max_capacity(3)
+            .eviction_policy(EvictionPolicy::Clock)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        cache.get(&'a');
+        let preview = cache.preview_admission(&'d');
+        assert!(preview.is_admitted());
+        assert_eq!(preview.victims(), &[&'b']);
+        let evicted = cache.insert_and_collect_evicted('d', "david");
+        assert_eq!(evicted, vec![('b', "bob")]);
+    }
+
+    #[test]
+    fn recent() {
+        let mut cache = Cache::new(10);
+        assert!(cache.recent(2).is_empty());
+
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        cache.insert_absent('d');
+        cache.get(&'b');
+        cache.get(&'a');
+
+        assert_eq!(cache.recent(2), vec![(&'a', &"alice"), (&'b', &"bob")]);
+        // The tombstone of 'd' is skipped.
+        assert_eq!(
+            cache.recent(10),
+            vec![(&'a', &"alice"), (&'b', &"bob"), (&'c', &"cindy")]
+        );
+        assert!(cache.recent(0).is_empty());
+    }
+
+    #[test]
+    fn least_recent() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(1)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert_absent('c');
+        cache.insert('d', "david");
+        cache.get(&'a');
+
+        // 'a' is in the protected region, behind the probation region.
+        assert_eq!(
+            cache.least_recent(2),
+            vec![(&'b', &"bob"), (&'d', &"david")]
+        );
+        assert_eq!(cache.least_recent(3).last(), Some(&(&'a', &"alice")));
+        assert_eq!(cache.next_eviction_key(), Some(&'b'));
+
+        let evicted = cache.evict_n(2);
+        assert_eq!(evicted, 2);
+        assert!(!cache.contains_key(&'b'));
+        assert_eq!(
+            cache.least_recent(2),
+            vec![(&'d', &"david"), (&'a', &"alice")]
+        );
+    }
+
+    #[test]
+    fn recent_hit_rate() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .hit_rate_window(80)
+            .build();
+        assert_eq!(cache.recent_hit_rate(), 1.0);
+
+        cache.insert('a', "alice");
+        for _ in 0..100 {
+            cache.get(&'a');
+        }
+        assert_eq!(cache.recent_hit_rate(), 1.0);
+
+        // The misses push the hits out of the window.
+        for _ in 0..40 {
+            cache.get(&'b');
+        }
+        let rate = cache.recent_hit_rate();
+        assert!(rate > 0.3 && rate < 0.6, "{}", rate);
+        for _ in 0..100 {
+            cache.get(&'b');
+        }
+        assert_eq!(cache.recent_hit_rate(), 0.0);
+
+        // Cached absences are hits.
+        cache.insert_absent('b');
+        for _ in 0..100 {
+            cache.get(&'b');
+        }
+        assert_eq!(cache.recent_hit_rate(), 1.0);
+    }
+
+    #[test]
+    fn freeze() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert_absent('c');
+        cache.insert_lazy('d', || "david");
+        cache.get(&'a');
+
+        let frozen = cache.freeze();
+        assert_eq!(frozen.len(), 3);
+        assert_eq!(frozen.get(&'a'), Some(&"alice"));
+        assert_eq!(frozen.get(&'b'), Some(&"bob"));
+        assert_eq!(frozen.get(&'d'), Some(&"david"));
+        // The tombstone is dropped.
+        assert!(!frozen.contains_key(&'c'));
+        assert_eq!(frozen.get(&'e'), None);
+
+        let mut entries = frozen.iter().collect::<Vec<_>>();
+        entries.sort_unstable();
+        assert_eq!(
+            entries,
+            vec![(&'a', &"alice"), (&'b', &"bob"), (&'d', &"david")]
+        );
+    }
+
+    #[test]
+    fn partition() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .track_insertion_order(true)
+            .build();
+        for (key, value) in [('a', 1), ('b', 2), ('c', 3), ('d', 4), ('e', 5)] {
+            cache.insert(key, value);
+        }
+        cache.insert_absent('f');
+        cache.get(&'a');
+
+        let (mut odd, mut even) = cache.partition(|_, v| v % 2 == 1);
+        assert_eq!(odd.entry_count(), 3);
+        assert_eq!(even.entry_count(), 2);
+        for key in ['a', 'c', 'e'] {
+            assert!(odd.contains_key(&key));
+            assert!(!even.contains_key(&key));
+        }
+        for key in ['b', 'd'] {
+            assert!(even.contains_key(&key));
+            assert!(!odd.contains_key(&key));
+        }
+        // The tombstone is dropped.
+        assert_eq!(odd.get_state(&'f'), Lookup::Miss);
+        assert_eq!(even.get_state(&'f'), Lookup::Miss);
+
+        // The LRU order and the insertion order are kept.
+        assert_eq!(odd.next_eviction_key(), Some(&'c'));
+        assert_eq!(odd.insertion_order(), vec![&'a', &'c', &'e']);
+        assert_eq!(even.insertion_order(), vec![&'b', &'d']);
+        assert_eq!(odd.policy().max_capacity(), Some(10));
+        assert_eq!(odd.check_invariants(), Ok(()));
+        assert_eq!(even.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn partition_notifies_evictions() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&evicted);
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .lazy_eviction(true)
+            .batch_eviction_listener(move |batch: Vec<(u32, u32)>| sink.borrow_mut().extend(batch))
+            .build();
+        cache.enable_frequency_sketch_for_testing();
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        // Make the most recent entries popular enough to evict the others.
+        for _ in 0..3 {
+            for i in 5..10 {
+                cache.get(&i);
+            }
+        }
+        // The eviction of the entries over the new capacity is deferred.
+        cache.set_max_capacity(3);
+        assert!(evicted.borrow().is_empty());
+
+        let (left, right) = cache.partition(|_, _| true);
+        assert_eq!(left.entry_count(), 3);
+        assert_eq!(right.entry_count(), 0);
+        // Every entry not kept was either evicted and notified, or rejected.
+        let evicted = evicted.borrow().len() as u64;
+        assert!(evicted > 0);
+        assert_eq!(evicted + left.total_rejected(), 7);
+    }
+
+    #[test]
+    fn batch_eviction_listener() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let batches = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&batches);
+        let mut cache = Cache::builder()
+            .max_capacity(1001)
+            .batch_eviction_listener(move |batch: Vec<(u32, u32)>| sink.borrow_mut().push(batch))
+            .build();
+        cache.insert_absent(1000);
+        for i in 0..1000 {
+            cache.insert(i, i * 10);
+        }
+        assert!(batches.borrow().is_empty());
+
+        // Shrinking the capacity by a lot fires the listener once. The tombstone
+        // of 1000 is evicted but not passed.
+        cache.set_max_capacity(10);
+        assert_eq!(cache.run_pending_maintenance(), 991);
+        {
+            let batches = batches.borrow();
+            assert_eq!(batches.len(), 1);
+            let expected = (0..990).map(|i| (i, i * 10)).collect::<Vec<_>>();
+            assert_eq!(batches[0], expected);
+        }
+
+        batches.borrow_mut().clear();
+        assert_eq!(cache.evict_n(2), 2);
+        assert_eq!(*batches.borrow(), vec![vec![(990, 9900), (991, 9910)]]);
+
+        // Invalidated entries are not passed.
+        batches.borrow_mut().clear();
+        cache.invalidate(&992);
+        assert!(batches.borrow().is_empty());
+    }
+
+    #[test]
+    fn estimated_distinct_keys() {
+        let mut cache = Cache::builder()
+            .max_capacity(100)
+            .track_cardinality(true)
+            .build();
+        assert_eq!(cache.estimated_distinct_keys(), 0);
+
+        for i in 0..50_000u32 {
+            cache.insert(i, ());
+            // The repeated keys are not counted again.
+            cache.insert(i / 2, ());
+        }
+        let estimate = cache.estimated_distinct_keys();
+        assert!((45_000..=55_000).contains(&estimate), "{}", estimate);
+        assert_eq!(
+            cache
+                .clone_with_capacity(Some(10))
+                .estimated_distinct_keys(),
+            estimate
+        );
+
+        // Not tracked by default.
+        let mut cache = Cache::new(100);
+        cache.insert(0, ());
+        assert_eq!(cache.estimated_distinct_keys(), 0);
+    }
+
+    #[test]
+    fn read_without_maintenance() {
+        fn read(cache: &Cache<char, u32>) {
+            assert_eq!(cache.peek(&'a'), Some(&1));
+            assert_eq!(cache.peek(&'b'), None);
+            assert_eq!(cache.peek(&'c'), None);
+            assert!(cache.contains_key_ref(&'a'));
+            assert!(!cache.contains_key_ref(&'b'));
+            assert!(!cache.contains_key_ref(&'c'));
+            assert!(cache.contains_all(&[&'a', &'d']));
+            assert!(cache.contains_any(&[&'b', &'d']));
+            assert_eq!(cache.iter().count(), 2);
+            assert_eq!(cache.entry_count(), 3);
+        }
+
+        let mut cache = Cache::new(10);
+        cache.insert('a', 1);
+        cache.insert_absent('b');
+        cache.insert('d', 4);
+        read(&cache);
+
+        // The reads do not evict the entries over the max capacity, nor update
+        // the LRU order.
+        cache.set_max_capacity(2);
+        read(&cache);
+        assert_eq!(cache.next_eviction_key(), Some(&'a'));
+    }
+
+    #[test]
+    fn to_vec_by_recency() {
+        let mut cache = Cache::new(10);
+        for (key, value) in [('a', 1), ('b', 2), ('c', 3), ('d', 4)] {
+            cache.insert(key, value);
+        }
+        cache.insert_absent('e');
+        cache.get(&'b');
+        cache.get(&'a');
+
+        let expected = cache
+            .deques
+            .probation
+            .iter()
+            .filter(|kh| cache.contains_key_ref(&kh.key))
+            .map(|kh| *kh.key)
+            .collect::<Vec<_>>();
+        assert_eq!(expected, vec!['c', 'd', 'b', 'a']);
+
+        let entries = cache.to_vec_by_recency();
+        assert_eq!(entries, vec![('c', 3), ('d', 4), ('b', 2), ('a', 1)]);
+    }
+
+    #[test]
+    fn prune_orphans() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .track_insertion_order(true)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        assert_eq!(cache.prune_orphans(), 0);
+
+        // Remove the entry from the hash map only, leaving its nodes in the
+        // access-order and write-order queues.
+        cache.cache.remove(&'b');
+        assert!(cache.check_invariants().is_err());
+
+        assert_eq!(cache.prune_orphans(), 2);
+        assert_eq!(cache.entry_count(), 2);
+        assert_eq!(cache.check_invariants(), Ok(()));
+        assert_eq!(cache.insertion_order(), vec![&'a', &'c']);
+        assert_eq!(cache.prune_orphans(), 0);
+    }
+
+    #[test]
+    fn get_touch_lru_only() {
+        let mut cache = Cache::new(10);
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        let lru_keys = |cache: &Cache<char, &str>| {
+            cache
+                .least_recent(3)
+                .into_iter()
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>()
+        };
+
+        let hash_a = cache.hash(&'a');
+        let freq_a = cache.frequency_sketch.frequency(hash_a);
+        assert_eq!(cache.get_touch_lru_only(&'a'), Some(&"alice"));
+        assert_eq!(lru_keys(&cache), vec!['b', 'c', 'a']);
+        assert_eq!(cache.frequency_sketch.frequency(hash_a), freq_a);
+
+        assert_eq!(cache.get_touch_lru_only(&'d'), None);
+
+        // A plain `get` bumps both.
+        let hash_b = cache.hash(&'b');
+        let freq_b = cache.frequency_sketch.frequency(hash_b);
+        assert_eq!(cache.get(&'b'), Some(&"bob"));
+        assert_eq!(lru_keys(&cache), vec!['c', 'a', 'b']);
+        assert_eq!(cache.frequency_sketch.frequency(hash_b), freq_b + 1);
+    }
+
+    #[test]
+    fn cost_served() {
+        const COST: u64 = 7;
+
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .cost_fn(|_, _| COST)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert_absent('c');
+        assert_eq!(cache.cost_served(), 0);
+
+        let mut hit_count = 0;
+        for key in ['a', 'b', 'c', 'd', 'a'].iter() {
+            if cache.get(key).is_some() {
+                hit_count += 1;
+            }
+        }
+        assert_eq!(hit_count, 3);
+        assert_eq!(cache.cost_served(), hit_count * COST);
 
-    fn has_enough_capacity(&self, candidate_weight: u32, ws: u64) -> bool {
-        self.max_capacity
-            .map(|limit| ws + candidate_weight as u64 <= limit)
-            .unwrap_or(true)
+        // Reads not recording an access are not counted.
+        cache.peek(&'a');
+        cache.get_quiet(&'a');
+        assert_eq!(cache.cost_served(), hit_count * COST);
+
+        // A cache without a cost function does not count.
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.get(&'a');
+        assert_eq!(cache.cost_served(), 0);
     }
 
-    fn weights_to_evict(&self) -> u64 {
-        self.max_capacity
-            .map(|limit| self.entry_count.saturating_sub(limit))
-            .unwrap_or_default()
+    #[test]
+    fn reset_stats() {
+        let mut cache = Cache::builder()
+            .max_capacity(2)
+            .track_cardinality(true)
+            .cost_fn(|_, _| 1)
+            .build();
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.get(&'a');
+        cache.get(&'b');
+        cache.get(&'z');
+        // 'c' is rejected as it is less popular than 'a' and 'b'.
+        cache.insert('c', "cindy");
+        cache.evict_n(1);
+        assert_eq!(cache.total_evicted(), 1);
+        assert_eq!(cache.total_rejected(), 1);
+        assert_eq!(cache.cost_served(), 2);
+        assert!(cache.recent_hit_rate() < 1.0);
+        assert!(cache.estimated_distinct_keys() > 0);
+        let hash = cache.hash(&'b');
+        let freq = cache.frequency_sketch.frequency(hash);
+
+        cache.reset_stats();
+        assert_eq!(cache.total_evicted(), 0);
+        assert_eq!(cache.total_rejected(), 0);
+        assert_eq!(cache.cost_served(), 0);
+        assert_eq!(cache.recent_hit_rate(), 1.0);
+        assert_eq!(cache.estimated_distinct_keys(), 0);
+        // The contents and the popularity are kept.
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.frequency_sketch.frequency(hash), freq);
+
+        cache.get(&'b');
+        cache.get(&'z');
+        cache.evict_n(1);
+        assert_eq!(cache.total_evicted(), 1);
+        assert_eq!(cache.cost_served(), 1);
+        assert_eq!(cache.recent_hit_rate(), 0.5);
     }
 
-    #[inline]
-    fn should_enable_frequency_sketch(&self) -> bool {
-        if self.frequency_sketch_enabled {
-            false
-        } else if let Some(max_cap) = self.max_capacity {
-            self.entry_count >= max_cap / 2
-        } else {
-            false
-        }
+    #[test]
+    fn test_hasher_is_reproducible() {
+        let run = || {
+            let mut cache = Cache::builder().max_capacity(8).build_with_test_hasher();
+            cache.enable_frequency_sketch_for_testing();
+            let mut evicted = Vec::new();
+            // Interleave a few hot keys with one-hit wonders.
+            for i in 0..300u32 {
+                let key = if i % 2 == 0 { i % 10 } else { 1000 + i };
+                if cache.get(&key).is_none() {
+                    let victims = cache.insert_and_collect_evicted(key, i);
+                    evicted.extend(victims.into_iter().map(|(k, _)| k));
+                }
+            }
+            let mut keys = cache.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+            keys.sort_unstable();
+            (cache.hash(&1), evicted, keys, cache.total_rejected())
+        };
+
+        let first = run();
+        assert!(!first.1.is_empty());
+        assert!(first.3 > 0);
+        assert_eq!(first, run());
     }
 
-    #[inline]
-    fn enable_frequency_sketch(&mut self) {
-        if let Some(max_cap) = self.max_capacity {
-            self.do_enable_frequency_sketch(max_cap);
+    #[test]
+    fn is_over_capacity() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .lazy_eviction(true)
+            .build();
+        for i in 0..10 {
+            cache.insert(i, i);
         }
+        assert!(!cache.is_over_capacity());
+
+        cache.set_max_capacity(5);
+        // The read defers the eviction.
+        cache.get_quiet(&0);
+        assert!(cache.is_maintenance_due());
+        assert!(cache.is_over_capacity());
+
+        cache.run_pending_maintenance();
+        assert_eq!(cache.entry_count(), 5);
+        assert!(!cache.is_over_capacity());
+
+        let mut unbounded = Cache::builder().build();
+        unbounded.insert(0, 0);
+        assert!(!unbounded.is_over_capacity());
     }
 
-    #[cfg(test)]
-    fn enable_frequency_sketch_for_testing(&mut self) {
-        if let Some(max_cap) = self.max_capacity {
-            self.do_enable_frequency_sketch(max_cap);
-        }
+    fn cache_with_orphaned_victim(skip_orphaned_victims: bool) -> Cache<char, &'static str> {
+        let mut cache = Cache::builder()
+            .max_capacity(2)
+            .skip_orphaned_victims(skip_orphaned_victims)
+            .build();
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+
+        // Remove the LRU entry from the hash map only, leaving its node at the
+        // front of the probation queue.
+        cache.cache.remove(&'a');
+        cache.entry_count -= 1;
+        cache.set_max_capacity(1);
+        // Make 'c' more popular than 'b'.
+        cache.get(&'c');
+        cache.get(&'c');
+        cache
     }
 
-    #[inline]
-    fn do_enable_frequency_sketch(&mut self, cache_capacity: u64) {
-        let skt_capacity = common::sketch_capacity(cache_capacity);
-        self.frequency_sketch.ensure_capacity(skt_capacity);
-        self.frequency_sketch_enabled = true;
+    #[test]
+    fn skip_orphaned_victims() {
+        let mut cache = cache_with_orphaned_victim(true);
+        cache.insert('c', "cindy");
+        assert!(cache.contains_key(&'c'));
+        assert!(!cache.contains_key(&'b'));
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.total_evicted(), 1);
+        assert_eq!(cache.check_invariants(), Ok(()));
     }
 
-    #[inline]
-    fn handle_insert(&mut self, key: Rc<K>, hash: u64, policy_weight: u32) {
-        debug_assert_eq!(policy_weight, 1);
-        let has_free_space = self.has_enough_capacity(policy_weight, self.entry_count);
-        let (cache, deqs, freq) = (&mut self.cache, &mut self.deques, &self.frequency_sketch);
+    #[test]
+    #[should_panic(expected = "Cannot remove a victim from the hash map")]
+    fn orphaned_victim_panics_by_default() {
+        let mut cache = cache_with_orphaned_victim(false);
+        cache.insert('c', "cindy");
+    }
 
-        if has_free_space {
-            // Add the candidate to the deque.
-            let key = Rc::clone(&key);
-            let entry = cache.get_mut(&key).unwrap();
-            deqs.push_back_ao(
-                CacheRegion::MainProbation,
-                KeyHashDate::new(Rc::clone(&key), hash),
-                entry,
-            );
-            self.entry_count += 1;
-            // self.saturating_add_to_total_weight(policy_weight as u64);
+    #[test]
+    fn group_by_frequency() {
+        let mut cache = Cache::new(10);
+        assert!(cache.group_by_frequency().is_empty());
 
-            if self.should_enable_frequency_sketch() {
-                self.enable_frequency_sketch();
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        cache.insert('d', "david");
+        cache.insert_absent('e');
+        for (key, reads) in [('a', 3), ('b', 1), ('c', 3), ('e', 2)].iter() {
+            for _ in 0..*reads {
+                cache.get(key);
             }
-
-            return;
         }
 
-        if let Some(max) = self.max_capacity {
-            if policy_weight as u64 > max {
-                // The candidate is too big to fit in the cache. Reject it.
-                cache.remove(&Rc::clone(&key));
-                return;
+        let groups = cache.group_by_frequency();
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![0, 1, 3]);
+        assert_eq!(groups[&0], vec![&'d']);
+        assert_eq!(groups[&1], vec![&'b']);
+        assert_eq!(groups[&3], vec![&'a', &'c']);
+    }
+
+    #[test]
+    fn load_with_frequencies() {
+        let snapshot = || vec![('a', "alice", 4), ('b', "bob", 4)];
+        // Reads 'c' three times, then inserts it.
+        let challenge = |cache: &mut Cache<char, &str>| {
+            for _ in 0..3 {
+                cache.get(&'c');
             }
-        }
+            cache.insert('c', "cindy");
+            cache.contains_key(&'c')
+        };
 
-        let candidate_freq = freq.frequency(hash);
+        // The loaded keys are more popular than 'c'.
+        let mut cache = Cache::new(2);
+        cache.load_with_frequencies(snapshot());
+        assert_eq!(cache.entry_count(), 2);
+        assert!(!challenge(&mut cache));
+        assert!(cache.contains_key(&'a'));
+        assert!(cache.contains_key(&'b'));
 
-        match Self::admit(candidate_freq, deqs, freq) {
-            AdmissionResult::Admitted { victim_node } => {
-                // Remove the victim from the hash map and deque.
-                let mut vic_entry = cache
-                    .remove(unsafe { &victim_node.as_ref().element.key })
-                    .expect("Cannot remove a victim from the hash map");
-                deqs.unlink_ao(&mut vic_entry);
-                self.entry_count -= 1;
+        // A cold load of the same entries loses to 'c'.
+        let mut cache = Cache::new(2);
+        cache.enable_frequency_sketch_for_testing();
+        for (key, value, _) in snapshot() {
+            cache.insert(key, value);
+        }
+        assert!(challenge(&mut cache));
+    }
 
-                // Add the candidate to the deque.
-                let entry = cache.get_mut(&key).unwrap();
-                let key = Rc::clone(&key);
-                deqs.push_back_ao(
-                    CacheRegion::MainProbation,
-                    KeyHashDate::new(Rc::clone(&key), hash),
-                    entry,
-                );
+    #[test]
+    fn frequency_sketch_saturation() {
+        let mut cache: Cache<u32, ()> = Cache::new(64);
+        assert_eq!(cache.frequency_sketch_saturation(), 0.0);
 
-                self.entry_count += 1;
-                // Self::saturating_sub_from_total_weight(self, victims_weight);
-                // Self::saturating_add_to_total_weight(self, policy_weight as u64);
+        cache.enable_frequency_sketch_for_testing();
+        for key in 0..16 {
+            cache.get(&key);
+        }
+        let light = cache.frequency_sketch_saturation();
+        assert_eq!(light, 0.0);
 
-                if self.should_enable_frequency_sketch() {
-                    self.enable_frequency_sketch();
-                }
-            }
-            AdmissionResult::Rejected => {
-                // Remove the candidate from the cache.
-                cache.remove(&key);
+        // Each key saturates its counters. The sketch is halved periodically, so
+        // only the keys read since the last halving are saturated.
+        for key in 0..256 {
+            for _ in 0..20 {
+                cache.get(&key);
             }
         }
+        let heavy = cache.frequency_sketch_saturation();
+        assert!(heavy > 0.02, "saturation: {}", heavy);
+        assert!(heavy <= 1.0);
     }
 
-    /// Performs admission explained in the paper:
-    /// [Lightweight Robust Size Aware Cache Management][size-aware-cache-paper]
-    /// by Gil Einziger, Ohad Eytan, Roy Friedman, Ben Manes.
-    ///
-    /// [size-aware-cache-paper]: https://arxiv.org/abs/2105.08770
-    ///
-    /// In Micro Moka all policy weights are 1, so admission compares the candidate
-    /// with the single LRU victim in probation.
-    ///
-    #[inline]
-    fn admit(candidate_freq: u8, deqs: &Deques<K>, freq: &FrequencySketch) -> AdmissionResult<K> {
-        let Some(victim_node) = deqs.probation.peek_front_ptr() else {
-            return AdmissionResult::Rejected;
-        };
-        let victim_hash = unsafe { victim_node.as_ref() }.element.hash;
-        let victim_freq = freq.frequency(victim_hash);
-
-        // TODO: Implement some randomness to mitigate hash DoS attack.
-        // See Caffeine's implementation.
-
-        if candidate_freq > victim_freq {
-            AdmissionResult::Admitted { victim_node }
-        } else {
-            AdmissionResult::Rejected
+    #[test]
+    fn rename() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .track_insertion_order(true)
+            .build();
+        cache.enable_frequency_sketch_for_testing();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        for _ in 0..3 {
+            cache.get(&'a');
         }
-    }
+        cache.get(&'c');
+        let freq = cache.frequency_sketch.frequency(cache.hash(&'a'));
 
-    fn handle_update(&mut self, key: Rc<K>, policy_weight: u32, old_entry: ValueEntry<K, V>) {
-        let entry = self.cache.get_mut(&key).unwrap();
-        entry.replace_deq_nodes_with(old_entry);
-        entry.set_policy_weight(policy_weight);
+        assert!(cache.rename(&'a', 'z'));
+        assert_eq!(cache.check_invariants(), Ok(()));
+        assert_eq!(cache.entry_count(), 3);
+        assert!(!cache.contains_key_ref(&'a'));
+        assert_eq!(cache.peek(&'z'), Some(&"alice"));
+        assert_eq!(cache.frequency_sketch.frequency(cache.hash(&'z')), freq);
+        // The position in the LRU and the insertion orders are kept.
+        let lru_keys = cache
+            .least_recent(3)
+            .into_iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        assert_eq!(lru_keys, vec!['b', 'z', 'c']);
+        assert_eq!(cache.insertion_order(), vec![&'z', &'b', &'c']);
 
-        let deqs = &mut self.deques;
-        deqs.move_to_back_ao(entry);
+        // The source is missing or the destination exists.
+        assert!(!cache.rename(&'a', 'y'));
+        assert!(!cache.rename(&'b', 'c'));
+        cache.insert_absent('x');
+        assert!(!cache.rename(&'x', 'y'));
+        assert!(!cache.rename(&'b', 'x'));
+        assert_eq!(cache.peek(&'b'), Some(&"bob"));
 
-        // self.saturating_sub_from_total_weight(old_policy_weight as u64);
-        // self.saturating_add_to_total_weight(policy_weight as u64);
+        assert_eq!(cache.get(&'z'), Some(&"alice"));
+        cache.invalidate(&'z');
+        assert_eq!(cache.check_invariants(), Ok(()));
     }
 
-    #[inline]
-    fn evict_lru_entries(&mut self) {
-        const DEQ_NAME: &str = "probation";
-
-        let weights_to_evict = self.weights_to_evict();
-        let mut evicted_count = 0u64;
-        let mut evicted_policy_weight = 0u64;
+    #[test]
+    fn rename_panicking_hash() {
+        use std::{
+            cell::Cell,
+            hash::{Hash, Hasher},
+            panic::{catch_unwind, AssertUnwindSafe},
+        };
 
-        {
-            let deqs = &mut self.deques;
-            let (probation, cache) = (&mut deqs.probation, &mut self.cache);
+        // A key whose `Hash` panics after it has been hashed `hashes` times.
+        struct Key {
+            id: char,
+            hashes: Cell<u32>,
+        }
 
-            for _ in 0..EVICTION_BATCH_SIZE {
-                if evicted_policy_weight >= weights_to_evict {
-                    break;
+        impl Key {
+            fn new(id: char, hashes: u32) -> Self {
+                Self {
+                    id,
+                    hashes: Cell::new(hashes),
                 }
+            }
+        }
 
-                // clippy::map_clone will give us a false positive warning here.
-                // Version: clippy 0.1.77 (f2048098a1c 2024-02-09) in Rust 1.77.0-beta.2
-                #[allow(clippy::map_clone)]
-                let key = probation
-                    .peek_front()
-                    .map(|node| Rc::clone(&node.element.key));
+        impl PartialEq for Key {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
 
-                if key.is_none() {
-                    break;
-                }
-                let key = key.unwrap();
+        impl Eq for Key {}
 
-                if let Some(mut entry) = cache.remove(&key) {
-                    let weight = entry.policy_weight();
-                    Deques::unlink_ao_from_deque(DEQ_NAME, probation, &mut entry);
-                    evicted_count += 1;
-                    evicted_policy_weight = evicted_policy_weight.saturating_add(weight as u64);
-                } else {
-                    probation.pop_front();
-                }
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                let hashes = self.hashes.get();
+                assert!(hashes > 0, "intentional panic in hash for id={}", self.id);
+                self.hashes.set(hashes - 1);
+                self.id.hash(state);
             }
         }
 
-        self.entry_count -= evicted_count;
-        // self.saturating_sub_from_total_weight(evicted_policy_weight);
+        let mut cache = Cache::new(10);
+        cache.insert(Key::new('a', u32::MAX), "alice");
+
+        // The new key is hashed once to check that it is absent, and then
+        // panics when it is hashed for the entry.
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            cache.rename(&Key::new('a', u32::MAX), Key::new('z', 1))
+        }));
+        assert!(result.is_err());
+
+        // The entry is still there under the old key.
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.peek(&Key::new('a', u32::MAX)), Some(&"alice"));
+        assert_eq!(cache.check_invariants(), Ok(()));
     }
-}
 
-//
-// for testing
-//
-#[cfg(test)]
-impl<K, V, S> Cache<K, V, S>
-where
-    K: Hash + Eq,
-    S: BuildHasher + Clone,
-{
-}
+    /// Retags the node of the entry for the key with `CacheRegion::Other`, which
+    /// no access-order queue has.
+    fn retag_with_other_region(cache: &mut Cache<char, &str>, key: char) {
+        use crate::common::CacheRegion;
+        use tagptr::TagNonNull;
 
-// Access-Order Queue Node
-type AoqNode<K> = NonNull<DeqNode<KeyHashDate<K>>>;
+        let entry = cache.cache.get_mut(&key).unwrap();
+        let node = entry.access_order_q_node().unwrap().decompose_non_null();
+        entry.set_access_order_q_node(Some(TagNonNull::compose(node, CacheRegion::Other as usize)));
+    }
 
-enum AdmissionResult<K> {
-    Admitted { victim_node: AoqNode<K> },
-    Rejected,
-}
+    #[test]
+    fn panic_safe_mode() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .panic_safe_mode(true)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        retag_with_other_region(&mut cache, 'a');
 
-//
-// private free-standing functions
-//
+        // The reads and the removal skip the bad node.
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+        cache.invalidate(&'a');
+        assert!(!cache.contains_key(&'a'));
+        assert_eq!(cache.entry_count(), 1);
 
-// To see the debug prints, run test as `cargo test -- --nocapture`
-#[cfg(test)]
-mod tests {
-    use super::Cache;
+        // The node is left as an orphan.
+        assert_eq!(cache.prune_orphans(), 1);
+        assert_eq!(cache.check_invariants(), Ok(()));
+        assert_eq!(cache.get(&'b'), Some(&"bob"));
+    }
 
     #[test]
-    fn basic_single_thread() {
-        let mut cache = Cache::new(3);
-        cache.enable_frequency_sketch_for_testing();
-
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        assert_eq!(cache.get(&"a"), Some(&"alice"));
-        assert!(cache.contains_key(&"a"));
-        assert!(cache.contains_key(&"b"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        // counts: a -> 1, b -> 1
+    #[should_panic(expected = "internal error: entered unreachable code")]
+    fn region_mismatch_panics_by_default() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        retag_with_other_region(&mut cache, 'a');
+        cache.invalidate(&'a');
+    }
 
-        cache.insert("c", "cindy");
-        assert_eq!(cache.get(&"c"), Some(&"cindy"));
-        assert!(cache.contains_key(&"c"));
-        // counts: a -> 1, b -> 1, c -> 1
+    #[test]
+    fn size_info() {
+        let mut cache = Cache::new(5);
+        for i in 0..10 {
+            cache.insert(i, i);
+            let size = cache.size_info();
+            assert_eq!(size.entry_count(), cache.entry_count());
+            assert_eq!(size.weighted_size(), cache.weighted_size());
+        }
+        cache.invalidate(&0);
+        cache.invalidate(&1);
 
-        assert!(cache.contains_key(&"a"));
-        assert_eq!(cache.get(&"a"), Some(&"alice"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        assert!(cache.contains_key(&"b"));
-        // counts: a -> 2, b -> 2, c -> 1
+        let size = cache.size_info();
+        assert_eq!(size.entry_count(), 3);
+        assert_eq!(size.weighted_size(), 3);
+    }
 
-        // "d" should not be admitted because its frequency is too low.
-        cache.insert("d", "david"); //   count: d -> 0
-        assert_eq!(cache.get(&"d"), None); //   d -> 1
-        assert!(!cache.contains_key(&"d"));
+    #[test]
+    fn update_refreshes_recency() {
+        let lru_keys = |cache: &Cache<char, &str>| {
+            cache
+                .least_recent(3)
+                .into_iter()
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>()
+        };
 
-        cache.insert("d", "david");
-        assert!(!cache.contains_key(&"d"));
-        assert_eq!(cache.get(&"d"), None); //   d -> 2
+        for &refreshes in [true, false].iter() {
+            let mut cache = Cache::builder()
+                .max_capacity(10)
+                .update_refreshes_recency(refreshes)
+                .build();
+            cache.insert('a', "alice");
+            cache.insert('b', "bob");
+            cache.insert('c', "cindy");
 
-        // "d" should be admitted and "c" should be evicted
-        // because d's frequency is higher than c's.
-        cache.insert("d", "dennis");
-        assert_eq!(cache.get(&"a"), Some(&"alice"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        assert_eq!(cache.get(&"c"), None);
-        assert_eq!(cache.get(&"d"), Some(&"dennis"));
-        assert!(cache.contains_key(&"a"));
-        assert!(cache.contains_key(&"b"));
-        assert!(!cache.contains_key(&"c"));
-        assert!(cache.contains_key(&"d"));
+            cache.insert('a', "anna");
+            assert_eq!(cache.get_quiet(&'a'), Some(&"anna"));
+            if refreshes {
+                assert_eq!(lru_keys(&cache), vec!['b', 'c', 'a']);
+            } else {
+                assert_eq!(lru_keys(&cache), vec!['a', 'b', 'c']);
+            }
 
-        cache.invalidate(&"b");
-        assert_eq!(cache.get(&"b"), None);
-        assert!(!cache.contains_key(&"b"));
+            // A read still refreshes the recency.
+            cache.get(&'b');
+            assert_eq!(lru_keys(&cache).last(), Some(&'b'));
+
+            // A swap is an update, so it refreshes the recency only if updates do.
+            assert_eq!(cache.compare_and_swap(&'c', &"cindy", "carol"), Ok(()));
+            if refreshes {
+                assert_eq!(lru_keys(&cache), vec!['a', 'b', 'c']);
+            } else {
+                assert_eq!(lru_keys(&cache), vec!['a', 'c', 'b']);
+            }
+        }
     }
 
     #[test]
-    fn invalidate_all() {
-        let mut cache = Cache::new(100);
-        cache.enable_frequency_sketch_for_testing();
+    fn live_entry_count() {
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .lazy_eviction(true)
+            .build();
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.insert_absent(10);
+        assert_eq!(cache.live_entry_count(), 10);
 
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        cache.insert("c", "cindy");
-        assert_eq!(cache.get(&"a"), Some(&"alice"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        assert_eq!(cache.get(&"c"), Some(&"cindy"));
-        assert!(cache.contains_key(&"a"));
-        assert!(cache.contains_key(&"b"));
-        assert!(cache.contains_key(&"c"));
+        // The eviction of the entries over the capacity is deferred.
+        cache.set_max_capacity(4);
+        cache.get_quiet(&0);
+        assert!(cache.is_maintenance_due());
+        assert_eq!(cache.live_entry_count(), cache.cache.len());
+        assert_eq!(cache.live_entry_count() as u64, cache.entry_count());
 
-        cache.invalidate_all();
+        cache.run_pending_maintenance();
+        assert_eq!(cache.live_entry_count(), 4);
+        assert_eq!(cache.live_entry_count() as u64, cache.entry_count());
+    }
 
-        cache.insert("d", "david");
+    #[test]
+    fn hit_rate_for_trace() {
+        use crate::EvictionPolicy;
 
-        assert!(cache.get(&"a").is_none());
-        assert!(cache.get(&"b").is_none());
-        assert!(cache.get(&"c").is_none());
-        assert_eq!(cache.get(&"d"), Some(&"david"));
-        assert!(!cache.contains_key(&"a"));
-        assert!(!cache.contains_key(&"b"));
-        assert!(!cache.contains_key(&"c"));
-        assert!(cache.contains_key(&"d"));
+        assert_eq!(
+            Cache::<u32, ()>::hit_rate_for_trace(10, EvictionPolicy::TinyLfu, &[]),
+            1.0
+        );
+
+        // A loop over a hot working set, interrupted by scans of one-hit wonders.
+        let mut trace = Vec::new();
+        for round in 0..50 {
+            trace.extend(0..40);
+            trace.extend((0..100).map(|i| 10_000 + round * 100 + i));
+        }
+
+        let tiny_lfu = Cache::hit_rate_for_trace(50, EvictionPolicy::TinyLfu, &trace);
+        let clock = Cache::hit_rate_for_trace(50, EvictionPolicy::Clock, &trace);
+        // The admission policy keeps the scans from flushing the hot keys.
+        assert!(tiny_lfu > clock, "TinyLFU: {}, CLOCK: {}", tiny_lfu, clock);
+        assert!(tiny_lfu > 0.2, "TinyLFU: {}", tiny_lfu);
     }
 
     #[test]
-    fn invalidate_entries_if() {
-        use std::collections::HashSet;
+    fn protected_capacity() {
+        let mut cache = Cache::builder()
+            .max_capacity(100)
+            .promotion_threshold(1)
+            .protected_capacity(10)
+            .build();
 
-        let mut cache = Cache::new(100);
-        cache.enable_frequency_sketch_for_testing();
+        // Every entry is promoted by its first read. The protected region goes
+        // over the capacity only until the next write demotes its LRU entries.
+        for i in 0..600 {
+            cache.insert(i, i);
+            assert!(cache.deques.protected.len() <= 10);
+            cache.get(&i);
+            cache.get(&(i / 2));
+        }
+        cache.invalidate(&0);
+        assert_eq!(cache.deques.protected.len(), 10);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        cache.insert(0, "alice");
-        cache.insert(1, "bob");
-        cache.insert(2, "alex");
+    #[test]
+    fn region_of() {
+        use crate::EntryRegion;
 
-        assert_eq!(cache.get(&0), Some(&"alice"));
-        assert_eq!(cache.get(&1), Some(&"bob"));
-        assert_eq!(cache.get(&2), Some(&"alex"));
-        assert!(cache.contains_key(&0));
-        assert!(cache.contains_key(&1));
-        assert!(cache.contains_key(&2));
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(3)
+            .build();
+        cache.insert("hot", 1);
+        cache.insert("cold", 2);
+        assert_eq!(cache.region_of(&"hot"), Some(EntryRegion::MainProbation));
 
-        let names = ["alice", "alex"].iter().cloned().collect::<HashSet<_>>();
-        cache.invalidate_entries_if(move |_k, &v| names.contains(v));
+        for _ in 0..3 {
+            cache.get(&"hot");
+        }
+        cache.get(&"cold");
+        assert_eq!(cache.region_of(&"hot"), Some(EntryRegion::MainProtected));
+        assert_eq!(cache.region_of(&"cold"), Some(EntryRegion::MainProbation));
 
-        cache.insert(3, "alice");
+        cache.invalidate(&"hot");
+        assert_eq!(cache.region_of(&"hot"), None);
+    }
 
-        assert!(cache.get(&0).is_none());
-        assert!(cache.get(&2).is_none());
-        assert_eq!(cache.get(&1), Some(&"bob"));
-        // This should survive as it was inserted after calling invalidate_entries_if.
-        assert_eq!(cache.get(&3), Some(&"alice"));
+    #[test]
+    fn maintenance_interval() {
+        let mut cache = Cache::builder()
+            .max_capacity(1000)
+            .promotion_threshold(1)
+            .protected_capacity(1)
+            .maintenance_interval(4)
+            .build();
 
-        assert!(!cache.contains_key(&0));
-        assert!(cache.contains_key(&1));
-        assert!(!cache.contains_key(&2));
-        assert!(cache.contains_key(&3));
+        // Every read promotes an entry, so each maintenance run demotes the
+        // protected entries over the capacity.
+        let mut runs = 0;
+        for i in 0..100 {
+            let protected = cache.deques.protected.len();
+            cache.insert(i, i);
+            if cache.deques.protected.len() < protected {
+                runs += 1;
+            }
+            cache.get(&i);
+        }
+        assert_eq!(runs, 25);
+        assert_eq!(cache.check_invariants(), Ok(()));
 
-        assert_eq!(cache.cache.len(), 2);
+        // The write operations over the lowered capacity defer the eviction...
+        cache.set_max_capacity(50);
+        cache.invalidate(&1000);
+        assert_eq!(cache.entry_count(), 100);
+        assert!(cache.is_maintenance_due());
 
-        cache.invalidate_entries_if(|_k, &v| v == "alice");
-        cache.invalidate_entries_if(|_k, &v| v == "bob");
+        // ...but an insert evicts right away.
+        cache.insert(100, 100);
+        assert_eq!(cache.entry_count(), 50);
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        assert!(cache.get(&1).is_none());
-        assert!(cache.get(&3).is_none());
+    #[test]
+    fn recency_rank() {
+        let mut cache = Cache::new(10);
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.recency_rank(&0), Some(0));
 
-        assert!(!cache.contains_key(&1));
-        assert!(!cache.contains_key(&3));
+        // Reading the LRU entry moves it to the MRU end.
+        cache.get(&0);
+        cache.get(&1);
+        assert_eq!(cache.recency_rank(&2), Some(0));
+        assert_eq!(cache.recency_rank(&1), Some(4));
+        assert_eq!(cache.next_eviction_key(), Some(&2));
+        assert_eq!(cache.recency_rank(&5), None);
 
-        assert_eq!(cache.cache.len(), 0);
+        // A promoted entry is not in probation.
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(1)
+            .build();
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.get(&'a');
+        assert_eq!(cache.recency_rank(&'a'), None);
+        assert_eq!(cache.recency_rank(&'b'), Some(0));
     }
 
-    #[cfg_attr(target_pointer_width = "16", ignore)]
     #[test]
-    fn test_skt_capacity_will_not_overflow() {
-        // power of two
-        let pot = |exp| 2u64.pow(exp);
+    fn versioned_values() {
+        let mut cache = Cache::builder().max_capacity(10).hit_rate_window(8).build();
+        cache.insert_versioned('a', "alice", 1);
+        assert_eq!(cache.get_if_version_at_least(&'a', 2), None);
+        assert_eq!(cache.get_if_version_at_least(&'a', 1), Some(&"alice"));
+        assert_eq!(cache.get_if_version_at_least(&'b', 0), None);
+        assert_eq!(cache.recent_hit_rate(), 1.0 / 3.0);
 
-        let ensure_sketch_len = |max_capacity, len, name| {
-            let mut cache = Cache::<u8, u8>::new(max_capacity);
-            cache.enable_frequency_sketch_for_testing();
-            assert_eq!(cache.frequency_sketch.table_len(), len as usize, "{}", name);
-        };
+        // The stale value stays until it is replaced.
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
+        cache.insert_versioned('a', "anne", 2);
+        assert_eq!(cache.get_if_version_at_least(&'a', 2), Some(&"anne"));
 
-        if cfg!(target_pointer_width = "32") {
-            let pot24 = pot(24);
-            let pot16 = pot(16);
-            ensure_sketch_len(0, 128, "0");
-            ensure_sketch_len(128, 128, "128");
-            ensure_sketch_len(pot16, pot16, "pot16");
-            // due to ceiling to next_power_of_two
-            ensure_sketch_len(pot16 + 1, pot(17), "pot16 + 1");
-            // due to ceiling to next_power_of_two
-            ensure_sketch_len(pot24 - 1, pot24, "pot24 - 1");
-            ensure_sketch_len(pot24, pot24, "pot24");
-            ensure_sketch_len(pot(27), pot24, "pot(27)");
-            ensure_sketch_len(u32::MAX as u64, pot24, "u32::MAX");
-        } else {
-            // target_pointer_width: 64 or larger.
-            let pot30 = pot(30);
-            let pot16 = pot(16);
-            ensure_sketch_len(0, 128, "0");
-            ensure_sketch_len(128, 128, "128");
-            ensure_sketch_len(pot16, pot16, "pot16");
-            // due to ceiling to next_power_of_two
-            ensure_sketch_len(pot16 + 1, pot(17), "pot16 + 1");
+        // A value inserted without a version has version zero.
+        cache.insert('a', "amy");
+        assert_eq!(cache.get_if_version_at_least(&'a', 1), None);
+        assert_eq!(cache.get_if_version_at_least(&'a', 0), Some(&"amy"));
 
-            // The following tests will allocate large memory (~8GiB).
-            // Skip when running on Circle CI.
-            if !cfg!(circleci) {
-                // due to ceiling to next_power_of_two
-                ensure_sketch_len(pot30 - 1, pot30, "pot30- 1");
-                ensure_sketch_len(pot30, pot30, "pot30");
-                ensure_sketch_len(u64::MAX, pot30, "u64::MAX");
-            }
-        };
+        // The version is kept by the clone.
+        cache.insert_versioned('b', "bob", 5);
+        let mut clone = cache.clone_with_capacity(None);
+        assert_eq!(clone.get_if_version_at_least(&'b', 5), Some(&"bob"));
     }
 
     #[test]
-    fn remove_decrements_entry_count() {
-        let mut cache = Cache::new(3);
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        assert_eq!(cache.entry_count(), 2);
+    fn entries_since() {
+        use std::collections::hash_map::RandomState;
 
-        let removed = cache.remove(&"a");
-        assert_eq!(removed, Some("alice"));
-        assert_eq!(cache.entry_count(), 1);
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        cache.insert('c', "cindy");
+        let version = cache.version();
+        assert!(cache.entries_since(version).is_empty());
 
-        cache.remove(&"nonexistent");
-        assert_eq!(cache.entry_count(), 1);
+        // Reads do not modify the entries.
+        cache.get(&'a');
+        cache.insert('d', "david");
+        cache.insert('b', "bill");
+        assert_eq!(cache.compare_and_swap(&'c', &"cindy", "carol"), Ok(()));
+        cache.invalidate(&'d');
+        assert_eq!(
+            cache.entries_since(version),
+            vec![(&'b', &"bill"), (&'c', &"carol")]
+        );
+        assert_eq!(cache.entries_since(cache.version()), vec![]);
+        assert_eq!(cache.entries_since(0).len(), 3);
 
-        cache.remove(&"b");
-        assert_eq!(cache.entry_count(), 0);
+        // The modifications are kept by rehash.
+        let cache = cache.rehash(RandomState::default());
+        assert_eq!(
+            cache.entries_since(version),
+            vec![(&'b', &"bill"), (&'c', &"carol")]
+        );
     }
 
     #[test]
-    fn invalidate_decrements_entry_count() {
-        let mut cache = Cache::new(3);
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        assert_eq!(cache.entry_count(), 2);
+    fn lazy_values_are_not_cloned_as_tombstones() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice".to_string());
+        cache.insert_lazy('b', || "bob".to_string());
 
-        cache.invalidate(&"a");
-        assert_eq!(cache.entry_count(), 1);
+        // The pending value is not copied, rather than copied as a tombstone.
+        let mut clone = cache.clone_with_capacity(None);
+        assert_eq!(clone.entry_count(), 1);
+        assert_eq!(clone.get_state(&'b'), Lookup::Miss);
+        assert_eq!(clone.get(&'a'), Some(&"alice".to_string()));
+        assert_eq!(clone.check_invariants(), Ok(()));
 
-        cache.invalidate(&"nonexistent");
-        assert_eq!(cache.entry_count(), 1);
+        // The pending values do not take the room of the copied ones.
+        cache.insert_lazy('c', || "cindy".to_string());
+        let clone = cache.clone_with_capacity(Some(1));
+        assert!(clone.contains_key_ref(&'a'));
 
-        cache.invalidate(&"b");
-        assert_eq!(cache.entry_count(), 0);
+        assert_eq!(cache.get(&'b'), Some(&"bob".to_string()));
     }
 
     #[test]
-    fn insert_after_remove_on_full_cache() {
-        let mut cache = Cache::new(2);
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        assert_eq!(cache.entry_count(), 2);
+    fn invalidate_all_except_keeps_lazy_values() {
+        let mut cache = Cache::new(10);
+        cache.insert_lazy('a', || "alice");
+        cache.insert_lazy('b', || "bob");
+        cache.insert('c', "cindy");
 
-        cache.remove(&"a");
-        assert_eq!(cache.entry_count(), 1);
+        cache.invalidate_all_except(|_, _| true);
+        assert_eq!(cache.entry_count(), 3);
+        assert_eq!(cache.get(&'a'), Some(&"alice"));
 
-        cache.insert("c", "cindy");
-        assert_eq!(cache.entry_count(), 2);
-        assert_eq!(cache.get(&"c"), Some(&"cindy"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        assert_eq!(cache.get(&"a"), None);
+        cache.invalidate_all_except(|_, value| *value == "bob");
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.get(&'b'), Some(&"bob"));
+        assert_eq!(cache.check_invariants(), Ok(()));
     }
 
     #[test]
-    fn insert_after_invalidate_on_full_cache() {
-        let mut cache = Cache::new(2);
-        cache.insert("a", "alice");
-        cache.insert("b", "bob");
-        assert_eq!(cache.entry_count(), 2);
+    fn invalidate_entries_if_sees_lazy_values() {
+        let mut cache = Cache::new(10);
+        cache.insert_lazy('a', || "alice");
+        cache.insert_lazy('b', || "bob");
 
-        cache.invalidate(&"a");
+        cache.invalidate_entries_if(|_, value| *value == "alice");
+        assert!(!cache.contains_key(&'a'));
         assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.get_quiet(&'b'), Some(&"bob"));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        cache.insert("c", "cindy");
-        assert_eq!(cache.entry_count(), 2);
-        assert_eq!(cache.get(&"c"), Some(&"cindy"));
-        assert_eq!(cache.get(&"b"), Some(&"bob"));
-        assert_eq!(cache.get(&"a"), None);
+    #[test]
+    fn invalidate_stale_sees_lazy_values() {
+        let mut cache = Cache::new(10);
+        cache.insert_lazy('a', || 1);
+        cache.insert_lazy('b', || 2);
+
+        assert_eq!(cache.invalidate_stale(|_, generation| *generation < 2), 1);
+        assert!(!cache.contains_key(&'a'));
+        assert_eq!(cache.get_quiet(&'b'), Some(&2));
     }
 
     #[test]
-    fn invalidate_all_panic_safety() {
-        use std::panic::catch_unwind;
-        use std::panic::AssertUnwindSafe;
-        use std::sync::atomic::{AtomicU32, Ordering};
+    fn drain_filter_sees_lazy_values() {
+        let mut cache = Cache::new(10);
+        cache.insert_lazy('a', || 1);
+        cache.insert_lazy('b', || 2);
 
-        static DROP_COUNT: AtomicU32 = AtomicU32::new(0);
+        assert_eq!(cache.drain_filter(|_, v| *v == 1), vec![('a', 1)]);
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.get_quiet(&'b'), Some(&2));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
 
-        struct PanicOnDrop {
-            id: u32,
-            should_panic: bool,
-        }
+    #[test]
+    fn evict_by_score_sees_lazy_values() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', 30);
+        cache.insert_lazy('b', || 10);
+        cache.insert_lazy('c', || 20);
 
-        impl Drop for PanicOnDrop {
-            fn drop(&mut self) {
-                DROP_COUNT.fetch_add(1, Ordering::Relaxed);
-                if self.should_panic {
-                    panic!("intentional panic in drop for id={}", self.id);
-                }
-            }
+        assert_eq!(cache.evict_by_score(1, |_, v| *v), 1);
+        assert!(!cache.contains_key(&'b'));
+        assert_eq!(cache.get_quiet(&'c'), Some(&20));
+        assert_eq!(cache.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn rebuilding_keeps_entry_info() {
+        use std::collections::hash_map::RandomState;
+
+        let mut cache = Cache::builder()
+            .max_capacity(10)
+            .promotion_threshold(3)
+            .build();
+        cache.insert_versioned('a', "alice", 7);
+        for _ in 0..5 {
+            cache.get(&'a');
         }
+        cache.insert('b', "bob");
+        cache.get(&'b');
+        cache.get(&'b');
 
-        DROP_COUNT.store(0, Ordering::Relaxed);
-        let mut cache = Cache::new(10);
-        cache.insert(
-            1,
-            PanicOnDrop {
-                id: 1,
-                should_panic: false,
-            },
-        );
-        cache.insert(
-            2,
-            PanicOnDrop {
-                id: 2,
-                should_panic: true,
-            },
-        );
-        cache.insert(
-            3,
-            PanicOnDrop {
-                id: 3,
-                should_panic: false,
-            },
-        );
-        assert_eq!(cache.entry_count(), 3);
+        let check = |cache: &Cache<char, &str, _>| {
+            assert_eq!(cache.access_count(&'a'), Some(5));
+            assert_eq!(cache.access_count(&'b'), Some(2));
+            assert_eq!(cache.cache.get(&'b').unwrap().info.hits, 2);
+            assert_eq!(cache.cache.get(&'a').unwrap().value_version(), 7);
+        };
+        check(&cache.clone_with_capacity(None));
+        let cache = cache.rehash(RandomState::default());
+        check(&cache);
+        let (cache, _) = cache.partition(|_, _| true);
+        check(&cache);
+    }
 
-        let result = catch_unwind(AssertUnwindSafe(|| {
-            cache.invalidate_all();
-        }));
-        assert!(result.is_err());
+    #[test]
+    fn entries_since_after_clone_and_partition() {
+        let mut cache = Cache::new(10);
+        cache.insert('a', "alice");
+        cache.insert('b', "bob");
+        let version = cache.version();
+        cache.insert('c', "cindy");
 
-        assert_eq!(cache.entry_count(), 0);
-        assert_eq!(cache.cache.len(), 0);
+        let clone = cache.clone_with_capacity(None);
+        assert_eq!(clone.version(), cache.version());
+        assert_eq!(clone.entries_since(0).len(), 3);
+        assert_eq!(clone.entries_since(version), vec![(&'c', &"cindy")]);
 
-        cache.insert(
-            4,
-            PanicOnDrop {
-                id: 4,
-                should_panic: false,
-            },
-        );
-        assert_eq!(cache.entry_count(), 1);
-        assert!(cache.contains_key(&4));
+        let (left, right) = cache.partition(|key, _| *key != 'b');
+        assert_eq!(left.entries_since(version), vec![(&'c', &"cindy")]);
+        assert_eq!(right.entries_since(0), vec![(&'b', &"bob")]);
+        assert!(right.entries_since(version).is_empty());
     }
 
     #[test]
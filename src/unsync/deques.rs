@@ -10,6 +10,10 @@ pub(crate) struct Deques<K> {
     pub(crate) window: Deque<KeyHashDate<K>>, //    Not used yet.
     pub(crate) probation: Deque<KeyHashDate<K>>,
     pub(crate) protected: Deque<KeyHashDate<K>>, // Not used yet.
+    // The insertion order. Only populated when the tracking is enabled.
+    pub(crate) write_order: Deque<KeyHashDate<K>>,
+    // Skip the inconsistent nodes instead of panicking.
+    pub(crate) panic_safe: bool,
 }
 
 impl<K> Default for Deques<K> {
@@ -18,6 +22,8 @@ impl<K> Default for Deques<K> {
             window: Deque::new(CacheRegion::Window),
             probation: Deque::new(CacheRegion::MainProbation),
             protected: Deque::new(CacheRegion::MainProtected),
+            write_order: Deque::new(CacheRegion::Other),
+            panic_safe: false,
         }
     }
 }
@@ -27,6 +33,7 @@ impl<K> Deques<K> {
         self.window = Deque::new(CacheRegion::Window);
         self.probation = Deque::new(CacheRegion::MainProbation);
         self.protected = Deque::new(CacheRegion::MainProtected);
+        self.write_order = Deque::new(CacheRegion::Other);
     }
 
     pub(crate) fn push_back_ao<V>(
@@ -35,17 +42,69 @@ impl<K> Deques<K> {
         kh: KeyHashDate<K>,
         entry: &mut ValueEntry<K, V>,
     ) {
-        let node = Box::new(DeqNode::new(kh));
+        let node = DeqNode::new(kh);
         let node = match region {
             CacheRegion::Window => self.window.push_back(node),
             CacheRegion::MainProbation => self.probation.push_back(node),
             CacheRegion::MainProtected => self.protected.push_back(node),
+            CacheRegion::Other if self.panic_safe => {
+                return self.push_back_ao(CacheRegion::MainProbation, node.element, entry);
+            }
             CacheRegion::Other => unreachable!(),
         };
         let tagged_node = TagNonNull::compose(node, region as usize);
         entry.set_access_order_q_node(Some(tagged_node));
     }
 
+    pub(crate) fn push_back_wo<V>(&mut self, kh: KeyHashDate<K>, entry: &mut ValueEntry<K, V>) {
+        let node = self.write_order.push_back(DeqNode::new(kh));
+        entry.set_write_order_q_node(Some(node));
+    }
+
+    /// Returns the deque whose front node is the next eviction victim: the
+    /// probation deque, or the protected deque if the probation deque is empty.
+    pub(crate) fn victim_deque(&self) -> &Deque<KeyHashDate<K>> {
+        if self.probation.len() == 0 {
+            &self.protected
+        } else {
+            &self.probation
+        }
+    }
+
+    pub(crate) fn victim_deque_mut(&mut self) -> &mut Deque<KeyHashDate<K>> {
+        if self.probation.len() == 0 {
+            &mut self.protected
+        } else {
+            &mut self.probation
+        }
+    }
+
+    /// Moves the node of the entry from its current deque to the back of the
+    /// deque of the given region.
+    pub(crate) fn move_to_region_ao<V>(
+        &mut self,
+        region: CacheRegion,
+        entry: &mut ValueEntry<K, V>,
+    ) {
+        if let Some(tagged_node) = entry.take_access_order_q_node() {
+            let (node, tag) = tagged_node.decompose();
+            let kh = unsafe {
+                match tag.into() {
+                    CacheRegion::Window => self.window.unlink_and_take(node),
+                    CacheRegion::MainProbation => self.probation.unlink_and_take(node),
+                    CacheRegion::MainProtected => self.protected.unlink_and_take(node),
+                    _ if self.panic_safe => {
+                        // Leave the node as is.
+                        entry.set_access_order_q_node(Some(tagged_node));
+                        return;
+                    }
+                    _ => unreachable!(),
+                }
+            };
+            self.push_back_ao(region, kh, entry);
+        }
+    }
+
     pub(crate) fn move_to_back_ao<V>(&mut self, entry: &ValueEntry<K, V>) {
         if let Some(tagged_node) = entry.access_order_q_node() {
             let (node, tag) = tagged_node.decompose();
@@ -74,6 +133,7 @@ impl<K> Deques<K> {
                     }
                     unsafe { self.protected.move_to_back(node) };
                 }
+                _ if self.panic_safe => (),
                 _ => unreachable!(),
             }
         }
@@ -85,28 +145,41 @@ impl<K> Deques<K> {
         }
     }
 
-    pub(crate) fn unlink_ao_from_deque<V>(
-        deq_name: &str,
-        deq: &mut Deque<KeyHashDate<K>>,
-        entry: &mut ValueEntry<K, V>,
-    ) {
-        if let Some(node) = entry.take_access_order_q_node() {
-            unsafe { Self::unlink_node_ao_from_deque(deq_name, deq, node) };
+    pub(crate) fn unlink_wo<V>(&mut self, entry: &mut ValueEntry<K, V>) {
+        if let Some(node) = entry.take_write_order_q_node() {
+            #[cfg(debug_assertions)]
+            {
+                let p = unsafe { node.as_ref() };
+                debug_assert!(self.write_order.contains(p));
+            }
+            unsafe { self.write_order.unlink_and_drop(node) };
         }
     }
 
     pub(crate) fn unlink_node_ao(&mut self, tagged_node: TagNonNull<DeqNode<KeyHashDate<K>>, 2>) {
+        let panic_safe = self.panic_safe;
         unsafe {
             match tagged_node.decompose_tag().into() {
-                CacheRegion::Window => {
-                    Self::unlink_node_ao_from_deque("window", &mut self.window, tagged_node)
-                }
-                CacheRegion::MainProbation => {
-                    Self::unlink_node_ao_from_deque("probation", &mut self.probation, tagged_node)
-                }
-                CacheRegion::MainProtected => {
-                    Self::unlink_node_ao_from_deque("protected", &mut self.protected, tagged_node)
-                }
+                CacheRegion::Window => Self::unlink_node_ao_from_deque(
+                    "window",
+                    &mut self.window,
+                    tagged_node,
+                    panic_safe,
+                ),
+                CacheRegion::MainProbation => Self::unlink_node_ao_from_deque(
+                    "probation",
+                    &mut self.probation,
+                    tagged_node,
+                    panic_safe,
+                ),
+                CacheRegion::MainProtected => Self::unlink_node_ao_from_deque(
+                    "protected",
+                    &mut self.protected,
+                    tagged_node,
+                    panic_safe,
+                ),
+                // Leave the node in the deque it is in. It will be an orphan.
+                _ if panic_safe => (),
                 _ => unreachable!(),
             }
         }
@@ -116,9 +189,13 @@ impl<K> Deques<K> {
         deq_name: &str,
         deq: &mut Deque<KeyHashDate<K>>,
         tagged_node: TagNonNull<DeqNode<KeyHashDate<K>>, 2>,
+        panic_safe: bool,
     ) {
         let (node, tag) = tagged_node.decompose();
         if deq.region() != tag {
+            if panic_safe {
+                return;
+            }
             panic!(
                 "unlink_node - node is not a member of {} deque. {:?}",
                 deq_name,
@@ -129,6 +206,9 @@ impl<K> Deques<K> {
         #[cfg(debug_assertions)]
         {
             if !deq.contains(node.as_ref()) {
+                if panic_safe {
+                    return;
+                }
                 panic!(
                     "unlink_node - node is not a member of {} deque. {:?}",
                     deq_name,
@@ -0,0 +1,82 @@
+use std::{
+    borrow::Borrow,
+    collections::{hash_map::RandomState, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash},
+    rc::Rc,
+};
+
+/// A read-only view of a cache, returned by the [`freeze`][freeze-method]
+/// method of the `Cache`.
+///
+/// It only keeps the cached values in a `HashMap`, without the access-order
+/// queues and the historic popularity estimator. So the reads take `&self` and
+/// do no bookkeeping, which makes it cheaper to read than a `Cache` once the
+/// contents no longer change.
+///
+/// [freeze-method]: ./struct.Cache.html#method.freeze
+pub struct FrozenCache<K, V, S = RandomState> {
+    cache: HashMap<Rc<K>, V, S>,
+}
+
+impl<K, V, S> fmt::Debug for FrozenCache<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, S> FrozenCache<K, V, S> {
+    pub(crate) fn new(cache: HashMap<Rc<K>, V, S>) -> Self {
+        Self { cache }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns `true` if there is no entry.
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Creates an iterator visiting all key-value pairs in arbitrary order. The
+    /// iterator element type is `(&K, &V)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.cache.iter().map(|(k, v)| (&**k, v))
+    }
+}
+
+impl<K, V, S> FrozenCache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.get(key)
+    }
+
+    /// Returns `true` if there is a value for the key.
+    ///
+    /// The key may be any borrowed form of the cache's key type, but `Hash` and `Eq`
+    /// on the borrowed form _must_ match those for the key type.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Rc<K>: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.cache.contains_key(key)
+    }
+}
@@ -2,6 +2,26 @@ use super::{Cache, ValueEntry};
 
 use std::{hash::Hash, rc::Rc};
 
+/// An opaque position in the iteration order of a cache, to resume an
+/// incremental iteration by [`Cache::iter_from`][iter-from-method].
+///
+/// [iter-from-method]: ./struct.Cache.html#method.iter_from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    // The number of map entries (including tombstones) already visited.
+    position: usize,
+}
+
+impl Cursor {
+    pub(crate) fn new(position: usize) -> Self {
+        Self { position }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+}
+
 type HashMapIter<'i, K, V> = std::collections::hash_map::Iter<'i, Rc<K>, ValueEntry<K, V>>;
 
 pub struct Iter<'i, K, V> {
@@ -24,9 +44,30 @@ where
     type Item = (&'i K, &'i V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some((k, entry)) = self.iter.next() {
-            return Some((k, &entry.value));
-        }
-        None
+        // Skip the tombstones of negatively cached keys.
+        self.iter.by_ref().find_map(Self::map_entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Any number of the remaining map entries can be tombstones.
+        (0, self.iter.size_hint().1)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.iter.by_ref().filter_map(Self::map_entry).nth(n)
+    }
+
+    fn last(self) -> Option<Self::Item> {
+        self.iter.filter_map(Self::map_entry).last()
+    }
+
+    fn count(self) -> usize {
+        self.iter.filter(|(_, entry)| entry.value.is_some()).count()
+    }
+}
+
+impl<'i, K, V> Iter<'i, K, V> {
+    fn map_entry((k, entry): (&'i Rc<K>, &'i ValueEntry<K, V>)) -> Option<(&'i K, &'i V)> {
+        entry.value.as_ref().map(|v| (&**k, v))
     }
 }
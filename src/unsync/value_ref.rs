@@ -0,0 +1,32 @@
+use std::ops::Deref;
+
+/// A reference to a cached value, returned by the
+/// [`get_guard`][get-guard-method] method of the `Cache`.
+///
+/// The cache hit is recorded when the `ValueRef` is created, so dropping it has
+/// no side effects.
+///
+/// [get-guard-method]: ./struct.Cache.html#method.get_guard
+pub struct ValueRef<'a, V> {
+    value: &'a V,
+}
+
+impl<'a, V> ValueRef<'a, V> {
+    pub(crate) fn new(value: &'a V) -> Self {
+        Self { value }
+    }
+
+    /// Returns the underlying reference to the value, with the lifetime of the
+    /// borrow of the cache.
+    pub fn into_ref(self) -> &'a V {
+        self.value
+    }
+}
+
+impl<V> Deref for ValueRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}